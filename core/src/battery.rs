@@ -0,0 +1,180 @@
+//! Battery voltage and charge, from the ADC reading of a 2S LiPo
+
+/// The ADC reference, VDDA
+const ADC_REF_MV: f32 = 3300.0;
+const ADC_MAX: f32 = 4095.0;
+
+/// How far above a threshold the pack has to get to leave a warning, in mV
+const HYSTERESIS: u32 = 100;
+
+/// How long each blink of the warning LED takes, in ms
+const LOW_BLINK: u32 = 1000;
+const CRITICAL_BLINK: u32 = 200;
+
+/// Charge left against the voltage of one LiPo cell at rest
+const CELL_CHARGE: [(u32, f32); 21] = [
+    (3270, 0.0),
+    (3610, 5.0),
+    (3690, 10.0),
+    (3710, 15.0),
+    (3730, 20.0),
+    (3750, 25.0),
+    (3770, 30.0),
+    (3790, 35.0),
+    (3800, 40.0),
+    (3820, 45.0),
+    (3840, 50.0),
+    (3850, 55.0),
+    (3870, 60.0),
+    (3910, 65.0),
+    (3950, 70.0),
+    (3980, 75.0),
+    (4020, 80.0),
+    (4080, 85.0),
+    (4110, 90.0),
+    (4150, 95.0),
+    (4200, 100.0),
+];
+
+const CELLS: u32 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BatteryConfig {
+    /// Pack voltage over the voltage at the pin
+    pub divider: f32,
+    /// Pack voltage to start warning at, in mV
+    pub low: u32,
+    /// Pack voltage to warn urgently at, in mV
+    pub critical: u32,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> BatteryConfig {
+        BatteryConfig {
+            // 200k over 100k
+            divider: 3.0,
+            low: 7000,
+            critical: 6600,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BatteryLevel {
+    Ok,
+    Low,
+    Critical,
+}
+
+impl BatteryLevel {
+    /// The level for a pack voltage, coming from this one
+    pub fn next(self, pack_mv: u32, config: &BatteryConfig) -> BatteryLevel {
+        // Only leave a level once clear of its threshold
+        let critical = match self {
+            BatteryLevel::Critical => config.critical + HYSTERESIS,
+            _ => config.critical,
+        };
+        let low = match self {
+            BatteryLevel::Ok => config.low,
+            _ => config.low + HYSTERESIS,
+        };
+
+        if pack_mv < critical {
+            BatteryLevel::Critical
+        } else if pack_mv < low {
+            BatteryLevel::Low
+        } else {
+            BatteryLevel::Ok
+        }
+    }
+
+    /// Whether the warning LED is on at `now`
+    pub fn led(self, now: u32) -> bool {
+        match self {
+            BatteryLevel::Ok => false,
+            BatteryLevel::Low => now % LOW_BLINK < LOW_BLINK / 2,
+            BatteryLevel::Critical => now % CRITICAL_BLINK < CRITICAL_BLINK / 2,
+        }
+    }
+}
+
+/// The pack voltage in mV for a raw ADC reading
+pub fn pack_mv(raw: u16, config: &BatteryConfig) -> u32 {
+    (f32::from(raw) * ADC_REF_MV / ADC_MAX * config.divider) as u32
+}
+
+/// How much charge is left in a 2S pack, from 0 to 100 %
+pub fn state_of_charge(pack_mv: u32) -> f32 {
+    let cell_mv = pack_mv / CELLS;
+
+    let (first, _) = CELL_CHARGE[0];
+    if cell_mv <= first {
+        return 0.0;
+    }
+
+    for pair in CELL_CHARGE.windows(2) {
+        let (low_mv, low) = pair[0];
+        let (high_mv, high) = pair[1];
+
+        if cell_mv <= high_mv {
+            let t = (cell_mv - low_mv) as f32 / (high_mv - low_mv) as f32;
+            return low + (high - low) * t;
+        }
+    }
+
+    100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pack_mv, state_of_charge, BatteryConfig, BatteryLevel};
+
+    #[test]
+    fn raw_to_pack_voltage() {
+        let config = BatteryConfig::default();
+        assert_eq!(pack_mv(0, &config), 0);
+        assert_eq!(pack_mv(4095, &config), 9900);
+        assert_eq!(pack_mv(2048, &config), 4951);
+    }
+
+    #[test]
+    fn charge_of_a_2s_pack() {
+        assert_eq!(state_of_charge(8400), 100.0);
+        assert_eq!(state_of_charge(9000), 100.0);
+        assert_eq!(state_of_charge(7680), 50.0);
+        assert_eq!(state_of_charge(6000), 0.0);
+        assert_eq!(state_of_charge(7690), 52.5);
+    }
+
+    #[test]
+    fn levels_have_hysteresis() {
+        let config = BatteryConfig::default();
+        let level = BatteryLevel::Ok;
+
+        let level = level.next(7500, &config);
+        assert_eq!(level, BatteryLevel::Ok);
+
+        let level = level.next(6900, &config);
+        assert_eq!(level, BatteryLevel::Low);
+
+        // Just over the threshold isn't enough to go back
+        let level = level.next(7050, &config);
+        assert_eq!(level, BatteryLevel::Low);
+        assert_eq!(level.next(7100, &config), BatteryLevel::Ok);
+
+        let level = level.next(6500, &config);
+        assert_eq!(level, BatteryLevel::Critical);
+        assert_eq!(level.next(6650, &config), BatteryLevel::Critical);
+        assert_eq!(level.next(6800, &config), BatteryLevel::Low);
+        assert_eq!(level.next(7200, &config), BatteryLevel::Ok);
+    }
+
+    #[test]
+    fn warning_led_patterns() {
+        assert!(!BatteryLevel::Ok.led(0));
+        assert!(BatteryLevel::Low.led(100));
+        assert!(!BatteryLevel::Low.led(600));
+        assert!(BatteryLevel::Critical.led(50));
+        assert!(!BatteryLevel::Critical.led(150));
+    }
+}
@@ -0,0 +1,347 @@
+/*!
+ *  Motor characterisation
+ *
+ *  Both wheels are driven with the same voltage, first ramped up slowly
+ *  enough that the acceleration is next to nothing, then stepped so that it
+ *  is large. The wheel velocities are recorded against the voltage, and kS,
+ *  kV and kA fit to each wheel by least squares.
+ *
+ *  Run it with the wheels lifted, or with a clear run ahead of the mouse.
+ */
+use crate::motors::Feedforward;
+
+/// How fast the voltage goes up during the ramp, in V/ms
+const RAMP_RATE: f32 = 0.0005;
+
+/// How long the wheels get to stop before the step, in ms
+const REST_TIME: u32 = 1000;
+
+/// How long the step is held, in ms
+const STEP_TIME: u32 = 1000;
+
+pub const MAX_SAMPLES: usize = 800;
+
+/// The ramp ends early past this many samples, to leave room for the step
+const RAMP_SAMPLES: usize = MAX_SAMPLES * 3 / 4;
+
+/// Slower than this, in ticks/ms, a wheel is taken to be stopped
+const MIN_VELOCITY: f64 = 0.01;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Phase {
+    Ramp,
+    Rest,
+    Step,
+    Done,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Sample {
+    time: u32,
+    /// The voltage the velocities were reached with
+    volts: f32,
+    left: f32,
+    right: f32,
+}
+
+/// Sums for the normal equations of V = kS sign(v) + kV v + kA a
+#[derive(Default)]
+struct LeastSquares {
+    xtx: [[f64; 3]; 3],
+    xty: [f64; 3],
+    count: usize,
+}
+
+impl LeastSquares {
+    fn add(&mut self, x: [f64; 3], y: f64) {
+        for i in 0..3 {
+            for j in 0..3 {
+                self.xtx[i][j] += x[i] * x[j];
+            }
+            self.xty[i] += x[i] * y;
+        }
+        self.count += 1;
+    }
+
+    /// Solve by Cramer's rule, if there is enough to go on
+    fn solve(&self) -> Option<[f64; 3]> {
+        let det = determinant(&self.xtx);
+        if self.count < 3 || det == 0.0 {
+            return None;
+        }
+
+        let mut result = [0.0; 3];
+        for (column, value) in result.iter_mut().enumerate() {
+            let mut m = self.xtx;
+            for (row, y) in m.iter_mut().zip(self.xty.iter()) {
+                row[column] = *y;
+            }
+            *value = determinant(&m) / det;
+        }
+
+        Some(result)
+    }
+}
+
+fn determinant(m: &[[f64; 3]; 3]) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+pub struct Characterize {
+    volts: f32,
+    phase: Phase,
+    phase_start: Option<u32>,
+    /// The voltage given out by the last update
+    applied: f32,
+    samples: [Sample; MAX_SAMPLES],
+    len: usize,
+    /// Where the step samples start, once it has
+    step_start: Option<usize>,
+}
+
+impl Characterize {
+    /// Ramp the motors up to `volts`, then step them to it
+    pub fn new(volts: f32) -> Characterize {
+        Characterize {
+            volts,
+            phase: Phase::Ramp,
+            phase_start: None,
+            applied: 0.0,
+            samples: [Sample::default(); MAX_SAMPLES],
+            len: 0,
+            step_start: None,
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// How many samples have been recorded
+    pub fn samples(&self) -> usize {
+        self.len
+    }
+
+    /**
+     *  Record the wheel velocities, in ticks/ms, from the last voltage
+     *
+     *  Returns the voltage to drive both motors with until the next update.
+     */
+    pub fn update(
+        &mut self,
+        now: u32,
+        left_velocity: f64,
+        right_velocity: f64,
+    ) -> f64 {
+        let elapsed = now.wrapping_sub(*self.phase_start.get_or_insert(now));
+
+        let recording = self.phase == Phase::Ramp || self.phase == Phase::Step;
+        if recording && self.len < MAX_SAMPLES {
+            self.samples[self.len] = Sample {
+                time: now,
+                volts: self.applied,
+                left: left_velocity as f32,
+                right: right_velocity as f32,
+            };
+            self.len += 1;
+        }
+
+        let volts = match self.phase {
+            Phase::Ramp => {
+                let volts = RAMP_RATE * elapsed as f32;
+                if volts >= self.volts || self.len >= RAMP_SAMPLES {
+                    self.next_phase(Phase::Rest, now);
+                    0.0
+                } else {
+                    volts
+                }
+            }
+            Phase::Rest => {
+                if elapsed >= REST_TIME {
+                    self.step_start = Some(self.len);
+                    self.next_phase(Phase::Step, now);
+                    self.volts
+                } else {
+                    0.0
+                }
+            }
+            Phase::Step => {
+                if elapsed >= STEP_TIME {
+                    self.next_phase(Phase::Done, now);
+                    0.0
+                } else {
+                    self.volts
+                }
+            }
+            Phase::Done => 0.0,
+        };
+
+        self.applied = volts;
+        f64::from(volts)
+    }
+
+    fn next_phase(&mut self, phase: Phase, now: u32) {
+        self.phase = phase;
+        self.phase_start = Some(now);
+    }
+
+    pub fn fit_left(&self) -> Option<Feedforward> {
+        self.fit(|sample| sample.left)
+    }
+
+    pub fn fit_right(&self) -> Option<Feedforward> {
+        self.fit(|sample| sample.right)
+    }
+
+    fn fit<F: Fn(&Sample) -> f32>(&self, velocity: F) -> Option<Feedforward> {
+        let mut squares = LeastSquares::default();
+
+        // The acceleration can't be taken across the rest
+        let step_start = self.step_start.unwrap_or(self.len);
+        let ramp = &self.samples[..step_start];
+        let step = &self.samples[step_start..self.len];
+
+        for samples in [ramp, step].iter() {
+            for window in samples.windows(3) {
+                let (before, sample, after) = (window[0], window[1], window[2]);
+
+                let v = f64::from(velocity(&sample));
+                if (-MIN_VELOCITY..MIN_VELOCITY).contains(&v) {
+                    continue;
+                }
+
+                let sign = if v > 0.0 { 1.0 } else { -1.0 };
+                let a = f64::from(velocity(&after) - velocity(&before))
+                    / f64::from(after.time.wrapping_sub(before.time));
+
+                squares.add([sign, v, a], f64::from(sample.volts));
+            }
+        }
+
+        squares
+            .solve()
+            .map(|[ks, kv, ka]| Feedforward { ks, kv, ka })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Characterize, Phase, MAX_SAMPLES};
+    use crate::motors::Feedforward;
+
+    /// A wheel that follows the feedforward model exactly
+    struct Wheel {
+        model: Feedforward,
+        velocity: f64,
+    }
+
+    impl Wheel {
+        fn update(&mut self, volts: f64, dt: f64) {
+            // Small steps, or the fast part of the step goes unstable
+            for _ in 0..100 {
+                let dt = dt / 100.0;
+                let drive = volts - self.model.kv * self.velocity;
+                let accel = if self.velocity > 0.0 || drive > self.model.ks {
+                    (drive - self.model.ks) / self.model.ka
+                } else {
+                    0.0
+                };
+                self.velocity = (self.velocity + accel * dt).max(0.0);
+            }
+        }
+    }
+
+    fn close(fit: Feedforward, model: Feedforward) {
+        assert!((fit.ks - model.ks).abs() < model.ks * 0.1, "{:?}", fit);
+        assert!((fit.kv - model.kv).abs() < model.kv * 0.05, "{:?}", fit);
+        assert!((fit.ka - model.ka).abs() < model.ka * 0.15, "{:?}", fit);
+    }
+
+    #[test]
+    fn goes_through_the_phases() {
+        let mut test = Characterize::new(1.0);
+
+        assert_eq!(test.update(0, 0.0, 0.0), 0.0);
+        assert_eq!(test.update(1000, 0.0, 0.0), 0.5);
+        assert_eq!(test.update(2000, 0.0, 0.0), 0.0);
+        assert_eq!(test.phase(), Phase::Rest);
+        assert_eq!(test.update(2500, 0.0, 0.0), 0.0);
+        assert_eq!(test.update(3000, 0.0, 0.0), 1.0);
+        assert_eq!(test.phase(), Phase::Step);
+        assert_eq!(test.update(3500, 0.0, 0.0), 1.0);
+        assert_eq!(test.update(4000, 0.0, 0.0), 0.0);
+        assert_eq!(test.phase(), Phase::Done);
+        assert_eq!(test.samples(), 5);
+    }
+
+    #[test]
+    fn times_wrap_around() {
+        let mut test = Characterize::new(1.0);
+        let start = u32::MAX - 500;
+
+        assert_eq!(test.update(start, 0.0, 0.0), 0.0);
+        assert_eq!(test.update(start.wrapping_add(1000), 0.0, 0.0), 0.5);
+        assert_eq!(test.phase(), Phase::Ramp);
+    }
+
+    #[test]
+    fn fits_the_model() {
+        let left = Feedforward {
+            ks: 0.3,
+            kv: 2.0,
+            ka: 200.0,
+        };
+        let right = Feedforward {
+            ks: 0.4,
+            kv: 2.5,
+            ka: 150.0,
+        };
+        let mut left_wheel = Wheel {
+            model: left,
+            velocity: 0.0,
+        };
+        let mut right_wheel = Wheel {
+            model: right,
+            velocity: 0.0,
+        };
+
+        let mut test = Characterize::new(3.0);
+        let mut volts = 0.0;
+        let mut time = 0;
+        while test.phase() != Phase::Done {
+            left_wheel.update(volts, 11.0);
+            right_wheel.update(volts, 11.0);
+            time += 11;
+            volts =
+                test.update(time, left_wheel.velocity, right_wheel.velocity);
+        }
+
+        close(test.fit_left().unwrap(), left);
+        close(test.fit_right().unwrap(), right);
+    }
+
+    #[test]
+    fn long_ramps_leave_room_for_the_step() {
+        let mut test = Characterize::new(100.0);
+        let mut time = 0;
+        while test.phase() != Phase::Done {
+            test.update(time, 1.0, 1.0);
+            time += 10;
+        }
+
+        assert!(test.samples() <= MAX_SAMPLES);
+        assert!(test.step_start.unwrap() < MAX_SAMPLES - 50);
+    }
+
+    #[test]
+    fn nothing_moving_is_no_fit() {
+        let mut test = Characterize::new(1.0);
+        for time in 0..1000 {
+            test.update(time * 10, 0.0, 0.0);
+        }
+
+        assert_eq!(test.fit_left(), None);
+    }
+}
@@ -0,0 +1,501 @@
+use crate::battery::BatteryConfig;
+use crate::crc::crc32;
+use crate::distance::SensorCalibration;
+use crate::filter::{FilterConfig, FilterKind};
+use crate::motors::velocity::VelocityConfig;
+use crate::motors::Feedforward;
+use crate::mouse::MouseConfig;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BotConfig {
+    pub left_p: f64,
+    pub left_i: f64,
+    pub left_d: f64,
+
+    pub right_p: f64,
+    pub right_i: f64,
+    pub right_d: f64,
+
+    pub left_feedforward: Feedforward,
+    pub right_feedforward: Feedforward,
+
+    pub spin_p: f64,
+    pub spin_i: f64,
+    pub spin_d: f64,
+    pub spin_err: f64,
+    pub spin_settle: u32,
+    pub spin_max_velocity: f64,
+    pub spin_max_accel: f64,
+
+    pub linear_p: f64,
+    pub linear_i: f64,
+    pub linear_d: f64,
+    pub linear_spin_p: f64,
+    pub linear_spin_i: f64,
+    pub linear_spin_d: f64,
+    pub linear_spin_pos_p: f64,
+    pub linear_err: f64,
+    pub linear_front_err: f64,
+    pub linear_settle: u32,
+    pub linear_max_velocity: f64,
+    pub linear_max_accel: f64,
+    pub linear_end_velocity: f64,
+
+    pub path_p: f64,
+    pub path_i: f64,
+    pub path_d: f64,
+    pub path_velocity: f64,
+
+    pub ticks_per_spin: f64,
+    pub ticks_per_cell: f64,
+
+    pub cell_width: f64,
+    pub cell_offset: f64,
+    pub wall_threshold: f64,
+    pub wall_hysteresis: f64,
+    pub wall_window: f64,
+    pub front_wall_distance: f64,
+    pub front_wall_lookahead: f64,
+
+    pub front_calibration: SensorCalibration,
+    pub left_calibration: SensorCalibration,
+    pub right_calibration: SensorCalibration,
+
+    pub distance_filter: FilterConfig,
+
+    pub velocity_filter: VelocityConfig,
+
+    pub battery: BatteryConfig,
+
+    pub mouse: MouseConfig,
+}
+
+crate::params! {
+    pub static BOT_CONFIG_PARAMS: BotConfig;
+
+    left_p: f64, "V/(ticks/ms)", 0.0, 1e6,
+        "left wheel velocity P gain";
+    left_i: f64, "V/(ticks/ms)", 0.0, 1e6,
+        "left wheel velocity I gain";
+    left_d: f64, "V/(ticks/ms)", 0.0, 1e6,
+        "left wheel velocity D gain";
+
+    right_p: f64, "V/(ticks/ms)", 0.0, 1e6,
+        "right wheel velocity P gain";
+    right_i: f64, "V/(ticks/ms)", 0.0, 1e6,
+        "right wheel velocity I gain";
+    right_d: f64, "V/(ticks/ms)", 0.0, 1e6,
+        "right wheel velocity D gain";
+
+    left_feedforward.ks: f64, "V", 0.0, 10.0,
+        "left motor voltage to overcome static friction";
+    left_feedforward.kv: f64, "V/(ticks/ms)", 0.0, 100.0,
+        "left motor voltage per wheel velocity";
+    left_feedforward.ka: f64, "V/(ticks/ms^2)", 0.0, 10000.0,
+        "left motor voltage per wheel acceleration";
+    right_feedforward.ks: f64, "V", 0.0, 10.0,
+        "right motor voltage to overcome static friction";
+    right_feedforward.kv: f64, "V/(ticks/ms)", 0.0, 100.0,
+        "right motor voltage per wheel velocity";
+    right_feedforward.ka: f64, "V/(ticks/ms^2)", 0.0, 10000.0,
+        "right motor voltage per wheel acceleration";
+
+    spin_p: f64, "", 0.0, 100.0, "spin position P gain";
+    spin_i: f64, "", 0.0, 100.0, "spin position I gain";
+    spin_d: f64, "", 0.0, 100.0, "spin position D gain";
+    spin_err: f64, "ticks", 0.0, 1000.0, "how close a spin has to get";
+    spin_settle: u32, "ms", 0.0, 10000.0, "how long a spin has to stay close";
+    spin_max_velocity: f64, "ticks/ms", 0.0, 100.0, "fastest spin";
+    spin_max_accel: f64, "ticks/ms^2", 0.0, 1.0, "spin acceleration";
+
+    linear_p: f64, "", 0.0, 100.0, "linear position P gain";
+    linear_i: f64, "", 0.0, 100.0, "linear position I gain";
+    linear_d: f64, "", 0.0, 100.0, "linear position D gain";
+    linear_spin_p: f64, "", 0.0, 100.0, "wall centering P gain";
+    linear_spin_i: f64, "", 0.0, 100.0, "wall centering I gain";
+    linear_spin_d: f64, "", 0.0, 100.0, "wall centering D gain";
+    linear_spin_pos_p: f64, "", 0.0, 100.0, "straightness P gain";
+    linear_err: f64, "ticks", 0.0, 1000.0, "how close a linear has to get";
+    linear_front_err: f64, "mm", 0.0, 765.0,
+        "how close to the front wall distance a linear has to get";
+    linear_settle: u32, "ms", 0.0, 10000.0,
+        "how long a linear has to stay close";
+    linear_max_velocity: f64, "ticks/ms", 0.0, 100.0, "fastest linear";
+    linear_max_accel: f64, "ticks/ms^2", 0.0, 1.0, "linear acceleration";
+    linear_end_velocity: f64, "ticks/ms", 0.0, 100.0,
+        "velocity at the end of a linear";
+
+    path_p: f64, "", 0.0, 100.0, "path follower P gain";
+    path_i: f64, "", 0.0, 100.0, "path follower I gain";
+    path_d: f64, "", 0.0, 100.0, "path follower D gain";
+    path_velocity: f64, "mm/s", 0.0, 5000.0, "path follower speed";
+
+    ticks_per_spin: f64, "ticks", 1.0, 100000.0, "ticks in a full spin";
+    ticks_per_cell: f64, "ticks", 1.0, 100000.0, "ticks across a cell";
+
+    cell_width: f64, "mm", 1.0, 1000.0, "width of a cell";
+    cell_offset: f64, "mm", 0.0, 765.0,
+        "side distance with the mouse centered between walls";
+    wall_threshold: f64, "mm", 0.0, 765.0,
+        "distance below which there is a wall";
+    wall_hysteresis: f64, "mm", 0.0, 100.0,
+        "how far past the wall threshold a reading has to be to change";
+    wall_window: f64, "cells", 0.0, 1.0,
+        "how much of the end of a cell walls are read over";
+    front_wall_distance: f64, "mm", 0.0, 765.0,
+        "front distance to stop at in front of a wall";
+    front_wall_lookahead: f64, "mm", 0.0, 765.0,
+        "front distance under which a linear move stops at the wall";
+
+    front_calibration.offset: i32, "mm", -255.0, 255.0,
+        "front sensor offset on top of the factory one";
+    front_calibration.crosstalk: f32, "Mcps", 0.0, 511.0,
+        "front sensor cover crosstalk";
+    left_calibration.offset: i32, "mm", -255.0, 255.0,
+        "left sensor offset on top of the factory one";
+    left_calibration.crosstalk: f32, "Mcps", 0.0, 511.0,
+        "left sensor cover crosstalk";
+    right_calibration.offset: i32, "mm", -255.0, 255.0,
+        "right sensor offset on top of the factory one";
+    right_calibration.crosstalk: f32, "Mcps", 0.0, 511.0,
+        "right sensor cover crosstalk";
+
+    distance_filter.kind: FilterKind, "", 0.0, 3.0,
+        "distance filter, 0 none, 1 median, 2 exponential or 3 kalman";
+    distance_filter.median_window: u32, "samples", 1.0, 9.0,
+        "how many samples the median filter looks at";
+    distance_filter.alpha: f32, "", 0.0, 1.0,
+        "weight of a new sample in the exponential filter";
+    distance_filter.process_noise: f32, "mm^2/ms", 0.0, 1000.0,
+        "how fast the kalman filter expects the distance to drift";
+    distance_filter.measurement_noise: f32, "mm^2", 0.0, 10000.0,
+        "variance of a single distance sample";
+    distance_filter.stale_after: u32, "ms", 0.0, 10000.0,
+        "how long without a valid sample before a distance is stale";
+
+    velocity_filter.alpha: f32, "", 0.0, 1.0,
+        "how much of an encoder residual corrects the position";
+    velocity_filter.beta: f32, "", 0.0, 2.0,
+        "how much of an encoder residual corrects the velocity";
+
+    battery.divider: f32, "", 1.0, 100.0,
+        "battery voltage over the voltage at the ADC pin";
+    battery.low: u32, "mV", 0.0, 20000.0, "battery voltage to warn at";
+    battery.critical: u32, "mV", 0.0, 20000.0,
+        "battery voltage to warn urgently at";
+
+    mouse.wheel_diameter: f32, "mm", 1.0, 1000.0, "wheel diameter";
+    mouse.gearbox_ratio: f32, "", 1.0, 1000.0, "motor turns per wheel turn";
+    mouse.ticks_per_rev: f32, "ticks", 1.0, 10000.0,
+        "encoder ticks per motor turn";
+    mouse.wheelbase: f32, "mm", 1.0, 1000.0, "distance between the wheels";
+    mouse.width: f32, "mm", 1.0, 1000.0, "width of the mouse";
+    mouse.length: f32, "mm", 1.0, 1000.0, "length of the mouse";
+    mouse.front_offset: f32, "mm", 0.0, 1000.0,
+        "center of the wheels to the front sensor";
+}
+
+/// Identifies a saved config record, "MMCF"
+const CONFIG_MAGIC: u32 = 0x4643_4d4d;
+
+/// Bump this whenever the parameters or their order change
+pub const CONFIG_VERSION: u16 = 10;
+
+/// Magic, version and payload length
+const HEADER_SIZE: usize = 8;
+const CRC_SIZE: usize = 4;
+
+/// The largest a saved record can be
+pub const CONFIG_RECORD_SIZE: usize = 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    BadMagic,
+    BadVersion(u16),
+    BadLength,
+    BadCrc,
+    BadValue,
+    BufferTooSmall,
+}
+
+/// Writes little endian values into a buffer
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), ConfigError> {
+        let end = self.len + bytes.len();
+        if end > self.buf.len() {
+            return Err(ConfigError::BufferTooSmall);
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn u16(&mut self, v: u16) -> Result<(), ConfigError> {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    fn u32(&mut self, v: u32) -> Result<(), ConfigError> {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    fn f64(&mut self, v: f64) -> Result<(), ConfigError> {
+        self.bytes(&v.to_bits().to_le_bytes())
+    }
+}
+
+/// Reads little endian values out of a buffer
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes<'b>(&'b mut self, len: usize) -> Result<&'a [u8], ConfigError> {
+        if len > self.buf.len() {
+            return Err(ConfigError::BadLength);
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, ConfigError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, ConfigError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn f64(&mut self) -> Result<f64, ConfigError> {
+        let b = self.bytes(8)?;
+        let mut bits = [0; 8];
+        bits.copy_from_slice(b);
+        Ok(f64::from_bits(u64::from_le_bytes(bits)))
+    }
+}
+
+impl Default for BotConfig {
+    fn default() -> BotConfig {
+        BotConfig {
+            // 3000 PWM counts at 7.4 V
+            left_p: 2.22,
+            left_i: 0.0,
+            left_d: 0.0,
+            // 3000 PWM counts at 7.4 V
+            right_p: 2.22,
+            right_i: 0.0,
+            right_d: 0.0,
+            left_feedforward: Feedforward::default(),
+            right_feedforward: Feedforward::default(),
+            spin_p: 0.01,
+            spin_i: 0.0,
+            spin_d: 0.0,
+            spin_err: 10.0,
+            spin_settle: 200,
+            spin_max_velocity: 1.0,
+            spin_max_accel: 0.005,
+            linear_p: 0.01,
+            linear_i: 0.0,
+            linear_d: 0.0,
+            linear_spin_p: 0.01,
+            linear_spin_i: 0.0,
+            linear_spin_d: 0.0,
+            linear_spin_pos_p: 1.0,
+            linear_err: 20.0,
+            linear_front_err: 2.0,
+            linear_settle: 200,
+            linear_max_velocity: 1.0,
+            linear_max_accel: 0.005,
+            linear_end_velocity: 0.0,
+            path_p: 0.005,
+            path_i: 0.0,
+            path_d: 0.0,
+            path_velocity: 200.0,
+            ticks_per_spin: 2064.03,
+            ticks_per_cell: 1620.0,
+            cell_width: 180.0,
+            cell_offset: 53.0,
+            wall_threshold: 120.0,
+            wall_hysteresis: 10.0,
+            wall_window: 0.3,
+            front_wall_distance: 35.0,
+            front_wall_lookahead: 180.0,
+            front_calibration: SensorCalibration::default(),
+            left_calibration: SensorCalibration::default(),
+            right_calibration: SensorCalibration::default(),
+            distance_filter: FilterConfig::default(),
+            velocity_filter: VelocityConfig::default(),
+            battery: BatteryConfig::default(),
+            mouse: MouseConfig {
+                wheel_diameter: 32.0,
+                gearbox_ratio: 75.0,
+                ticks_per_rev: 12.0,
+                wheelbase: 73.0,
+                width: 64.0,
+                length: 90.0,
+                front_offset: 40.0,
+            },
+        }
+    }
+}
+
+impl BotConfig {
+    /**
+     *  Write the config as a record that can be saved in flash
+     *
+     *  The record is a header with a magic number, the schema version and the
+     *  payload length, then every parameter as an f64 in registry order, then
+     *  a CRC-32 of everything before it. Returns the length of the record.
+     */
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, ConfigError> {
+        let mut writer = Writer { buf, len: 0 };
+
+        writer.u32(CONFIG_MAGIC)?;
+        writer.u16(CONFIG_VERSION)?;
+        // The length gets filled in once the payload is written
+        writer.u16(0)?;
+
+        for param in BOT_CONFIG_PARAMS {
+            writer.f64(param.get(self))?;
+        }
+
+        let payload_len = (writer.len - HEADER_SIZE) as u16;
+        writer.buf[6..8].copy_from_slice(&payload_len.to_le_bytes());
+
+        let crc = crc32(&writer.buf[..writer.len]);
+        writer.u32(crc)?;
+
+        Ok(writer.len)
+    }
+
+    /// Read back a record written by `encode`, checking that it is intact
+    pub fn decode(buf: &[u8]) -> Result<BotConfig, ConfigError> {
+        let mut reader = Reader { buf };
+
+        if reader.u32()? != CONFIG_MAGIC {
+            return Err(ConfigError::BadMagic);
+        }
+
+        let version = reader.u16()?;
+        if version != CONFIG_VERSION {
+            return Err(ConfigError::BadVersion(version));
+        }
+
+        let payload_len = reader.u16()? as usize;
+        let record_len = HEADER_SIZE + payload_len;
+        if record_len + CRC_SIZE > buf.len() {
+            return Err(ConfigError::BadLength);
+        }
+
+        let mut crc_reader = Reader {
+            buf: &buf[record_len..],
+        };
+        if crc_reader.u32()? != crc32(&buf[..record_len]) {
+            return Err(ConfigError::BadCrc);
+        }
+
+        let mut reader = Reader {
+            buf: &buf[HEADER_SIZE..record_len],
+        };
+
+        let mut config = BotConfig::default();
+        for param in BOT_CONFIG_PARAMS {
+            let v = reader.f64()?;
+            param
+                .set(&mut config, v)
+                .map_err(|_| ConfigError::BadValue)?;
+        }
+
+        if !reader.buf.is_empty() {
+            return Err(ConfigError::BadLength);
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BotConfig, ConfigError, BOT_CONFIG_PARAMS, CONFIG_RECORD_SIZE,
+    };
+
+    fn encoded(config: &BotConfig) -> ([u8; CONFIG_RECORD_SIZE], usize) {
+        let mut buf = [0; CONFIG_RECORD_SIZE];
+        let len = config.encode(&mut buf).unwrap();
+        (buf, len)
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let mut config = BotConfig {
+            spin_p: 0.5,
+            linear_settle: 123,
+            ..BotConfig::default()
+        };
+        config.mouse.wheelbase = 70.0;
+
+        let (buf, len) = encoded(&config);
+        assert_eq!(BotConfig::decode(&buf[..len]), Ok(config));
+    }
+
+    #[test]
+    fn config_bad_crc() {
+        let (mut buf, len) = encoded(&BotConfig::default());
+        buf[20] ^= 0x01;
+        assert_eq!(BotConfig::decode(&buf[..len]), Err(ConfigError::BadCrc));
+    }
+
+    #[test]
+    fn config_bad_version() {
+        let (mut buf, len) = encoded(&BotConfig::default());
+        buf[4] = 0xff;
+        assert_eq!(
+            BotConfig::decode(&buf[..len]),
+            Err(ConfigError::BadVersion(0xff))
+        );
+    }
+
+    #[test]
+    fn config_erased_flash() {
+        let buf = [0xff; CONFIG_RECORD_SIZE];
+        assert_eq!(BotConfig::decode(&buf), Err(ConfigError::BadMagic));
+    }
+
+    #[test]
+    fn config_truncated() {
+        let (buf, len) = encoded(&BotConfig::default());
+        assert_eq!(
+            BotConfig::decode(&buf[..len - 1]),
+            Err(ConfigError::BadLength)
+        );
+    }
+
+    #[test]
+    fn config_params_unique() {
+        for (i, a) in BOT_CONFIG_PARAMS.iter().enumerate() {
+            for b in &BOT_CONFIG_PARAMS[i + 1..] {
+                assert_ne!(a.name, b.name);
+            }
+        }
+    }
+
+    #[test]
+    fn config_defaults_in_range() {
+        let config = BotConfig::default();
+        for param in BOT_CONFIG_PARAMS {
+            assert!(
+                param.validate(param.get(&config)).is_ok(),
+                "{}",
+                param.name
+            );
+        }
+    }
+}
@@ -0,0 +1,64 @@
+/**
+ *  CRC-32 (IEEE 802.3), the same one zlib and ethernet use
+ *
+ *  This is the bitwise version, it is slow but needs no table and only runs
+ *  when saving or loading the config.
+ */
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff;
+
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xedb8_8320;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+
+    !crc
+}
+
+/**
+ *  CRC-16/CCITT-FALSE, used to check telemetry frames
+ *
+ *  Bitwise like `crc32`, frames are short enough that it doesn't matter.
+ */
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffff;
+
+    for byte in data {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc16, crc32};
+
+    #[test]
+    fn crc32_check() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn crc32_empty() {
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn crc16_check() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+}
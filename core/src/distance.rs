@@ -0,0 +1,70 @@
+use crate::vl6180x::RangeStatus;
+
+/// One range reading and what the sensor thought of it
+#[derive(Copy, Clone, Debug)]
+pub struct DistanceSample {
+    /// The range in mm, the sensor's maximum when there is nothing in range
+    pub range: u16,
+    pub status: RangeStatus,
+    /// The time the sample was read
+    pub timestamp: u32,
+}
+
+impl DistanceSample {
+    /// Whether the range can be trusted
+    pub fn is_valid(&self) -> bool {
+        self.status == RangeStatus::NoError
+    }
+
+    /// Whether the sensor saw nothing in range, rather than failing
+    pub fn is_no_target(&self) -> bool {
+        self.status.is_no_target()
+    }
+}
+
+/// One ambient light reading
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AmbientSample {
+    pub lux: f32,
+    /// The time the sample was read
+    pub timestamp: u32,
+}
+
+/**
+ *  Corrections for one sensor sitting behind the cover
+ *
+ *  The default changes nothing, so an uncalibrated sensor keeps its factory
+ *  offset.
+ */
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SensorCalibration {
+    /// Added to the factory part to part offset, in mm
+    pub offset: i32,
+    /// Signal reflected off the cover instead of the target, in Mcps
+    pub crosstalk: f32,
+}
+
+/**
+ *  A distance sensor that is sampled continuously in the background
+ *
+ *  `update` is called from the main loop and should be cheap when no new
+ *  sample is ready. `sample` only returns the last sample taken.
+ */
+pub trait DistanceSensor {
+    type Error;
+
+    /**
+     *  Check for and read a new sample, if one is ready
+     *
+     *  `WouldBlock` means a sample is still being read in the background,
+     *  and `update` should be called again to finish it. After an error the
+     *  last good sample is kept.
+     */
+    fn update(&mut self, now: u32) -> nb::Result<(), Self::Error>;
+
+    /// The latest sample
+    fn sample(&self) -> DistanceSample;
+
+    /// How many times talking to the sensor has failed
+    fn error_count(&self) -> u32;
+}
@@ -0,0 +1,505 @@
+/*!
+ *  Filtering for the distance sensors
+ *
+ *  Each sensor gets a `DistanceFilter` that turns its valid samples into one
+ *  estimate of the range, with a variance to say how much to trust it and a
+ *  stale flag for when the sensor has stopped giving good samples. A sample
+ *  with nothing in range is taken as the maximum range straight away, so a
+ *  wall going away shows up on the next sample. Faulty samples are left out
+ *  rather than filtered in.
+ */
+use crate::distance::DistanceSample;
+use crate::params::ParamType;
+
+/// The most samples the median filter can look at
+pub const MAX_MEDIAN_WINDOW: usize = 9;
+
+/// How a sensor's samples are combined
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterKind {
+    /// The latest valid sample as it is
+    None,
+    /// The median of the last few samples, which ignores odd spikes
+    Median,
+    /// An exponential moving average
+    Exponential,
+    /// A 1-D Kalman filter, assuming the range drifts as a random walk
+    Kalman,
+}
+
+impl FilterKind {
+    fn code(self) -> u32 {
+        match self {
+            FilterKind::None => 0,
+            FilterKind::Median => 1,
+            FilterKind::Exponential => 2,
+            FilterKind::Kalman => 3,
+        }
+    }
+
+    fn from_code(code: u32) -> FilterKind {
+        match code {
+            1 => FilterKind::Median,
+            2 => FilterKind::Exponential,
+            3 => FilterKind::Kalman,
+            _ => FilterKind::None,
+        }
+    }
+}
+
+/// Saved as its code, 0 to 3
+impl ParamType for FilterKind {
+    const INTEGER: bool = true;
+
+    fn to_f64(self) -> f64 {
+        f64::from(self.code())
+    }
+
+    fn from_f64(v: f64) -> FilterKind {
+        FilterKind::from_code(v as u32)
+    }
+}
+
+/// The filter settings, shared by all the sensors
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FilterConfig {
+    pub kind: FilterKind,
+    /// How many samples the median is taken over
+    pub median_window: u32,
+    /// How much of each new sample goes into the exponential average
+    pub alpha: f32,
+    /// How far the range can drift between samples, in mm^2/ms
+    pub process_noise: f32,
+    /// The variance of a single sample, in mm^2
+    pub measurement_noise: f32,
+    /// How old the last valid sample can get before the estimate is stale,
+    /// in ms
+    pub stale_after: u32,
+}
+
+impl Default for FilterConfig {
+    fn default() -> FilterConfig {
+        FilterConfig {
+            kind: FilterKind::Median,
+            median_window: 3,
+            alpha: 0.5,
+            process_noise: 1.0,
+            measurement_noise: 4.0,
+            stale_after: 100,
+        }
+    }
+}
+
+/// The filtered range of one sensor
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FilteredDistance {
+    /// The range in mm
+    pub range: f32,
+    /// How far off the range might be, in mm^2
+    pub variance: f32,
+    /// The time of the newest sample in the estimate
+    pub timestamp: u32,
+    /// Whether there hasn't been a good sample for too long, or ever. Nothing
+    /// in range counts as a good sample, only faults go stale.
+    pub stale: bool,
+}
+
+impl FilteredDistance {
+    /// The range, if it isn't stale
+    pub fn fresh_range(&self) -> Option<f32> {
+        if self.stale {
+            None
+        } else {
+            Some(self.range)
+        }
+    }
+}
+
+pub struct DistanceFilter {
+    kind: FilterKind,
+    window: [f32; MAX_MEDIAN_WINDOW],
+    window_len: usize,
+    next: usize,
+    estimate: f32,
+    variance: f32,
+    /// The newest sample looked at, valid or not
+    last_sample: Option<u32>,
+    /// The newest valid sample in the estimate
+    timestamp: Option<u32>,
+    stale: bool,
+    /// Whether the estimate is a sample with nothing in range
+    no_target: bool,
+}
+
+impl Default for DistanceFilter {
+    fn default() -> DistanceFilter {
+        DistanceFilter::new()
+    }
+}
+
+impl DistanceFilter {
+    pub const fn new() -> DistanceFilter {
+        DistanceFilter {
+            kind: FilterKind::None,
+            window: [0.0; MAX_MEDIAN_WINDOW],
+            window_len: 0,
+            next: 0,
+            estimate: 0.0,
+            variance: 0.0,
+            last_sample: None,
+            timestamp: None,
+            stale: true,
+            no_target: false,
+        }
+    }
+
+    /// Forget every sample, until the next valid one
+    pub fn reset(&mut self) {
+        *self = DistanceFilter {
+            kind: self.kind,
+            ..DistanceFilter::new()
+        };
+    }
+
+    /**
+     *  Take in the latest sample of the sensor
+     *
+     *  This can be called on every pass of the main loop, a sample that was
+     *  already taken in is only used to check for staleness. Once the
+     *  estimate is stale it starts again from the next valid sample, and it
+     *  also starts again going between nothing in range and a target.
+     */
+    pub fn update(
+        &mut self,
+        config: &FilterConfig,
+        sample: DistanceSample,
+        now: u32,
+    ) {
+        if config.kind != self.kind {
+            self.kind = config.kind;
+            self.reset();
+        }
+
+        if self.last_sample != Some(sample.timestamp) {
+            self.last_sample = Some(sample.timestamp);
+
+            if sample.is_no_target() {
+                self.reset();
+                self.add(config, f32::from(sample.range), sample.timestamp);
+                self.no_target = true;
+            } else if sample.is_valid() {
+                if self.stale || self.no_target {
+                    self.reset();
+                }
+                self.add(config, f32::from(sample.range), sample.timestamp);
+            }
+        }
+
+        self.stale = match self.timestamp {
+            Some(timestamp) => {
+                now.saturating_sub(timestamp) > config.stale_after
+            }
+            None => true,
+        };
+    }
+
+    fn add(&mut self, config: &FilterConfig, range: f32, timestamp: u32) {
+        let first = self.timestamp.is_none();
+        let dt = timestamp.saturating_sub(self.timestamp.unwrap_or(timestamp));
+        self.timestamp = Some(timestamp);
+
+        if first {
+            self.estimate = range;
+            self.variance = config.measurement_noise;
+        }
+
+        match self.kind {
+            FilterKind::None => {
+                self.estimate = range;
+                self.variance = config.measurement_noise;
+            }
+            FilterKind::Median => {
+                let len = match config.median_window as usize {
+                    0 => 1,
+                    len => len.min(MAX_MEDIAN_WINDOW),
+                };
+
+                if self.window_len > len {
+                    self.window_len = len;
+                }
+                if self.next >= len {
+                    self.next = 0;
+                }
+
+                self.window[self.next] = range;
+                self.next = (self.next + 1) % len;
+                if self.window_len < len {
+                    self.window_len += 1;
+                }
+
+                let window = &self.window[..self.window_len];
+                self.estimate = median(window);
+                self.variance = if window.len() > 1 {
+                    spread(window)
+                } else {
+                    config.measurement_noise
+                };
+            }
+            FilterKind::Exponential if !first => {
+                let error = range - self.estimate;
+                self.estimate += config.alpha * error;
+                self.variance = (1.0 - config.alpha)
+                    * (self.variance + config.alpha * error * error);
+            }
+            FilterKind::Kalman if !first => {
+                // Predict, then correct with the sample
+                let predicted =
+                    self.variance + config.process_noise * dt as f32;
+                let gain = predicted / (predicted + config.measurement_noise);
+                self.estimate += gain * (range - self.estimate);
+                self.variance = (1.0 - gain) * predicted;
+            }
+            FilterKind::Exponential | FilterKind::Kalman => {}
+        }
+    }
+
+    pub fn output(&self) -> FilteredDistance {
+        FilteredDistance {
+            range: self.estimate,
+            variance: self.variance,
+            timestamp: self.timestamp.unwrap_or(0),
+            stale: self.stale,
+        }
+    }
+}
+
+fn median(values: &[f32]) -> f32 {
+    let mut sorted = [0.0; MAX_MEDIAN_WINDOW];
+    let sorted = &mut sorted[..values.len()];
+    sorted.copy_from_slice(values);
+
+    // Insertion sort, there are only a handful
+    for i in 1..sorted.len() {
+        let mut j = i;
+        while j > 0 && sorted[j - 1] > sorted[j] {
+            sorted.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+
+    let mid = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+/// The variance of the values about their mean
+fn spread(values: &[f32]) -> f32 {
+    let n = values.len() as f32;
+    let mean = values.iter().sum::<f32>() / n;
+    values.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DistanceFilter, FilterConfig, FilterKind};
+    use crate::distance::DistanceSample;
+    use crate::vl6180x::RangeStatus;
+
+    fn config(kind: FilterKind) -> FilterConfig {
+        FilterConfig {
+            kind,
+            ..FilterConfig::default()
+        }
+    }
+
+    fn sample(range: u16, timestamp: u32) -> DistanceSample {
+        DistanceSample {
+            range,
+            status: RangeStatus::NoError,
+            timestamp,
+        }
+    }
+
+    fn no_target(timestamp: u32) -> DistanceSample {
+        DistanceSample {
+            range: 765,
+            status: RangeStatus::NoTargetIgnore,
+            timestamp,
+        }
+    }
+
+    fn invalid(timestamp: u32) -> DistanceSample {
+        DistanceSample {
+            range: 255,
+            status: RangeStatus::MaxConvergence,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn starts_out_stale() {
+        let mut filter = DistanceFilter::new();
+        filter.update(&config(FilterKind::Median), invalid(0), 0);
+        assert!(filter.output().stale);
+        assert_eq!(filter.output().fresh_range(), None);
+    }
+
+    #[test]
+    fn median_ignores_spikes() {
+        let config = config(FilterKind::Median);
+        let mut filter = DistanceFilter::new();
+
+        for (t, &range) in [50, 52, 200, 51, 49].iter().enumerate() {
+            filter.update(&config, sample(range, t as u32), t as u32);
+            assert!(filter.output().range < 60.0);
+        }
+
+        assert_eq!(filter.output().range, 51.0);
+        assert_eq!(filter.output().timestamp, 4);
+        assert!(!filter.output().stale);
+    }
+
+    #[test]
+    fn median_of_an_even_window() {
+        let config = FilterConfig {
+            median_window: 4,
+            ..config(FilterKind::Median)
+        };
+        let mut filter = DistanceFilter::new();
+
+        for (t, &range) in [10, 20, 30, 40].iter().enumerate() {
+            filter.update(&config, sample(range, t as u32), t as u32);
+        }
+
+        assert_eq!(filter.output().range, 25.0);
+        assert_eq!(filter.output().variance, 125.0);
+    }
+
+    #[test]
+    fn exponential_converges() {
+        let config = config(FilterKind::Exponential);
+        let mut filter = DistanceFilter::new();
+
+        filter.update(&config, sample(100, 0), 0);
+        assert_eq!(filter.output().range, 100.0);
+
+        filter.update(&config, sample(50, 1), 1);
+        assert_eq!(filter.output().range, 75.0);
+
+        for t in 2..30 {
+            filter.update(&config, sample(50, t), t);
+        }
+
+        assert!((filter.output().range - 50.0).abs() < 0.01);
+        assert!(filter.output().variance < 0.01);
+    }
+
+    #[test]
+    fn kalman_variance_shrinks_with_samples() {
+        let config = config(FilterKind::Kalman);
+        let mut filter = DistanceFilter::new();
+
+        filter.update(&config, sample(100, 0), 0);
+        let first = filter.output().variance;
+        assert_eq!(first, config.measurement_noise);
+
+        filter.update(&config, sample(104, 1), 1);
+        let output = filter.output();
+        assert!(output.variance < first);
+        assert!(output.range > 100.0 && output.range < 104.0);
+    }
+
+    #[test]
+    fn kalman_trusts_new_samples_after_a_gap() {
+        let config = config(FilterKind::Kalman);
+        let mut soon = DistanceFilter::new();
+        let mut late = DistanceFilter::new();
+
+        soon.update(&config, sample(100, 0), 0);
+        soon.update(&config, sample(110, 1), 1);
+        late.update(&config, sample(100, 0), 0);
+        late.update(&config, sample(110, 50), 50);
+
+        assert!(late.output().range > soon.output().range);
+    }
+
+    #[test]
+    fn invalid_samples_are_left_out() {
+        let config = config(FilterKind::Median);
+        let mut filter = DistanceFilter::new();
+
+        filter.update(&config, sample(60, 0), 0);
+        filter.update(&config, invalid(10), 10);
+
+        assert_eq!(filter.output().range, 60.0);
+        assert_eq!(filter.output().timestamp, 0);
+        assert!(!filter.output().stale);
+    }
+
+    #[test]
+    fn goes_stale_without_valid_samples() {
+        let config = config(FilterKind::Median);
+        let mut filter = DistanceFilter::new();
+
+        filter.update(&config, sample(60, 0), 0);
+        filter.update(&config, invalid(50), config.stale_after);
+        assert!(!filter.output().stale);
+
+        filter.update(&config, invalid(150), config.stale_after + 1);
+        assert!(filter.output().stale);
+
+        // Starts again without the old samples
+        filter.update(&config, sample(120, 200), 200);
+        assert_eq!(filter.output().range, 120.0);
+        assert!(!filter.output().stale);
+    }
+
+    #[test]
+    fn a_wall_going_away_is_open_on_the_next_sample() {
+        let config = config(FilterKind::Median);
+        let mut filter = DistanceFilter::new();
+
+        for t in 0..3 {
+            filter.update(&config, sample(60, t), t);
+        }
+        filter.update(&config, no_target(3), 3);
+
+        assert_eq!(filter.output().range, 765.0);
+        assert_eq!(filter.output().timestamp, 3);
+        assert!(!filter.output().stale);
+
+        // Nothing in range for longer than stale_after is still fresh
+        let later = 3 + config.stale_after + 1;
+        filter.update(&config, no_target(later), later);
+        assert_eq!(filter.output().fresh_range(), Some(765.0));
+
+        // And the wall coming back isn't averaged with it
+        filter.update(&config, sample(60, later + 1), later + 1);
+        assert_eq!(filter.output().range, 60.0);
+    }
+
+    #[test]
+    fn the_same_sample_is_only_taken_once() {
+        let config = config(FilterKind::Exponential);
+        let mut filter = DistanceFilter::new();
+
+        filter.update(&config, sample(100, 0), 0);
+        filter.update(&config, sample(50, 1), 1);
+        filter.update(&config, sample(50, 1), 2);
+
+        assert_eq!(filter.output().range, 75.0);
+    }
+
+    #[test]
+    fn changing_the_kind_starts_again() {
+        let mut filter = DistanceFilter::new();
+
+        filter.update(&config(FilterKind::Exponential), sample(100, 0), 0);
+        filter.update(&config(FilterKind::Kalman), sample(50, 1), 1);
+
+        assert_eq!(filter.output().range, 50.0);
+    }
+}
@@ -0,0 +1,25 @@
+//! What the drivers in here need of an I2C bus
+
+/**
+ *  A transaction that runs in the background
+ *
+ *  `start` returns straight away, and `finish` says `WouldBlock` until the
+ *  transaction is done.
+ */
+pub trait Transaction {
+    type Error;
+
+    /// Start writing `bytes` to `address`, then reading `read` bytes back
+    fn start(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        read: usize,
+    ) -> Result<(), Self::Error>;
+
+    /// Get the bytes that were read, once the transaction is done
+    fn finish(&mut self, buffer: &mut [u8]) -> nb::Result<(), Self::Error>;
+
+    /// Give up on the transaction
+    fn cancel(&mut self);
+}
@@ -0,0 +1,28 @@
+//! Code shared between the micromouse firmware and the simulation
+//!
+//! Everything in here must stay `no_std` so that what gets benchmarked in the
+//! simulation is exactly what gets flashed onto the mouse. Anything in the
+//! firmware that doesn't touch the hardware belongs here too, since the
+//! firmware can't run its tests but this can, with `cargo test`.
+
+#![no_std]
+
+pub mod battery;
+pub mod characterize;
+pub mod config;
+pub mod crc;
+pub mod distance;
+pub mod filter;
+pub mod i2c;
+pub mod line;
+pub mod motors;
+pub mod mouse;
+pub mod navigate;
+pub mod params;
+pub mod path;
+pub mod pid;
+pub mod profile;
+pub mod ring;
+pub mod telemetry;
+pub mod vl6180x;
+pub mod walls;
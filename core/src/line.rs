@@ -0,0 +1,106 @@
+/// The longest command line, anything longer is dropped
+pub const LINE_LEN: usize = 128;
+
+/**
+ *  Collects received bytes into lines
+ *
+ *  Bytes get pushed in one at a time, and each newline hands back the line
+ *  before it, so any number of queued lines come out one after another.
+ *  Carriage returns are ignored. A line that doesn't fit is thrown away
+ *  whole and counted, rather than being run cut short.
+ */
+pub struct LineAssembler {
+    bytes: [u8; LINE_LEN],
+    len: usize,
+    overflowed: bool,
+    overflows: u32,
+}
+
+impl Default for LineAssembler {
+    fn default() -> LineAssembler {
+        LineAssembler::new()
+    }
+}
+
+impl LineAssembler {
+    pub fn new() -> LineAssembler {
+        LineAssembler {
+            bytes: [0; LINE_LEN],
+            len: 0,
+            overflowed: false,
+            overflows: 0,
+        }
+    }
+
+    /// Add a byte, returning the line if it finished one
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        match byte {
+            b'\n' => {
+                let len = self.len;
+                self.len = 0;
+
+                if self.overflowed {
+                    self.overflowed = false;
+                    self.overflows += 1;
+                    None
+                } else {
+                    Some(&self.bytes[..len])
+                }
+            }
+            b'\r' => None,
+            _ => {
+                if self.len < LINE_LEN {
+                    self.bytes[self.len] = byte;
+                    self.len += 1;
+                } else {
+                    self.overflowed = true;
+                }
+                None
+            }
+        }
+    }
+
+    /// How many lines were too long
+    pub fn overflows(&self) -> u32 {
+        self.overflows
+    }
+
+    pub fn reset_overflows(&mut self) {
+        self.overflows = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LineAssembler, LINE_LEN};
+
+    fn push_all<'a>(
+        lines: &'a mut LineAssembler,
+        bytes: &[u8],
+    ) -> Option<&'a [u8]> {
+        let (last, rest) = bytes.split_last().unwrap();
+        for byte in rest {
+            assert_eq!(lines.push(*byte), None);
+        }
+        lines.push(*last)
+    }
+
+    #[test]
+    fn line_several() {
+        let mut lines = LineAssembler::new();
+        assert_eq!(push_all(&mut lines, b"plan go\r\n"), Some(&b"plan go"[..]));
+        assert_eq!(push_all(&mut lines, b"report\n"), Some(&b"report"[..]));
+        assert_eq!(push_all(&mut lines, b"\n"), Some(&b""[..]));
+    }
+
+    #[test]
+    fn line_too_long() {
+        let mut lines = LineAssembler::new();
+        for _ in 0..LINE_LEN + 10 {
+            assert_eq!(lines.push(b'a'), None);
+        }
+        assert_eq!(lines.push(b'\n'), None);
+        assert_eq!(lines.overflows(), 1);
+        assert_eq!(push_all(&mut lines, b"ok\n"), Some(&b"ok"[..]));
+    }
+}
@@ -0,0 +1,103 @@
+pub mod control;
+pub mod velocity;
+
+/// The PWM count for full power, the ARR of the motor timers
+pub const PWM_MAX: i32 = 10000;
+
+/// Used when the battery can't be read, like when running off USB
+const NOMINAL_BATTERY_VOLTS: f64 = 7.4;
+const MIN_BATTERY_VOLTS: f64 = 3.0;
+
+/**
+ *  What it takes to turn a wheel, from the motor constants
+ *
+ *  The voltage for a wheel velocity `v` in ticks/ms and acceleration `a` in
+ *  ticks/ms^2 is kS sign(v) + kV v + kA a. kS overcomes static friction, kV
+ *  the back EMF and kA the inertia.
+ */
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Feedforward {
+    pub ks: f64,
+    pub kv: f64,
+    pub ka: f64,
+}
+
+impl Feedforward {
+    pub fn volts(&self, velocity: f64, acceleration: f64) -> f64 {
+        let sign = if velocity > 0.0 {
+            1.0
+        } else if velocity < 0.0 {
+            -1.0
+        } else {
+            0.0
+        };
+
+        self.ks * sign + self.kv * velocity + self.ka * acceleration
+    }
+}
+
+pub trait Motor {
+    fn change_power(&mut self, power: i32);
+}
+
+pub trait Encoder {
+    fn count(&self) -> i32;
+    fn reset(&mut self);
+}
+
+/**
+ *  The PWM count that puts `volts` across a motor
+ *
+ *  The duty cycle is the fraction of the battery voltage the motor sees, so
+ *  the same volts take more duty as the battery runs down. Anything more than
+ *  the battery has is full power.
+ */
+pub fn volts_to_duty(volts: f64, battery_volts: f64) -> i32 {
+    let battery_volts = if battery_volts < MIN_BATTERY_VOLTS {
+        NOMINAL_BATTERY_VOLTS
+    } else {
+        battery_volts
+    };
+
+    let duty = (volts / battery_volts * f64::from(PWM_MAX)) as i32;
+    duty.signum() * duty.abs().min(PWM_MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{volts_to_duty, Feedforward, PWM_MAX};
+
+    #[test]
+    fn feedforward_terms() {
+        let feedforward = Feedforward {
+            ks: 0.5,
+            kv: 2.0,
+            ka: 10.0,
+        };
+
+        assert_eq!(feedforward.volts(0.0, 0.0), 0.0);
+        assert_eq!(feedforward.volts(1.0, 0.0), 2.5);
+        assert_eq!(feedforward.volts(-1.0, 0.0), -2.5);
+        assert_eq!(feedforward.volts(1.0, 0.1), 3.5);
+        assert_eq!(feedforward.volts(0.0, 0.1), 1.0);
+    }
+
+    #[test]
+    fn duty_follows_the_battery() {
+        assert_eq!(volts_to_duty(4.0, 8.0), PWM_MAX / 2);
+        assert_eq!(volts_to_duty(4.0, 6.4), PWM_MAX * 5 / 8);
+        assert_eq!(volts_to_duty(-4.0, 8.0), -PWM_MAX / 2);
+        assert_eq!(volts_to_duty(0.0, 8.0), 0);
+    }
+
+    #[test]
+    fn duty_is_limited_to_full_power() {
+        assert_eq!(volts_to_duty(12.0, 8.0), PWM_MAX);
+        assert_eq!(volts_to_duty(-12.0, 8.0), -PWM_MAX);
+    }
+
+    #[test]
+    fn unknown_battery_is_nominal() {
+        assert_eq!(volts_to_duty(3.7, 0.0), PWM_MAX / 2);
+    }
+}
@@ -0,0 +1,383 @@
+/*!
+ *  Closed loop control of one wheel
+ *
+ *  A `MotorControl` owns a motor and the encoder on the same wheel, and
+ *  drives the motor to hold a position or a velocity from the encoder. The
+ *  gains of its PID are for whichever of the two it is used for, and the PID
+ *  is expected to keep its output to what the motor can take.
+ */
+use core::mem;
+
+use crate::motors::velocity::VelocityConfig;
+use crate::motors::velocity::VelocityEstimator;
+use crate::motors::volts_to_duty;
+use crate::motors::Encoder;
+use crate::motors::Feedforward;
+use crate::motors::Motor;
+use crate::pid::Controller;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DriveMode {
+    /// The motor is off
+    Idle,
+    /// Hold the encoder at a count, in ticks
+    Position(i32),
+    /// Turn the wheel at a velocity, in ticks/ms
+    Velocity(f64),
+    /// Put a fixed voltage across the motor, without looking at the encoder
+    Volts(f64),
+}
+
+pub struct MotorControl<M, E, C>
+where
+    M: Motor,
+    E: Encoder,
+    C: Controller,
+{
+    pub pid: C,
+    feedforward: Feedforward,
+    mode: DriveMode,
+    motor: M,
+    encoder: E,
+    velocity: VelocityEstimator,
+    velocity_config: VelocityConfig,
+    /// The velocity target at the last update, for the acceleration
+    last_target: f64,
+    volts: f64,
+    duty: i32,
+    battery_volts: f64,
+}
+
+impl<M, E, C> MotorControl<M, E, C>
+where
+    M: Motor,
+    E: Encoder,
+    C: Controller,
+{
+    /// Start out idle
+    pub fn new(pid: C, motor: M, encoder: E) -> MotorControl<M, E, C> {
+        MotorControl {
+            pid,
+            feedforward: Feedforward::default(),
+            mode: DriveMode::Idle,
+            motor,
+            encoder,
+            velocity: VelocityEstimator::new(),
+            velocity_config: VelocityConfig::default(),
+            last_target: 0.0,
+            volts: 0.0,
+            duty: 0,
+            battery_volts: 0.0,
+        }
+    }
+
+    /// The model used on top of the PID in velocity mode
+    pub fn set_feedforward(&mut self, feedforward: Feedforward) {
+        self.feedforward = feedforward;
+    }
+
+    pub fn set_velocity_filter(&mut self, config: VelocityConfig) {
+        self.velocity_config = config;
+    }
+
+    /// The battery voltage to work out the duty from, see `volts_to_duty`
+    pub fn set_battery_volts(&mut self, battery_volts: f64) {
+        self.battery_volts = battery_volts;
+    }
+
+    pub fn mode(&self) -> DriveMode {
+        self.mode
+    }
+
+    pub fn idle(&mut self) {
+        self.change_mode(DriveMode::Idle);
+        self.drive(0.0);
+    }
+
+    pub fn set_position(&mut self, position: i32) {
+        self.change_mode(DriveMode::Position(position));
+        self.pid.set_target(f64::from(position));
+    }
+
+    pub fn set_velocity(&mut self, velocity: f64) {
+        self.change_mode(DriveMode::Velocity(velocity));
+        self.pid.set_target(velocity);
+
+        // Nothing left over from moving to creep along with once stopped
+        if velocity == 0.0 {
+            self.pid.reset();
+        }
+    }
+
+    /// Unlike the other modes, this takes effect right away
+    pub fn set_volts(&mut self, volts: f64) {
+        self.change_mode(DriveMode::Volts(volts));
+        self.drive(volts);
+    }
+
+    fn change_mode(&mut self, mode: DriveMode) {
+        // The integral of one mode means nothing to another
+        if mem::discriminant(&mode) != mem::discriminant(&self.mode) {
+            self.pid.reset();
+
+            // Nor does its target, taking it as the last velocity would look
+            // like a jump in acceleration
+            if let DriveMode::Velocity(target) = mode {
+                self.last_target = target;
+            }
+        }
+
+        self.mode = mode;
+    }
+
+    /// Zero the encoder and forget the history of the PID
+    pub fn reset(&mut self) {
+        self.velocity.rebase(self.encoder.count());
+        self.encoder.reset();
+        self.pid.reset();
+    }
+
+    /// Measure the wheel and drive the motor, every `delta_time` ms
+    pub fn update(&mut self, delta_time: u32) {
+        let position = self.encoder.count();
+        self.velocity
+            .update(&self.velocity_config, position, delta_time);
+
+        let velocity = self.velocity.velocity();
+        let delta_time = f64::from(delta_time);
+
+        let volts = match self.mode {
+            DriveMode::Idle => 0.0,
+            DriveMode::Position(_) => {
+                self.pid.update(f64::from(position), delta_time)
+            }
+            DriveMode::Velocity(target) => {
+                // The model does most of the work, the PID only corrects what
+                // it gets wrong
+                let accel = (target - self.last_target) / delta_time;
+                self.last_target = target;
+
+                self.feedforward.volts(target, accel)
+                    + self.pid.update(velocity, delta_time)
+            }
+            DriveMode::Volts(volts) => volts,
+        };
+
+        self.drive(volts);
+    }
+
+    fn drive(&mut self, volts: f64) {
+        self.volts = volts;
+        self.duty = volts_to_duty(volts, self.battery_volts);
+        self.motor.change_power(self.duty);
+    }
+
+    /// The encoder count, in ticks
+    pub fn position(&self) -> i32 {
+        self.encoder.count()
+    }
+
+    /// The estimated velocity, in ticks/ms
+    pub fn velocity(&self) -> f64 {
+        self.velocity.velocity()
+    }
+
+    pub fn velocity_estimator(&self) -> &VelocityEstimator {
+        &self.velocity
+    }
+
+    /// The position or velocity being held, whichever the mode is
+    pub fn target(&self) -> f64 {
+        self.pid.target()
+    }
+
+    /// The voltage asked of the motor
+    pub fn volts(&self) -> f64 {
+        self.volts
+    }
+
+    /// The PWM count the motor is driven with, out of `PWM_MAX`
+    pub fn duty(&self) -> i32 {
+        self.duty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::{DriveMode, MotorControl};
+    use crate::motors::{Encoder, Feedforward, Motor, PWM_MAX};
+    use crate::pid::Proportional;
+
+    struct TestMotor<'a>(&'a Cell<i32>);
+
+    impl<'a> Motor for TestMotor<'a> {
+        fn change_power(&mut self, power: i32) {
+            self.0.set(power);
+        }
+    }
+
+    struct TestEncoder<'a>(&'a Cell<i32>);
+
+    impl<'a> Encoder for TestEncoder<'a> {
+        fn count(&self) -> i32 {
+            self.0.get()
+        }
+
+        fn reset(&mut self) {
+            self.0.set(0);
+        }
+    }
+
+    fn control<'a>(
+        power: &'a Cell<i32>,
+        count: &'a Cell<i32>,
+    ) -> MotorControl<TestMotor<'a>, TestEncoder<'a>, Proportional> {
+        let mut control = MotorControl::new(
+            Proportional::new(1.0),
+            TestMotor(power),
+            TestEncoder(count),
+        );
+        control.set_battery_volts(8.0);
+        control
+    }
+
+    #[test]
+    fn idle_turns_the_motor_off() {
+        let power = Cell::new(1234);
+        let count = Cell::new(0);
+        let mut control = control(&power, &count);
+
+        control.idle();
+        assert_eq!(power.get(), 0);
+
+        control.update(10);
+        assert_eq!(control.mode(), DriveMode::Idle);
+        assert_eq!(power.get(), 0);
+    }
+
+    #[test]
+    fn estimates_velocity() {
+        let power = Cell::new(0);
+        let count = Cell::new(0);
+        let mut control = control(&power, &count);
+
+        for period in 0..100 {
+            count.set(period * 5);
+            control.update(10);
+        }
+        assert!((control.velocity() - 0.5).abs() < 0.01);
+
+        // Zeroing the encoder doesn't look like going backwards
+        control.reset();
+        assert_eq!(control.position(), 0);
+        count.set(5);
+        control.update(10);
+        assert!((control.velocity() - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn holds_a_position() {
+        let power = Cell::new(0);
+        let count = Cell::new(0);
+        let mut control = control(&power, &count);
+
+        control.set_position(2);
+        control.update(10);
+        assert_eq!(control.volts(), 2.0);
+        assert_eq!(power.get(), PWM_MAX / 4);
+
+        // Past the target it backs up, no harder than full power
+        count.set(100);
+        control.update(10);
+        assert_eq!(control.volts(), -98.0);
+        assert_eq!(power.get(), -PWM_MAX);
+    }
+
+    #[test]
+    fn velocity_adds_the_feedforward() {
+        let power = Cell::new(0);
+        let count = Cell::new(0);
+        let mut control = control(&power, &count);
+        control.set_feedforward(Feedforward {
+            ks: 0.5,
+            kv: 2.0,
+            ka: 0.0,
+        });
+
+        control.set_velocity(1.0);
+        control.update(10);
+
+        // 2.5 V of model and 1 V of error
+        assert_eq!(control.volts(), 3.5);
+        assert_eq!(control.target(), 1.0);
+    }
+
+    #[test]
+    fn no_acceleration_from_another_mode() {
+        let power = Cell::new(0);
+        let count = Cell::new(0);
+        let mut control = control(&power, &count);
+        control.set_feedforward(Feedforward {
+            ks: 0.0,
+            kv: 2.0,
+            ka: 100.0,
+        });
+
+        control.set_velocity(3.0);
+        control.update(10);
+
+        // The velocity from before holding a position is long gone
+        control.set_position(0);
+        control.update(10);
+
+        control.set_velocity(1.0);
+        control.update(10);
+
+        // 2 V of model and 1 V of error, none of it for accelerating
+        assert_eq!(control.volts(), 3.0);
+    }
+
+    #[test]
+    fn acceleration_starts_from_reentering_velocity() {
+        let power = Cell::new(0);
+        let count = Cell::new(0);
+        let mut control = control(&power, &count);
+        control.set_feedforward(Feedforward {
+            ks: 0.0,
+            kv: 0.0,
+            ka: 100.0,
+        });
+        control.set_velocity(2.0);
+        control.update(10);
+
+        // Like characterizing, which drives the motor open loop
+        control.set_volts(1.0);
+        control.update(10);
+
+        // The first update back in velocity mode is all error, none of it
+        // for accelerating
+        control.set_velocity(0.5);
+        control.update(10);
+        assert_eq!(control.volts(), 0.5);
+
+        // But changing the target after that is
+        control.set_velocity(0.6);
+        control.update(10);
+        assert!((control.volts() - 1.6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn volts_are_applied_right_away() {
+        let power = Cell::new(0);
+        let count = Cell::new(0);
+        let mut control = control(&power, &count);
+
+        control.set_volts(-2.0);
+        assert_eq!(power.get(), -PWM_MAX / 4);
+
+        control.update(10);
+        assert_eq!(power.get(), -PWM_MAX / 4);
+    }
+}
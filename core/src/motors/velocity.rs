@@ -0,0 +1,204 @@
+/*!
+ *  Wheel velocity from the encoder counts
+ *
+ *  Counting ticks over a control period only gives the velocity to the
+ *  nearest tick per period, which is a big step at crawl speeds. An
+ *  alpha-beta filter tracks the count as a position and velocity instead,
+ *  correcting both by a fraction of how far each new count is from where it
+ *  predicted, so the velocity moves smoothly between the steps.
+ */
+use crate::mouse::MouseConfig;
+
+/// The variance of the count from it being whole ticks, in ticks^2
+const QUANTIZATION_VARIANCE: f64 = 1.0 / 12.0;
+
+/// How much of each new residual goes into its average
+const RESIDUAL_WEIGHT: f64 = 0.05;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VelocityConfig {
+    /// How much of the residual corrects the position
+    pub alpha: f32,
+    /// How much of the residual, over the period, corrects the velocity
+    pub beta: f32,
+}
+
+impl Default for VelocityConfig {
+    fn default() -> VelocityConfig {
+        VelocityConfig {
+            alpha: 0.5,
+            beta: 0.1,
+        }
+    }
+}
+
+/// A wheel velocity in mm/s
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct WheelVelocity {
+    pub velocity: f32,
+    /// In (mm/s)^2
+    pub variance: f32,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct VelocityEstimator {
+    started: bool,
+    /// Where the count is thought to be, in ticks
+    position: f64,
+    /// In ticks/ms
+    velocity: f64,
+    /// The average squared residual, in ticks^2
+    residual_variance: f64,
+    variance: f64,
+}
+
+impl VelocityEstimator {
+    pub fn new() -> VelocityEstimator {
+        VelocityEstimator::default()
+    }
+
+    /// Take a new count, `delta_time` ms after the last one
+    pub fn update(
+        &mut self,
+        config: &VelocityConfig,
+        count: i32,
+        delta_time: u32,
+    ) {
+        if !self.started || delta_time == 0 {
+            self.started = true;
+            self.position = f64::from(count);
+            return;
+        }
+
+        let alpha = f64::from(config.alpha);
+        let beta = f64::from(config.beta);
+        let delta_time = f64::from(delta_time);
+
+        let predicted = self.position + self.velocity * delta_time;
+        let residual = f64::from(count) - predicted;
+
+        self.position = predicted + alpha * residual;
+        self.velocity += beta * residual / delta_time;
+
+        self.residual_variance +=
+            RESIDUAL_WEIGHT * (residual * residual - self.residual_variance);
+
+        // How much of the noise on the count makes it into the velocity, for
+        // a filter that has settled
+        let noise = if self.residual_variance > QUANTIZATION_VARIANCE {
+            self.residual_variance
+        } else {
+            QUANTIZATION_VARIANCE
+        };
+        let gain = 2.0 * beta * beta / (alpha * (4.0 - 2.0 * alpha - beta));
+
+        self.variance = noise * gain / (delta_time * delta_time);
+    }
+
+    /// Follow the encoder being zeroed, keeping the velocity
+    pub fn rebase(&mut self, count: i32) {
+        self.position -= f64::from(count);
+    }
+
+    /// In ticks/ms
+    pub fn velocity(&self) -> f64 {
+        self.velocity
+    }
+
+    /// In (ticks/ms)^2
+    pub fn variance(&self) -> f64 {
+        self.variance
+    }
+
+    pub fn wheel_velocity(&self, mouse: &MouseConfig) -> WheelVelocity {
+        // ticks/ms to mm/s
+        let scale = mouse.ticks_to_mm(1000.0);
+
+        WheelVelocity {
+            velocity: self.velocity as f32 * scale,
+            variance: self.variance as f32 * scale * scale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VelocityConfig, VelocityEstimator};
+    use crate::config::BotConfig;
+
+    #[test]
+    fn first_count_is_not_a_velocity() {
+        let config = VelocityConfig::default();
+        let mut estimator = VelocityEstimator::new();
+
+        estimator.update(&config, 1000, 10);
+        assert_eq!(estimator.velocity(), 0.0);
+    }
+
+    #[test]
+    fn tracks_a_crawl_between_ticks() {
+        let config = VelocityConfig::default();
+        let mut estimator = VelocityEstimator::new();
+
+        // 0.03 ticks/ms, a tick every 3 or 4 periods
+        for period in 0..300 {
+            let count = (f64::from(period) * 0.3) as i32;
+            estimator.update(&config, count, 10);
+        }
+
+        for period in 300..330 {
+            let count = (f64::from(period) * 0.3) as i32;
+            estimator.update(&config, count, 10);
+
+            assert!((estimator.velocity() - 0.03).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn stops_when_the_count_does() {
+        let config = VelocityConfig::default();
+        let mut estimator = VelocityEstimator::new();
+
+        for period in 0..100 {
+            estimator.update(&config, period * 5, 10);
+        }
+        assert!((estimator.velocity() - 0.5).abs() < 0.01);
+
+        for _ in 0..100 {
+            estimator.update(&config, 500, 10);
+        }
+        assert!(estimator.velocity().abs() < 0.01);
+    }
+
+    #[test]
+    fn rebase_keeps_the_velocity() {
+        let config = VelocityConfig::default();
+        let mut estimator = VelocityEstimator::new();
+
+        for period in 0..100 {
+            estimator.update(&config, period * 5, 10);
+        }
+        let velocity = estimator.velocity();
+
+        estimator.rebase(495);
+        estimator.update(&config, 5, 10);
+        assert!((estimator.velocity() - velocity).abs() < 0.01);
+    }
+
+    #[test]
+    fn reports_mm_per_second() {
+        let config = BotConfig::default();
+        let mut estimator = VelocityEstimator::new();
+
+        let ticks_per_ms = config.mouse.mm_to_ticks(0.2);
+        for period in 0..200 {
+            let count = (ticks_per_ms * 10.0 * period as f32) as i32;
+            estimator.update(&config.velocity_filter, count, 10);
+        }
+
+        let wheel = estimator.wheel_velocity(&config.mouse);
+        assert!((wheel.velocity - 200.0).abs() < 5.0);
+        assert!(wheel.variance > 0.0);
+        assert!(wheel.variance < 25.0);
+    }
+}
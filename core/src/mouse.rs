@@ -1,6 +1,6 @@
 use core::f32;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct MouseConfig {
     pub wheel_diameter: f32,
     pub gearbox_ratio: f32,
@@ -13,7 +13,8 @@ pub struct MouseConfig {
 
 impl MouseConfig {
     pub fn ticks_per_mm(&self) -> f32 {
-        (self.ticks_per_rev * self.gearbox_ratio) / (self.wheel_diameter * f32::consts::PI)
+        (self.ticks_per_rev * self.gearbox_ratio)
+            / (self.wheel_diameter * f32::consts::PI)
     }
 
     pub fn ticks_to_mm(&self, ticks: f32) -> f32 {
@@ -48,14 +49,3 @@ impl MouseConfig {
         rads * self.mm_per_rad()
     }
 }
-
-struct Mouse {
-    path: Path,
-}
-
-impl Mouse {
-    fn update(&mut self, time: u32, left_encoder: f32, right_encoder: f32, left_distance: u8, right_distance: u8, front_distance: u8) -> (f32, f32) {
-        let (angular_power, remaining_segments) = self.path.update();
-    }
-}
-
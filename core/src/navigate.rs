@@ -1,10 +1,9 @@
+//! Maze navigation shared between the firmware and the simulation
+
 use rand::rngs::SmallRng;
 use rand::Rng;
 use rand::SeedableRng;
 
-use crate::mouse::Direction;
-use crate::Visualize;
-
 const F_MOVES: [Option<Move>; 2] = [Some(Move::Forward), None];
 const L_MOVES: [Option<Move>; 2] = [Some(Move::TurnLeft), Some(Move::Forward)];
 const R_MOVES: [Option<Move>; 2] = [Some(Move::TurnRight), Some(Move::Forward)];
@@ -26,34 +25,54 @@ pub struct MoveOptions {
     pub right: bool,
 }
 
-pub trait Navigate {
-    type Cell: Visualize + Copy;
-    fn navigate(
-        &mut self,
-        x: usize,
-        y: usize,
-        d: Direction,
-        move_options: MoveOptions,
-    ) -> [Option<Move>; 2];
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    North,
+    South,
+    East,
+    West,
+}
 
-    fn get_cell(&self, x: i32, y: i32) -> Self::Cell;
+impl Direction {
+    pub fn turn_left(self) -> Direction {
+        match self {
+            Direction::North => Direction::West,
+            Direction::West => Direction::South,
+            Direction::South => Direction::East,
+            Direction::East => Direction::North,
+        }
+    }
+
+    pub fn turn_right(self) -> Direction {
+        match self {
+            Direction::North => Direction::East,
+            Direction::East => Direction::South,
+            Direction::South => Direction::West,
+            Direction::West => Direction::North,
+        }
+    }
+
+    pub fn turn_around(self) -> Direction {
+        match self {
+            Direction::North => Direction::South,
+            Direction::East => Direction::West,
+            Direction::South => Direction::North,
+            Direction::West => Direction::East,
+        }
+    }
 }
 
-impl<N: Navigate> Navigate for Box<N> {
-    type Cell = N::Cell;
+pub trait Navigate {
+    type Cell: Copy;
     fn navigate(
         &mut self,
-        x: usize,
-        y: usize,
+        x: i32,
+        y: i32,
         d: Direction,
         move_options: MoveOptions,
-    ) -> [Option<Move>; 2] {
-        self.as_mut().navigate(x, y, d, move_options)
-    }
+    ) -> [Option<Move>; 2];
 
-    fn get_cell(&self, x: i32, y: i32) -> Self::Cell {
-        self.as_ref().get_cell(x, y)
-    }
+    fn get_cell(&self, x: i32, y: i32) -> Self::Cell;
 }
 
 pub struct LeftWall {}
@@ -64,12 +83,18 @@ impl LeftWall {
     }
 }
 
+impl Default for LeftWall {
+    fn default() -> LeftWall {
+        LeftWall::new()
+    }
+}
+
 impl Navigate for LeftWall {
     type Cell = ();
     fn navigate(
         &mut self,
-        _x: usize,
-        _y: usize,
+        _x: i32,
+        _y: i32,
         _d: Direction,
         move_options: MoveOptions,
     ) -> [Option<Move>; 2] {
@@ -84,9 +109,7 @@ impl Navigate for LeftWall {
         }
     }
 
-    fn get_cell(&self, x: i32, y: i32) -> Self::Cell {
-        ()
-    }
+    fn get_cell(&self, _x: i32, _y: i32) -> Self::Cell {}
 }
 
 pub struct RandomNavigate {
@@ -105,8 +128,8 @@ impl Navigate for RandomNavigate {
     type Cell = ();
     fn navigate(
         &mut self,
-        _x: usize,
-        _y: usize,
+        _x: i32,
+        _y: i32,
         _d: Direction,
         move_options: MoveOptions,
     ) -> [Option<Move>; 2] {
@@ -142,9 +165,7 @@ impl Navigate for RandomNavigate {
         }
     }
 
-    fn get_cell(&self, x: i32, y: i32) -> Self::Cell {
-        ()
-    }
+    fn get_cell(&self, _x: i32, _y: i32) -> Self::Cell {}
 }
 
 pub struct DeadEndNavigate {
@@ -159,11 +180,17 @@ impl DeadEndNavigate {
     }
 }
 
+impl Default for DeadEndNavigate {
+    fn default() -> DeadEndNavigate {
+        DeadEndNavigate::new()
+    }
+}
+
 impl Navigate for DeadEndNavigate {
     type Cell = bool;
 
     fn get_cell(&self, x: i32, y: i32) -> bool {
-        if x >= 0 && x <= 15 && y >= 0 && y <= 15 {
+        if (0..=15).contains(&x) && (0..=15).contains(&y) {
             self.cells[x as usize][y as usize]
         } else {
             true
@@ -172,32 +199,17 @@ impl Navigate for DeadEndNavigate {
 
     fn navigate(
         &mut self,
-        x: usize,
-        y: usize,
+        x: i32,
+        y: i32,
         d: Direction,
         move_options: MoveOptions,
     ) -> [Option<Move>; 2] {
-        let x = x as i32;
-        let y = y as i32;
+        let ux = x.clamp(0, 15) as usize;
 
-        let ux = if x < 0 {
-            0
-        } else if x > 15 {
-            15
-        } else {
-            x
-        } as usize;
-
-        let uy = if y < 0 {
-            0
-        } else if y > 15 {
-            15
-        } else {
-            y
-        } as usize;
+        let uy = y.clamp(0, 15) as usize;
 
         // win condition
-        if x >= 7 && x <= 8 && y >= 7 && y <= 8 {
+        if (7..=8).contains(&x) && (7..=8).contains(&y) {
             [Some(Move::TurnLeft), Some(Move::TurnLeft)]
         } else {
             let left_blocked = !move_options.left
@@ -241,8 +253,6 @@ impl Navigate for DeadEndNavigate {
                 self.cells[ux][uy] = true;
             }
 
-            let c = self.cells[ux][uy];
-
             if move_options.forward && !front_blocked {
                 F_MOVES
             } else if move_options.left && !left_blocked {
@@ -256,6 +266,118 @@ impl Navigate for DeadEndNavigate {
     }
 }
 
+pub struct LessRandomNavigate {
+    cells: [[u8; 16]; 16],
+}
+
+impl LessRandomNavigate {
+    pub fn new() -> LessRandomNavigate {
+        LessRandomNavigate {
+            cells: [[0; 16]; 16],
+        }
+    }
+}
+
+impl Default for LessRandomNavigate {
+    fn default() -> LessRandomNavigate {
+        LessRandomNavigate::new()
+    }
+}
+
+impl Navigate for LessRandomNavigate {
+    type Cell = u8;
+
+    fn get_cell(&self, x: i32, y: i32) -> u8 {
+        if (0..=15).contains(&x) && (0..=15).contains(&y) {
+            self.cells[x as usize][y as usize]
+        } else {
+            255
+        }
+    }
+
+    fn navigate(
+        &mut self,
+        x: i32,
+        y: i32,
+        d: Direction,
+        move_options: MoveOptions,
+    ) -> [Option<Move>; 2] {
+        let ux = x.clamp(0, 15) as usize;
+        let uy = y.clamp(0, 15) as usize;
+
+        self.cells[ux][uy] = self.cells[ux][uy].saturating_add(1);
+
+        // win condition
+        if (7..=8).contains(&x) && (7..=8).contains(&y) {
+            [Some(Move::TurnAround), Some(Move::TurnLeft)]
+        } else {
+            let left_cell = match d {
+                Direction::North => self.get_cell(x - 1, y),
+                Direction::South => self.get_cell(x + 1, y),
+                Direction::East => self.get_cell(x, y + 1),
+                Direction::West => self.get_cell(x, y - 1),
+            };
+
+            let front_cell = match d {
+                Direction::North => self.get_cell(x, y + 1),
+                Direction::South => self.get_cell(x, y - 1),
+                Direction::East => self.get_cell(x + 1, y),
+                Direction::West => self.get_cell(x - 1, y),
+            };
+
+            let right_cell = match d {
+                Direction::North => self.get_cell(x + 1, y),
+                Direction::South => self.get_cell(x - 1, y),
+                Direction::East => self.get_cell(x, y - 1),
+                Direction::West => self.get_cell(x, y + 1),
+            };
+
+            if move_options.forward
+                && if move_options.left {
+                    front_cell <= left_cell
+                } else {
+                    true
+                }
+                && if move_options.right {
+                    front_cell <= right_cell
+                } else {
+                    true
+                }
+            {
+                F_MOVES
+            } else if move_options.left
+                && if move_options.forward {
+                    left_cell <= front_cell
+                } else {
+                    true
+                }
+                && if move_options.right {
+                    left_cell <= right_cell
+                } else {
+                    true
+                }
+            {
+                L_MOVES
+            } else if move_options.right
+                && if move_options.forward {
+                    right_cell <= front_cell
+                } else {
+                    true
+                }
+                && if move_options.left {
+                    right_cell <= left_cell
+                } else {
+                    true
+                }
+            {
+                R_MOVES
+            } else {
+                B_MOVES
+            }
+        }
+    }
+}
+
 pub struct CountingNavigate {
     cells: [[u8; 16]; 16],
 }
@@ -268,11 +390,17 @@ impl CountingNavigate {
     }
 }
 
+impl Default for CountingNavigate {
+    fn default() -> CountingNavigate {
+        CountingNavigate::new()
+    }
+}
+
 impl Navigate for CountingNavigate {
     type Cell = u8;
 
     fn get_cell(&self, x: i32, y: i32) -> u8 {
-        if x >= 0 && x <= 15 && y >= 0 && y <= 15 {
+        if (0..=15).contains(&x) && (0..=15).contains(&y) {
             self.cells[x as usize][y as usize]
         } else {
             255
@@ -281,36 +409,18 @@ impl Navigate for CountingNavigate {
 
     fn navigate(
         &mut self,
-        x: usize,
-        y: usize,
+        x: i32,
+        y: i32,
         d: Direction,
         move_options: MoveOptions,
     ) -> [Option<Move>; 2] {
-        let x = x as i32;
-        let y = y as i32;
-        let ux = if x < 0 {
-            0
-        } else if x > 15 {
-            15
-        } else {
-            x
-        } as usize;
-        let uy = if y < 0 {
-            0
-        } else if y > 15 {
-            15
-        } else {
-            y
-        } as usize;
+        let ux = x.clamp(0, 15) as usize;
+        let uy = y.clamp(0, 15) as usize;
 
-        if self.cells[ux][uy] < 255 {
-            self.cells[ux][uy] += 1;
-        }
-
-        let c = self.cells[ux][uy];
+        self.cells[ux][uy] = self.cells[ux][uy].saturating_add(1);
 
         // win condition
-        if x >= 7 && x <= 8 && y >= 7 && y <= 8 {
+        if (7..=8).contains(&x) && (7..=8).contains(&y) {
             [Some(Move::TurnLeft), Some(Move::TurnLeft)]
         } else {
             let left_cell = match d {
@@ -392,11 +502,17 @@ impl CountingDeadEndNavigate {
     }
 }
 
+impl Default for CountingDeadEndNavigate {
+    fn default() -> CountingDeadEndNavigate {
+        CountingDeadEndNavigate::new()
+    }
+}
+
 impl Navigate for CountingDeadEndNavigate {
     type Cell = u8;
 
     fn get_cell(&self, x: i32, y: i32) -> u8 {
-        if x >= 0 && x <= 15 && y >= 0 && y <= 15 {
+        if (0..=15).contains(&x) && (0..=15).contains(&y) {
             self.cells[x as usize][y as usize]
         } else {
             255
@@ -405,34 +521,18 @@ impl Navigate for CountingDeadEndNavigate {
 
     fn navigate(
         &mut self,
-        x: usize,
-        y: usize,
+        x: i32,
+        y: i32,
         d: Direction,
         move_options: MoveOptions,
     ) -> [Option<Move>; 2] {
-        let x = x as i32;
-        let y = y as i32;
-        let ux = if x < 0 {
-            0
-        } else if x > 15 {
-            15
-        } else {
-            x
-        } as usize;
-        let uy = if y < 0 {
-            0
-        } else if y > 15 {
-            15
-        } else {
-            y
-        } as usize;
+        let ux = x.clamp(0, 15) as usize;
+        let uy = y.clamp(0, 15) as usize;
 
-        if self.cells[ux][uy] < 255 {
-            self.cells[ux][uy] += 1;
-        }
+        self.cells[ux][uy] = self.cells[ux][uy].saturating_add(1);
 
         // win condition
-        if x >= 7 && x <= 8 && y >= 7 && y <= 8 {
+        if (7..=8).contains(&x) && (7..=8).contains(&y) {
             [Some(Move::TurnLeft), Some(Move::TurnLeft)]
         } else {
             let left_cell = match d {
@@ -532,11 +632,11 @@ impl FloodFillNavigate {
     pub fn new() -> FloodFillNavigate {
         let mut cells = [[0; 16]; 16];
 
-        for x in 0..16 {
+        for (x, column) in cells.iter_mut().enumerate() {
             let lx = if x <= 7 { 7 - x } else { x - 8 };
-            for y in 0..16 {
+            for (y, cell) in column.iter_mut().enumerate() {
                 let ly = if y <= 7 { 7 - y } else { y - 8 };
-                cells[x][y] = (lx + ly) as u8;
+                *cell = (lx + ly) as u8;
             }
         }
 
@@ -544,11 +644,17 @@ impl FloodFillNavigate {
     }
 }
 
+impl Default for FloodFillNavigate {
+    fn default() -> FloodFillNavigate {
+        FloodFillNavigate::new()
+    }
+}
+
 impl Navigate for FloodFillNavigate {
     type Cell = u8;
 
     fn get_cell(&self, x: i32, y: i32) -> u8 {
-        if x >= 0 && x <= 15 && y >= 0 && y <= 15 {
+        if (0..=15).contains(&x) && (0..=15).contains(&y) {
             self.cells[x as usize][y as usize]
         } else {
             255
@@ -557,34 +663,18 @@ impl Navigate for FloodFillNavigate {
 
     fn navigate(
         &mut self,
-        x: usize,
-        y: usize,
+        x: i32,
+        y: i32,
         d: Direction,
         move_options: MoveOptions,
     ) -> [Option<Move>; 2] {
-        let x = x as i32;
-        let y = y as i32;
-        let ux = if x < 0 {
-            0
-        } else if x > 15 {
-            15
-        } else {
-            x
-        } as usize;
-        let uy = if y < 0 {
-            0
-        } else if y > 15 {
-            15
-        } else {
-            y
-        } as usize;
+        let ux = x.clamp(0, 15) as usize;
+        let uy = y.clamp(0, 15) as usize;
 
-        if self.cells[ux][uy] < 255 {
-            self.cells[ux][uy] += 1;
-        }
+        self.cells[ux][uy] = self.cells[ux][uy].saturating_add(1);
 
         // win condition
-        if x >= 7 && x <= 8 && y >= 7 && y <= 8 {
+        if (7..=8).contains(&x) && (7..=8).contains(&y) {
             [Some(Move::TurnLeft), Some(Move::TurnLeft)]
         } else {
             let left_cell = match d {
@@ -662,11 +752,11 @@ impl FloodFillDeadEndNavigate {
     pub fn new() -> FloodFillDeadEndNavigate {
         let mut cells = [[0; 16]; 16];
 
-        for x in 0..16 {
+        for (x, column) in cells.iter_mut().enumerate() {
             let lx = if x <= 7 { 7 - x } else { x - 8 };
-            for y in 0..16 {
+            for (y, cell) in column.iter_mut().enumerate() {
                 let ly = if y <= 7 { 7 - y } else { y - 8 };
-                cells[x][y] = (lx + ly) as u8;
+                *cell = (lx + ly) as u8;
             }
         }
 
@@ -674,11 +764,17 @@ impl FloodFillDeadEndNavigate {
     }
 }
 
+impl Default for FloodFillDeadEndNavigate {
+    fn default() -> FloodFillDeadEndNavigate {
+        FloodFillDeadEndNavigate::new()
+    }
+}
+
 impl Navigate for FloodFillDeadEndNavigate {
     type Cell = u8;
 
     fn get_cell(&self, x: i32, y: i32) -> u8 {
-        if x >= 0 && x <= 15 && y >= 0 && y <= 15 {
+        if (0..=15).contains(&x) && (0..=15).contains(&y) {
             self.cells[x as usize][y as usize]
         } else {
             255
@@ -687,34 +783,18 @@ impl Navigate for FloodFillDeadEndNavigate {
 
     fn navigate(
         &mut self,
-        x: usize,
-        y: usize,
+        x: i32,
+        y: i32,
         d: Direction,
         move_options: MoveOptions,
     ) -> [Option<Move>; 2] {
-        let x = x as i32;
-        let y = y as i32;
-        let ux = if x < 0 {
-            0
-        } else if x > 15 {
-            15
-        } else {
-            x
-        } as usize;
-        let uy = if y < 0 {
-            0
-        } else if y > 15 {
-            15
-        } else {
-            y
-        } as usize;
+        let ux = x.clamp(0, 15) as usize;
+        let uy = y.clamp(0, 15) as usize;
 
-        if self.cells[ux][uy] < 255 {
-            self.cells[ux][uy] += 1;
-        }
+        self.cells[ux][uy] = self.cells[ux][uy].saturating_add(1);
 
         // win condition
-        if x >= 7 && x <= 8 && y >= 7 && y <= 8 {
+        if (7..=8).contains(&x) && (7..=8).contains(&y) {
             [Some(Move::TurnLeft), Some(Move::TurnLeft)]
         } else {
             let left_cell = match d {
@@ -814,11 +894,11 @@ impl FloodFillSquareNavigate {
     pub fn new() -> FloodFillSquareNavigate {
         let mut cells = [[0; 16]; 16];
 
-        for x in 0..16 {
+        for (x, column) in cells.iter_mut().enumerate() {
             let lx = if x <= 7 { 7 - x } else { x - 8 };
-            for y in 0..16 {
+            for (y, cell) in column.iter_mut().enumerate() {
                 let ly = if y <= 7 { 7 - y } else { y - 8 };
-                cells[x][y] = usize::max(lx, ly) as u8;
+                *cell = usize::max(lx, ly) as u8;
             }
         }
 
@@ -826,11 +906,17 @@ impl FloodFillSquareNavigate {
     }
 }
 
+impl Default for FloodFillSquareNavigate {
+    fn default() -> FloodFillSquareNavigate {
+        FloodFillSquareNavigate::new()
+    }
+}
+
 impl Navigate for FloodFillSquareNavigate {
     type Cell = u8;
 
     fn get_cell(&self, x: i32, y: i32) -> u8 {
-        if x >= 0 && x <= 15 && y >= 0 && y <= 15 {
+        if (0..=15).contains(&x) && (0..=15).contains(&y) {
             self.cells[x as usize][y as usize]
         } else {
             255
@@ -839,34 +925,18 @@ impl Navigate for FloodFillSquareNavigate {
 
     fn navigate(
         &mut self,
-        x: usize,
-        y: usize,
+        x: i32,
+        y: i32,
         d: Direction,
         move_options: MoveOptions,
     ) -> [Option<Move>; 2] {
-        let x = x as i32;
-        let y = y as i32;
-        let ux = if x < 0 {
-            0
-        } else if x > 15 {
-            15
-        } else {
-            x
-        } as usize;
-        let uy = if y < 0 {
-            0
-        } else if y > 15 {
-            15
-        } else {
-            y
-        } as usize;
+        let ux = x.clamp(0, 15) as usize;
+        let uy = y.clamp(0, 15) as usize;
 
-        if self.cells[ux][uy] < 255 {
-            self.cells[ux][uy] += 1;
-        }
+        self.cells[ux][uy] = self.cells[ux][uy].saturating_add(1);
 
         // win condition
-        if x >= 7 && x <= 8 && y >= 7 && y <= 8 {
+        if (7..=8).contains(&x) && (7..=8).contains(&y) {
             [Some(Move::TurnLeft), Some(Move::TurnLeft)]
         } else {
             let left_cell = match d {
@@ -944,11 +1014,11 @@ impl FloodFillSquareDeadEndNavigate {
     pub fn new() -> FloodFillSquareDeadEndNavigate {
         let mut cells = [[0; 16]; 16];
 
-        for x in 0..16 {
+        for (x, column) in cells.iter_mut().enumerate() {
             let lx = if x <= 7 { 7 - x } else { x - 8 };
-            for y in 0..16 {
+            for (y, cell) in column.iter_mut().enumerate() {
                 let ly = if y <= 7 { 7 - y } else { y - 8 };
-                cells[x][y] = usize::max(lx, ly) as u8;
+                *cell = usize::max(lx, ly) as u8;
             }
         }
 
@@ -956,11 +1026,17 @@ impl FloodFillSquareDeadEndNavigate {
     }
 }
 
+impl Default for FloodFillSquareDeadEndNavigate {
+    fn default() -> FloodFillSquareDeadEndNavigate {
+        FloodFillSquareDeadEndNavigate::new()
+    }
+}
+
 impl Navigate for FloodFillSquareDeadEndNavigate {
     type Cell = u8;
 
     fn get_cell(&self, x: i32, y: i32) -> u8 {
-        if x >= 0 && x <= 15 && y >= 0 && y <= 15 {
+        if (0..=15).contains(&x) && (0..=15).contains(&y) {
             self.cells[x as usize][y as usize]
         } else {
             255
@@ -969,34 +1045,18 @@ impl Navigate for FloodFillSquareDeadEndNavigate {
 
     fn navigate(
         &mut self,
-        x: usize,
-        y: usize,
+        x: i32,
+        y: i32,
         d: Direction,
         move_options: MoveOptions,
     ) -> [Option<Move>; 2] {
-        let x = x as i32;
-        let y = y as i32;
-        let ux = if x < 0 {
-            0
-        } else if x > 15 {
-            15
-        } else {
-            x
-        } as usize;
-        let uy = if y < 0 {
-            0
-        } else if y > 15 {
-            15
-        } else {
-            y
-        } as usize;
+        let ux = x.clamp(0, 15) as usize;
+        let uy = y.clamp(0, 15) as usize;
 
-        if self.cells[ux][uy] < 255 {
-            self.cells[ux][uy] += 1;
-        }
+        self.cells[ux][uy] = self.cells[ux][uy].saturating_add(1);
 
         // win condition
-        if x >= 7 && x <= 8 && y >= 7 && y <= 8 {
+        if (7..=8).contains(&x) && (7..=8).contains(&y) {
             [Some(Move::TurnLeft), Some(Move::TurnLeft)]
         } else {
             let left_cell = match d {
@@ -1105,11 +1165,17 @@ impl TwelvePartitionNavigate {
     }
 }
 
+impl Default for TwelvePartitionNavigate {
+    fn default() -> TwelvePartitionNavigate {
+        TwelvePartitionNavigate::new()
+    }
+}
+
 impl Navigate for TwelvePartitionNavigate {
     type Cell = u8;
 
     fn get_cell(&self, x: i32, y: i32) -> u8 {
-        if x >= 0 && x <= 15 && y >= 0 && y <= 15 {
+        if (0..=15).contains(&x) && (0..=15).contains(&y) {
             self.cells[x as usize][y as usize]
         } else {
             255
@@ -1118,34 +1184,18 @@ impl Navigate for TwelvePartitionNavigate {
 
     fn navigate(
         &mut self,
-        x: usize,
-        y: usize,
+        x: i32,
+        y: i32,
         d: Direction,
         move_options: MoveOptions,
     ) -> [Option<Move>; 2] {
-        let x = x as i32;
-        let y = y as i32;
-        let ux = if x < 0 {
-            0
-        } else if x > 15 {
-            15
-        } else {
-            x
-        } as usize;
-        let uy = if y < 0 {
-            0
-        } else if y > 15 {
-            15
-        } else {
-            y
-        } as usize;
+        let ux = x.clamp(0, 15) as usize;
+        let uy = y.clamp(0, 15) as usize;
 
-        if self.cells[ux][uy] < 255 {
-            self.cells[ux][uy] += 1;
-        }
+        self.cells[ux][uy] = self.cells[ux][uy].saturating_add(1);
 
         // win condition
-        if x >= 7 && x <= 8 && y >= 7 && y <= 8 {
+        if (7..=8).contains(&x) && (7..=8).contains(&y) {
             [Some(Move::TurnLeft), Some(Move::TurnLeft)]
         } else {
             let left_cell = match d {
@@ -1276,7 +1326,7 @@ impl Navigate for TwelvePartitionNavigate {
                     Direction::West => RIGHT,
                 },
 
-                (x, y) if x >= 7 && x <= 8 && y >= 7 && y <= 8 => [
+                (x, y) if (7..=8).contains(&x) && (7..=8).contains(&y) => [
                     [Some(Move::TurnAround), Some(Move::TurnAround)],
                     [Some(Move::TurnAround), Some(Move::TurnAround)],
                     [Some(Move::TurnAround), Some(Move::TurnAround)],
@@ -1289,10 +1339,10 @@ impl Navigate for TwelvePartitionNavigate {
 
             // filter by walls
             let possibilities_iter =
-                possibilities.iter().filter(|&moves| match moves {
-                    &F_MOVES => move_options.forward,
-                    &L_MOVES => move_options.left,
-                    &R_MOVES => move_options.right,
+                possibilities.iter().filter(|&moves| match *moves {
+                    F_MOVES => move_options.forward,
+                    L_MOVES => move_options.left,
+                    R_MOVES => move_options.right,
                     _ => true,
                 });
 
@@ -1327,3 +1377,44 @@ impl Navigate for TwelvePartitionNavigate {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_OPEN: MoveOptions = MoveOptions {
+        forward: true,
+        left: true,
+        right: true,
+    };
+
+    #[test]
+    fn direction_turn_around() {
+        for &d in &[
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ] {
+            assert_eq!(d.turn_around(), d.turn_right().turn_right());
+            assert_eq!(d.turn_around(), d.turn_left().turn_left());
+            assert_eq!(d.turn_left().turn_right(), d);
+        }
+    }
+
+    #[test]
+    fn flood_fill_heads_for_center() {
+        let mut nav = FloodFillNavigate::new();
+        assert_eq!(nav.navigate(0, 0, Direction::North, ALL_OPEN), F_MOVES);
+        assert_eq!(nav.navigate(0, 0, Direction::West, ALL_OPEN), R_MOVES);
+    }
+
+    #[test]
+    fn counting_counts_visits() {
+        let mut nav = CountingNavigate::new();
+        nav.navigate(3, 4, Direction::North, ALL_OPEN);
+        nav.navigate(3, 4, Direction::North, ALL_OPEN);
+        assert_eq!(nav.get_cell(3, 4), 2);
+        assert_eq!(nav.get_cell(-1, 4), 255);
+    }
+}
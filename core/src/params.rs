@@ -0,0 +1,399 @@
+use core::fmt::Write;
+
+/**
+ *  A type that a parameter can have
+ *
+ *  Every value goes through an f64 on its way in and out of the registry,
+ *  which holds all of these exactly.
+ */
+pub trait ParamType: Copy {
+    const INTEGER: bool;
+
+    fn to_f64(self) -> f64;
+    fn from_f64(v: f64) -> Self;
+}
+
+impl ParamType for f64 {
+    const INTEGER: bool = false;
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f64(v: f64) -> f64 {
+        v
+    }
+}
+
+impl ParamType for f32 {
+    const INTEGER: bool = false;
+
+    fn to_f64(self) -> f64 {
+        f64::from(self)
+    }
+
+    fn from_f64(v: f64) -> f32 {
+        v as f32
+    }
+}
+
+impl ParamType for u32 {
+    const INTEGER: bool = true;
+
+    fn to_f64(self) -> f64 {
+        f64::from(self)
+    }
+
+    fn from_f64(v: f64) -> u32 {
+        v as u32
+    }
+}
+
+impl ParamType for i32 {
+    const INTEGER: bool = true;
+
+    fn to_f64(self) -> f64 {
+        f64::from(self)
+    }
+
+    fn from_f64(v: f64) -> i32 {
+        v as i32
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParamError {
+    UnknownName,
+    InvalidValue,
+    NotFinite,
+    NotInteger,
+    OutOfRange,
+}
+
+/**
+ *  One field of a config struct
+ *
+ *  These are made by the `params!` macro, which is the only place a field
+ *  needs to be described.
+ */
+pub struct Param<T> {
+    pub name: &'static str,
+    pub units: &'static str,
+    pub min: f64,
+    pub max: f64,
+    pub integer: bool,
+    pub help: &'static str,
+    pub get: fn(&T) -> f64,
+    pub set: fn(&mut T, f64),
+}
+
+impl<T> Param<T> {
+    /// Check a value against the type and range of the parameter
+    pub fn validate(&self, v: f64) -> Result<f64, ParamError> {
+        // NaN gets past every comparison, and inf past infinite bounds
+        if !v.is_finite() {
+            Err(ParamError::NotFinite)
+        } else if self.integer && v as i64 as f64 != v {
+            Err(ParamError::NotInteger)
+        } else if v < self.min || v > self.max {
+            Err(ParamError::OutOfRange)
+        } else {
+            Ok(v)
+        }
+    }
+
+    pub fn get(&self, config: &T) -> f64 {
+        (self.get)(config)
+    }
+
+    pub fn set(&self, config: &mut T, v: f64) -> Result<(), ParamError> {
+        let v = self.validate(v)?;
+        (self.set)(config, v);
+        Ok(())
+    }
+
+    pub fn parse_and_set(
+        &self,
+        config: &mut T,
+        s: &str,
+    ) -> Result<(), ParamError> {
+        let v = s.parse().map_err(|_| ParamError::InvalidValue)?;
+        self.set(config, v)
+    }
+}
+
+pub fn find<'p, T>(
+    params: &'p [Param<T>],
+    name: &str,
+) -> Result<&'p Param<T>, ParamError> {
+    params
+        .iter()
+        .find(|p| p.name == name)
+        .ok_or(ParamError::UnknownName)
+}
+
+/**
+ *  Describe the fields of a config struct
+ *
+ *  This starts with `static NAME: Config;` for the table to make, followed
+ *  by a `field: type, "units", min, max, "help";` line per field. Nested
+ *  fields like `mouse.wheelbase` work too. The table of `Param`s is what
+ *  `handle_command` gets, sets and lists.
+ */
+#[macro_export]
+macro_rules! params {
+    (
+        $vis:vis static $table:ident: $config:ty;
+        $(
+            $field:ident $(. $sub:ident)* : $type:ty,
+            $units:expr, $min:expr, $max:expr, $help:expr;
+        )*
+    ) => {
+        $vis static $table: &[$crate::params::Param<$config>] = &[
+            $(
+                $crate::params::Param {
+                    name: concat!(
+                        stringify!($field) $(, ".", stringify!($sub))*
+                    ),
+                    units: $units,
+                    min: $min,
+                    max: $max,
+                    integer:
+                        <$type as $crate::params::ParamType>::INTEGER,
+                    help: $help,
+                    get: |c: &$config| {
+                        $crate::params::ParamType::to_f64(
+                            c.$field $(. $sub)*
+                        )
+                    },
+                    set: |c: &mut $config, v: f64| {
+                        c.$field $(. $sub)* =
+                            <$type as $crate::params::ParamType>::from_f64(v);
+                    },
+                },
+            )*
+        ];
+    };
+}
+
+fn write_param<T, W: Write>(w: &mut W, param: &Param<T>, config: &T) {
+    writeln!(w, "{}: {} {}", param.name, param.get(config), param.units).ok();
+}
+
+fn write_error<T, W: Write>(
+    w: &mut W,
+    keyword: &str,
+    param: Option<&Param<T>>,
+    e: ParamError,
+) {
+    match (e, param) {
+        (ParamError::OutOfRange, Some(p)) => writeln!(
+            w,
+            "{}: {} must be between {} and {}",
+            keyword, p.name, p.min, p.max
+        ),
+        (ParamError::NotFinite, Some(p)) => {
+            writeln!(w, "{}: {} must be a finite number", keyword, p.name)
+        }
+        (ParamError::NotInteger, Some(p)) => {
+            writeln!(w, "{}: {} must be a whole number", keyword, p.name)
+        }
+        (e, _) => writeln!(w, "{}: {:?}", keyword, e),
+    }
+    .ok();
+}
+
+/**
+ *  The commands for a config struct
+ *
+ *  - `list` (or nothing) prints every parameter
+ *  - `get <name>` or `<name>` prints one
+ *  - `set <name> <value>` or `<name> <value>` changes one
+ *  - `help <name>` prints the units, range and description
+ */
+pub fn handle_command<'a, T, W: Write, I: Iterator<Item = &'a str>>(
+    keyword: &str,
+    params: &[Param<T>],
+    config: &mut T,
+    w: &mut W,
+    mut args: I,
+) {
+    let (name, value) = match args.next() {
+        None | Some("list") => {
+            for param in params {
+                write_param(w, param, config);
+            }
+            return;
+        }
+        Some("help") => {
+            match args.next().map(|name| find(params, name)) {
+                Some(Ok(p)) => {
+                    writeln!(
+                        w,
+                        "{} ({}, {} to {}): {}",
+                        p.name, p.units, p.min, p.max, p.help
+                    )
+                    .ok();
+                }
+                Some(Err(e)) => write_error::<T, W>(w, keyword, None, e),
+                None => {
+                    writeln!(w, "{}: help <name>", keyword).ok();
+                }
+            }
+            return;
+        }
+        Some("get") => (args.next(), None),
+        Some("set") => {
+            let name = args.next();
+            match args.next() {
+                Some(value) => (name, Some(value)),
+                None => {
+                    writeln!(w, "{}: set <name> <value>", keyword).ok();
+                    return;
+                }
+            }
+        }
+        Some(name) => (Some(name), args.next()),
+    };
+
+    let param = match name.map(|name| find(params, name)) {
+        Some(Ok(param)) => param,
+        Some(Err(e)) => {
+            write_error::<T, W>(w, keyword, None, e);
+            return;
+        }
+        None => {
+            writeln!(w, "{}: missing name", keyword).ok();
+            return;
+        }
+    };
+
+    if let Some(value) = value {
+        if let Err(e) = param.parse_and_set(config, value) {
+            write_error(w, keyword, Some(param), e);
+            return;
+        }
+    }
+
+    write_param(w, param, config);
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt;
+
+    use super::{find, handle_command, ParamError};
+
+    struct Sink;
+
+    impl fmt::Write for Sink {
+        fn write_str(&mut self, _s: &str) -> fmt::Result {
+            Ok(())
+        }
+    }
+
+    struct Inner {
+        b: f32,
+    }
+
+    struct Test {
+        a: f64,
+        n: u32,
+        inner: Inner,
+        free: f64,
+    }
+
+    params! {
+        static TEST_PARAMS: Test;
+        a: f64, "mm", -1.0, 1.0, "a float";
+        n: u32, "ms", 0.0, 100.0, "an integer";
+        inner.b: f32, "", 0.0, 10.0, "a nested float";
+        free: f64, "", f64::NEG_INFINITY, f64::INFINITY, "an unbounded float";
+    }
+
+    fn test() -> Test {
+        Test {
+            a: 0.5,
+            n: 10,
+            inner: Inner { b: 2.0 },
+            free: 0.0,
+        }
+    }
+
+    #[test]
+    fn params_names() {
+        let names = TEST_PARAMS.iter().map(|p| p.name);
+        assert!(names.eq(["a", "n", "inner.b", "free"].iter().cloned()));
+    }
+
+    #[test]
+    fn params_get_set() {
+        let mut t = test();
+        let b = find(TEST_PARAMS, "inner.b").unwrap();
+        assert_eq!(b.get(&t), 2.0);
+        assert_eq!(b.parse_and_set(&mut t, "3.5"), Ok(()));
+        assert_eq!(t.inner.b, 3.5);
+
+        let n = find(TEST_PARAMS, "n").unwrap();
+        assert_eq!(n.set(&mut t, 42.0), Ok(()));
+        assert_eq!(t.n, 42);
+    }
+
+    #[test]
+    fn params_validation() {
+        let mut t = test();
+        let a = find(TEST_PARAMS, "a").unwrap();
+        assert_eq!(a.set(&mut t, 2.0), Err(ParamError::OutOfRange));
+        assert_eq!(
+            a.parse_and_set(&mut t, "abc"),
+            Err(ParamError::InvalidValue)
+        );
+        assert_eq!(t.a, 0.5);
+
+        let n = find(TEST_PARAMS, "n").unwrap();
+        assert_eq!(n.set(&mut t, 1.5), Err(ParamError::NotInteger));
+        assert_eq!(t.n, 10);
+
+        assert!(find(TEST_PARAMS, "c").is_err());
+    }
+
+    #[test]
+    fn params_not_finite() {
+        let mut t = test();
+        let a = find(TEST_PARAMS, "a").unwrap();
+        assert_eq!(a.set(&mut t, f64::NAN), Err(ParamError::NotFinite));
+        assert_eq!(a.parse_and_set(&mut t, "NaN"), Err(ParamError::NotFinite));
+        assert_eq!(t.a, 0.5);
+
+        let n = find(TEST_PARAMS, "n").unwrap();
+        assert_eq!(n.set(&mut t, f64::NAN), Err(ParamError::NotFinite));
+        assert_eq!(t.n, 10);
+
+        let free = find(TEST_PARAMS, "free").unwrap();
+        assert_eq!(free.set(&mut t, 1e300), Ok(()));
+        assert_eq!(free.set(&mut t, f64::INFINITY), Err(ParamError::NotFinite));
+        assert_eq!(
+            free.parse_and_set(&mut t, "-inf"),
+            Err(ParamError::NotFinite)
+        );
+        assert_eq!(t.free, 1e300);
+    }
+
+    #[test]
+    fn params_commands() {
+        let mut t = test();
+        let commands = ["set n 5", "a -0.25", "inner.b 11", "set a", "get a"];
+        for command in commands.iter() {
+            handle_command(
+                "test",
+                TEST_PARAMS,
+                &mut t,
+                &mut Sink,
+                command.split_whitespace(),
+            );
+        }
+        assert_eq!(t.n, 5);
+        assert_eq!(t.a, -0.25);
+        assert_eq!(t.inner.b, 2.0);
+    }
+}
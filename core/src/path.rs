@@ -0,0 +1,380 @@
+use core::f32::consts::FRAC_PI_2;
+use core::f32::consts::FRAC_PI_4;
+
+// The tests link std, whose own methods take over from these
+#[cfg(not(test))]
+use micromath::F32Ext;
+
+use arrayvec::{ArrayVec, CapacityError};
+
+use crate::mouse::MouseConfig;
+use crate::pid::Controller;
+
+fn psign(x: f32) -> f32 {
+    if x > 0.0 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/**
+ *  A piece of a path, in mm
+ *
+ *  Each segment has its own coordinate frame, with the start of the segment
+ *  at the origin, x pointing forward and y pointing to the right. Arcs with a
+ *  positive radius turn right, arcs with a negative radius turn left.
+ */
+#[derive(Copy, Clone, Debug)]
+pub enum Segment {
+    Line(f32),
+    Arc45(f32),
+    Arc90(f32),
+}
+
+impl Segment {
+    pub fn total_distance(&self) -> f32 {
+        match *self {
+            Segment::Line(d) => d,
+            Segment::Arc45(r) => FRAC_PI_4 * r.abs(),
+            Segment::Arc90(r) => FRAC_PI_2 * r.abs(),
+        }
+    }
+
+    /// How much the heading changes over the segment, positive is clockwise
+    pub fn heading_change(&self) -> f32 {
+        match *self {
+            Segment::Line(_) => 0.0,
+            Segment::Arc45(r) => psign(r) * FRAC_PI_4,
+            Segment::Arc90(r) => psign(r) * FRAC_PI_2,
+        }
+    }
+
+    /// 1/radius of the segment, positive when turning right
+    pub fn curvature(&self) -> f32 {
+        match *self {
+            Segment::Line(_) => 0.0,
+            Segment::Arc45(r) => 1.0 / r,
+            Segment::Arc90(r) => 1.0 / r,
+        }
+    }
+
+    pub fn distance_along(&self, x: f32, y: f32) -> f32 {
+        match *self {
+            Segment::Line(_) => x,
+            Segment::Arc45(r) | Segment::Arc90(r) => {
+                x.atan2(psign(r) * (r - y)) * r.abs()
+            }
+        }
+    }
+
+    /// The cross-track error, positive when to the right of the segment
+    pub fn distance_from(&self, x: f32, y: f32) -> f32 {
+        match *self {
+            Segment::Line(_d) => y,
+            Segment::Arc45(r) | Segment::Arc90(r) => {
+                psign(r) * (r.abs() - (x * x + (r - y) * (r - y)).sqrt())
+            }
+        }
+    }
+
+    /// The point and heading the segment ends at
+    pub fn end(&self) -> (f32, f32, f32) {
+        let heading = self.heading_change();
+        match *self {
+            Segment::Line(d) => (d, 0.0, 0.0),
+            Segment::Arc45(r) | Segment::Arc90(r) => (
+                r.abs() * heading.abs().sin(),
+                r * (1.0 - heading.cos()),
+                heading,
+            ),
+        }
+    }
+
+    /// Transform coordinates in this segment into the frame of the next one
+    pub fn offset_coords(&self, x: f32, y: f32) -> (f32, f32) {
+        let (end_x, end_y, heading) = self.end();
+        let dx = x - end_x;
+        let dy = y - end_y;
+        let (sin, cos) = (heading.sin(), heading.cos());
+        (dx * cos + dy * sin, dy * cos - dx * sin)
+    }
+}
+
+pub const PATH_BUF_LEN: usize = 64;
+
+/**
+ *  Follows a queue of segments using odometry
+ *
+ *  Wheel positions are given in mm. The position is tracked in the frame of
+ *  the current segment, and a PID on the cross-track error gives the spin
+ *  correction on top of the curvature of the segment.
+ */
+pub struct Path<C: Controller> {
+    pub pid: C,
+    pub segment_buffer: ArrayVec<[Segment; PATH_BUF_LEN]>,
+    pub left: f32,
+    pub right: f32,
+    pub x: f32,
+    pub y: f32,
+    pub dir: f32,
+    pub time: u32,
+}
+
+impl<C: Controller> Path<C> {
+    /// Start following from the wheel positions `left` and `right`
+    pub fn new(mut pid: C, now: u32, left: f32, right: f32) -> Path<C> {
+        pid.set_target(0.0);
+
+        Path {
+            pid,
+            segment_buffer: ArrayVec::new(),
+            left,
+            right,
+            x: 0.0,
+            y: 0.0,
+            dir: 0.0,
+            time: now,
+        }
+    }
+
+    /**
+     *  Add segments to the end of the path
+     *
+     *  Returns the space left in the buffer. If the segments don't all fit,
+     *  none of them are added.
+     */
+    pub fn add_segments(
+        &mut self,
+        segments: &[Segment],
+    ) -> Result<usize, CapacityError> {
+        if segments.len() > PATH_BUF_LEN - self.segment_buffer.len() {
+            return Err(CapacityError::new(()));
+        }
+
+        self.segment_buffer.extend(segments.iter().cloned());
+
+        Ok(PATH_BUF_LEN - self.segment_buffer.len())
+    }
+
+    /**
+     *  Update the odometry and the cross-track PID
+     *
+     *  Returns the curvature to drive at (1/mm, positive to the right) and
+     *  the number of segments that are left. When there are no segments left,
+     *  the curvature is 0.
+     */
+    pub fn update(
+        &mut self,
+        mouse: &MouseConfig,
+        now: u32,
+        left: f32,
+        right: f32,
+    ) -> (f32, usize) {
+        let delta_time = now - self.time;
+
+        let delta_left = left - self.left;
+        let delta_right = right - self.right;
+
+        let delta_linear = (delta_left + delta_right) / 2.0;
+        let delta_angular = mouse.mm_to_rads((delta_left - delta_right) / 2.0);
+
+        let mid_dir = self.dir + delta_angular / 2.0;
+
+        self.x += delta_linear * mid_dir.cos();
+        self.y += delta_linear * mid_dir.sin();
+        self.dir += delta_angular;
+
+        self.left = left;
+        self.right = right;
+        self.time = now;
+
+        if let Some(segment) = self.segment_buffer.first().cloned() {
+            if segment.distance_along(self.x, self.y)
+                >= segment.total_distance()
+            {
+                let (x, y) = segment.offset_coords(self.x, self.y);
+                self.x = x;
+                self.y = y;
+                self.dir -= segment.heading_change();
+                self.segment_buffer.remove(0);
+                self.pid.reset();
+            }
+        }
+
+        if let Some(segment) = self.segment_buffer.first() {
+            let offset = segment.distance_from(self.x, self.y);
+            let correction =
+                self.pid.update(offset as f64, delta_time as f64) as f32;
+            (segment.curvature() + correction, self.segment_buffer.len())
+        } else {
+            (0.0, 0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f32::consts::PI;
+
+    use super::{Path, Segment, PATH_BUF_LEN};
+    use crate::config::BotConfig;
+    use crate::pid::Proportional;
+
+    /// The geometry is checked with std's trig, not micromath's 0.002
+    const MAX_DELTA: f32 = 1e-6;
+
+    #[test]
+    fn segment_line_total_distance() {
+        let line = Segment::Line(10.0);
+        assert_eq!(line.total_distance(), 10.0);
+    }
+    #[test]
+    fn segment_line_distance_along() {
+        let line = Segment::Line(10.0);
+        assert_eq!(line.distance_along(7.0, 2.0), 7.0);
+    }
+    #[test]
+    fn segment_line_distance_from() {
+        let line = Segment::Line(10.0);
+        assert_eq!(line.distance_from(7.0, 2.0), 2.0);
+    }
+    #[test]
+    fn segment_line_offset_coords() {
+        let line = Segment::Line(10.0);
+        assert_eq!(line.offset_coords(11.0, 2.0), (1.0, 2.0));
+    }
+
+    #[test]
+    fn segment_arc45_total() {
+        let arc = Segment::Arc45(10.0);
+        assert_eq!(arc.total_distance(), 10.0 * PI * 2.0 / 8.0);
+    }
+
+    #[test]
+    fn segment_arc45_distance_along() {
+        let arc = Segment::Arc45(10.0);
+        assert_close(
+            arc.distance_along(7.778_174_4, 2.221_825_4),
+            arc.total_distance(),
+        );
+    }
+
+    #[test]
+    fn segment_arc45_distance_from() {
+        let arc = Segment::Arc45(10.0);
+        assert_close(arc.distance_from(7.778_174_4, 2.221_825_4), -1.0);
+    }
+
+    #[test]
+    fn segment_arc_45_offset_coords() {
+        let arc = Segment::Arc45(10.0);
+        assert_close2(arc.offset_coords(7.778_174_4, 2.221_825_4), (0.0, -1.0));
+    }
+
+    #[test]
+    fn segment_arc90_total() {
+        let arc = Segment::Arc90(10.0);
+        assert_close(arc.total_distance(), 10.0 * PI * 2.0 / 4.0);
+    }
+
+    #[test]
+    fn segment_arc90_distance_along() {
+        let arc = Segment::Arc90(10.0);
+        assert_close(arc.distance_from(7.778_174_4, 2.221_825_4), -1.0);
+    }
+
+    #[test]
+    fn segment_arc90_offset_coords() {
+        let arc = Segment::Arc90(10.0);
+        assert_close2(arc.offset_coords(11.0, 10.0), (0.0, -1.0));
+    }
+
+    #[test]
+    fn segment_arc90_left_end() {
+        let arc = Segment::Arc90(-10.0);
+        let (x, y, heading) = arc.end();
+        assert_close2((x, y), (10.0, -10.0));
+        assert_close(heading, -PI / 2.0);
+        assert_close(arc.distance_along(x, y), arc.total_distance());
+    }
+
+    #[test]
+    fn segment_arc90_left_offset_coords() {
+        let arc = Segment::Arc90(-10.0);
+        assert_close2(arc.offset_coords(11.0, -11.0), (1.0, 1.0));
+    }
+
+    fn path(gain: f64) -> Path<Proportional> {
+        Path::new(Proportional::new(gain), 0, 0.0, 0.0)
+    }
+
+    #[test]
+    fn path_moves_on_at_the_end_of_a_segment() {
+        let mouse = BotConfig::default().mouse;
+        let mut path = path(0.0);
+        path.add_segments(&[Segment::Line(100.0), Segment::Arc90(50.0)])
+            .unwrap();
+
+        assert_eq!(path.update(&mouse, 10, 50.0, 50.0), (0.0, 2));
+        assert_eq!(path.update(&mouse, 20, 100.0, 100.0), (0.02, 1));
+        assert_close(path.x, 0.0);
+    }
+
+    #[test]
+    fn path_steers_back_onto_the_segment() {
+        let mouse = BotConfig::default().mouse;
+        let mut path = path(0.01);
+        path.add_segments(&[Segment::Line(100.0)]).unwrap();
+
+        // 2 mm to the right of the line wants turning left
+        path.y = 2.0;
+        let (curvature, remaining) = path.update(&mouse, 10, 0.0, 0.0);
+        assert_close(curvature, -0.02);
+        assert_eq!(remaining, 1);
+    }
+
+    #[test]
+    fn path_takes_all_the_segments_or_none() {
+        let mut path = path(0.0);
+        let segments = [Segment::Line(10.0); PATH_BUF_LEN - 1];
+
+        assert_eq!(path.add_segments(&segments), Ok(1));
+        assert!(path
+            .add_segments(&[Segment::Line(10.0), Segment::Line(10.0)])
+            .is_err());
+        assert_eq!(path.segment_buffer.len(), PATH_BUF_LEN - 1);
+        assert_eq!(path.add_segments(&[Segment::Line(10.0)]), Ok(0));
+    }
+
+    #[test]
+    fn path_is_done_without_segments() {
+        let mouse = BotConfig::default().mouse;
+        let mut path = path(1.0);
+
+        assert_eq!(path.update(&mouse, 10, 10.0, 10.0), (0.0, 0));
+    }
+
+    fn assert_close2(left: (f32, f32), right: (f32, f32)) {
+        let delta0 = (left.0 - right.0).abs();
+        let delta1 = (left.1 - right.1).abs();
+        assert!(
+            delta0 <= MAX_DELTA && delta1 <= MAX_DELTA,
+            "\nleft: {:?}\nright: {:?}\ndelta: {:?}\n",
+            left,
+            right,
+            (delta0, delta1),
+        );
+    }
+
+    fn assert_close(left: f32, right: f32) {
+        let delta = (left - right).abs();
+        assert!(
+            delta <= MAX_DELTA,
+            "\nleft: {}\nright: {}\ndelta: {}\n",
+            left,
+            right,
+            delta
+        );
+    }
+}
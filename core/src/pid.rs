@@ -0,0 +1,48 @@
+//! What the controllers in here need of a PID
+//!
+//! This is the `Controller` of the `pid_control` crate the firmware uses. The
+//! tests drive the same logic with a plain proportional controller, which is
+//! easier to work the numbers out for.
+
+pub trait Controller {
+    /// The output for the measured `value`, `delta_t` ms after the last one
+    fn update(&mut self, value: f64, delta_t: f64) -> f64;
+
+    fn set_target(&mut self, target: f64);
+
+    fn target(&self) -> f64;
+
+    /// Forget the integral and derivative history
+    fn reset(&mut self);
+}
+
+/// Just the proportional part of a PID, for the tests
+#[cfg(test)]
+pub(crate) struct Proportional {
+    pub gain: f64,
+    target: f64,
+}
+
+#[cfg(test)]
+impl Proportional {
+    pub fn new(gain: f64) -> Proportional {
+        Proportional { gain, target: 0.0 }
+    }
+}
+
+#[cfg(test)]
+impl Controller for Proportional {
+    fn update(&mut self, value: f64, _delta_t: f64) -> f64 {
+        self.gain * (self.target - value)
+    }
+
+    fn set_target(&mut self, target: f64) {
+        self.target = target;
+    }
+
+    fn target(&self) -> f64 {
+        self.target
+    }
+
+    fn reset(&mut self) {}
+}
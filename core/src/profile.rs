@@ -0,0 +1,288 @@
+/// Square root by Newton's method, since there is no f64::sqrt in core
+fn sqrt(x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+
+    let mut guess = if x > 1.0 { x / 2.0 } else { 1.0 };
+
+    for _ in 0..32 {
+        let next = (guess + x / guess) / 2.0;
+        if next == guess {
+            break;
+        }
+        guess = next;
+    }
+
+    guess
+}
+
+/**
+ *  A trapezoidal motion profile
+ *
+ *  Accelerates from the start velocity up to the max velocity, cruises, and
+ *  then decelerates so that it reaches the end velocity right at the target
+ *  distance. If the distance is too short to reach the max velocity, the
+ *  profile turns into a triangle. If it is too short to even reach the end
+ *  velocity, the end velocity is changed to the closest one that can be
+ *  reached.
+ *
+ *  The units are up to the caller, the bot uses ticks and ms.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Profile {
+    direction: f64,
+    distance: f64,
+    start_velocity: f64,
+    cruise_velocity: f64,
+    end_velocity: f64,
+    acceleration: f64,
+    accel_time: f64,
+    cruise_time: f64,
+    decel_time: f64,
+}
+
+impl Profile {
+    pub fn new(
+        distance: f64,
+        start_velocity: f64,
+        end_velocity: f64,
+        max_velocity: f64,
+        acceleration: f64,
+    ) -> Profile {
+        let direction = if distance < 0.0 { -1.0 } else { 1.0 };
+        let distance = distance * direction;
+        let max_velocity = abs(max_velocity);
+        let acceleration = abs(acceleration);
+
+        // Work with velocities along the direction of travel. Starting out
+        // backwards is treated like starting from a stop.
+        let start_velocity =
+            clamp(start_velocity * direction, 0.0, max_velocity);
+        let end_velocity = clamp(end_velocity * direction, 0.0, max_velocity);
+
+        let end_velocity = if end_velocity > start_velocity {
+            let reachable = sqrt(
+                start_velocity * start_velocity + 2.0 * acceleration * distance,
+            );
+            if end_velocity < reachable {
+                end_velocity
+            } else {
+                reachable
+            }
+        } else {
+            let reachable = sqrt(
+                start_velocity * start_velocity - 2.0 * acceleration * distance,
+            );
+            if end_velocity > reachable {
+                end_velocity
+            } else {
+                reachable
+            }
+        };
+
+        let peak_velocity = sqrt(
+            (2.0 * acceleration * distance
+                + start_velocity * start_velocity
+                + end_velocity * end_velocity)
+                / 2.0,
+        );
+
+        let cruise_velocity = if peak_velocity < max_velocity {
+            peak_velocity
+        } else {
+            max_velocity
+        };
+
+        let (accel_time, accel_distance, decel_time, decel_distance) =
+            if acceleration > 0.0 {
+                (
+                    (cruise_velocity - start_velocity) / acceleration,
+                    (cruise_velocity * cruise_velocity
+                        - start_velocity * start_velocity)
+                        / (2.0 * acceleration),
+                    (cruise_velocity - end_velocity) / acceleration,
+                    (cruise_velocity * cruise_velocity
+                        - end_velocity * end_velocity)
+                        / (2.0 * acceleration),
+                )
+            } else {
+                (0.0, 0.0, 0.0, 0.0)
+            };
+
+        let cruise_distance = distance - accel_distance - decel_distance;
+
+        let cruise_time = if cruise_velocity > 0.0 && cruise_distance > 0.0 {
+            cruise_distance / cruise_velocity
+        } else {
+            0.0
+        };
+
+        Profile {
+            direction,
+            distance,
+            start_velocity,
+            cruise_velocity,
+            end_velocity,
+            acceleration,
+            accel_time,
+            cruise_time,
+            decel_time,
+        }
+    }
+
+    /// The total time the profile takes
+    pub fn duration(&self) -> f64 {
+        self.accel_time + self.cruise_time + self.decel_time
+    }
+
+    pub fn is_done(&self, time: f64) -> bool {
+        time >= self.duration()
+    }
+
+    pub fn distance(&self) -> f64 {
+        self.distance * self.direction
+    }
+
+    pub fn end_velocity(&self) -> f64 {
+        self.end_velocity * self.direction
+    }
+
+    /**
+     *  The position and velocity setpoint at a time since the start
+     *
+     *  Before the start, this is the start of the profile, and after the end
+     *  it is the target distance at the end velocity.
+     */
+    pub fn setpoint(&self, time: f64) -> (f64, f64) {
+        let a = self.acceleration;
+        let v0 = self.start_velocity;
+        let vc = self.cruise_velocity;
+
+        let cruise_start = self.accel_time;
+        let decel_start = self.accel_time + self.cruise_time;
+
+        let (position, velocity) = if time <= 0.0 {
+            (0.0, v0)
+        } else if time < cruise_start {
+            (v0 * time + a * time * time / 2.0, v0 + a * time)
+        } else if time < decel_start {
+            let t = time - cruise_start;
+            let accel_distance = (v0 + vc) / 2.0 * self.accel_time;
+            (accel_distance + vc * t, vc)
+        } else if time < self.duration() {
+            let t = time - decel_start;
+            let accel_distance = (v0 + vc) / 2.0 * self.accel_time;
+            let cruise_distance = vc * self.cruise_time;
+            (
+                accel_distance + cruise_distance + vc * t - a * t * t / 2.0,
+                vc - a * t,
+            )
+        } else {
+            (self.distance, self.end_velocity)
+        };
+
+        (position * self.direction, velocity * self.direction)
+    }
+}
+
+fn abs(x: f64) -> f64 {
+    if x < 0.0 {
+        -x
+    } else {
+        x
+    }
+}
+
+fn clamp(x: f64, min: f64, max: f64) -> f64 {
+    if x < min {
+        min
+    } else if x > max {
+        max
+    } else {
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profile;
+
+    const MAX_DELTA: f64 = 0.000001;
+
+    fn assert_close(left: f64, right: f64) {
+        let delta = (left - right).abs();
+        assert!(
+            delta <= MAX_DELTA,
+            "\nleft: {}\nright: {}\ndelta: {}\n",
+            left,
+            right,
+            delta
+        );
+    }
+
+    #[test]
+    fn profile_trapezoid() {
+        let profile = Profile::new(100.0, 0.0, 0.0, 1.0, 0.1);
+        // 10 to accelerate over 5, 90 cruising, 10 to stop over 5
+        assert_close(profile.duration(), 110.0);
+        assert_close(profile.setpoint(10.0).0, 5.0);
+        assert_close(profile.setpoint(10.0).1, 1.0);
+        assert_close(profile.setpoint(55.0).0, 50.0);
+        assert_close(profile.setpoint(110.0).0, 100.0);
+        assert_close(profile.setpoint(110.0).1, 0.0);
+    }
+
+    #[test]
+    fn profile_triangle() {
+        let profile = Profile::new(10.0, 0.0, 0.0, 10.0, 0.1);
+        assert_close(profile.duration(), 20.0);
+        assert_close(profile.setpoint(10.0).0, 5.0);
+        assert_close(profile.setpoint(10.0).1, 1.0);
+    }
+
+    #[test]
+    fn profile_end_velocity() {
+        let profile = Profile::new(100.0, 0.0, 0.5, 1.0, 0.1);
+        let end = profile.setpoint(profile.duration());
+        assert_close(end.0, 100.0);
+        assert_close(end.1, 0.5);
+        assert_close(
+            profile.setpoint(profile.duration() - 0.001).0,
+            100.0 - 0.0005,
+        );
+    }
+
+    #[test]
+    fn profile_unreachable_end_velocity() {
+        let profile = Profile::new(5.0, 0.0, 1.0, 1.0, 0.1);
+        assert_close(
+            profile.end_velocity(),
+            1.0f64.min((2.0f64 * 0.1 * 5.0).sqrt()),
+        );
+        assert_close(profile.setpoint(profile.duration()).0, 5.0);
+    }
+
+    #[test]
+    fn profile_backwards() {
+        let profile = Profile::new(-100.0, 0.0, 0.0, 1.0, 0.1);
+        assert_close(profile.duration(), 110.0);
+        assert_close(profile.setpoint(10.0).0, -5.0);
+        assert_close(profile.setpoint(10.0).1, -1.0);
+        assert_close(profile.setpoint(200.0).0, -100.0);
+    }
+
+    #[test]
+    fn profile_continuous() {
+        let profile = Profile::new(37.0, 0.3, 0.2, 1.0, 0.05);
+        let mut last = profile.setpoint(0.0);
+        let mut t = 0.0;
+        while t < profile.duration() + 1.0 {
+            t += 0.01;
+            let next = profile.setpoint(t);
+            assert!((next.0 - last.0).abs() < 0.02);
+            assert!((next.1 - last.1).abs() < 0.001);
+            last = next;
+        }
+    }
+}
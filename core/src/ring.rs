@@ -0,0 +1,161 @@
+use core::cell::UnsafeCell;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The size of a ring buffer, a power of two so the counters can wrap
+pub const RING_LEN: usize = 1024;
+
+/**
+ *  A single producer, single consumer byte queue
+ *
+ *  One side only pushes and the other only pops, so no locking is needed
+ *  and this can sit between an interrupt and the main loop. The head and
+ *  tail count every byte ever pushed and popped, and wrap around.
+ */
+pub struct RingBuffer {
+    bytes: UnsafeCell<[u8; RING_LEN]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+unsafe impl Sync for RingBuffer {}
+
+impl RingBuffer {
+    pub const fn new() -> RingBuffer {
+        RingBuffer {
+            bytes: UnsafeCell::new([0; RING_LEN]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        self.head.load(Ordering::Acquire).wrapping_sub(tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn free(&self) -> usize {
+        RING_LEN - self.len()
+    }
+
+    /// Add a byte, giving it back if there is no room. Producer only.
+    pub fn push(&self, byte: u8) -> Result<(), u8> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        if head.wrapping_sub(tail) >= RING_LEN {
+            return Err(byte);
+        }
+
+        unsafe {
+            let bytes = self.bytes.get() as *mut u8;
+            bytes.add(head % RING_LEN).write(byte);
+        }
+
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Take the oldest byte. Consumer only.
+    pub fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+
+        if head == tail {
+            return None;
+        }
+
+        let byte = unsafe {
+            let bytes = self.bytes.get() as *const u8;
+            bytes.add(tail % RING_LEN).read()
+        };
+
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(byte)
+    }
+
+    /**
+     *  The oldest bytes that are next to each other in memory
+     *
+     *  This is what a DMA transfer can send in one go. The bytes stay in the
+     *  buffer until they are `consume`d. Consumer only.
+     */
+    pub fn contiguous(&self) -> &[u8] {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+
+        let start = tail % RING_LEN;
+        let len = head.wrapping_sub(tail).min(RING_LEN - start);
+
+        unsafe {
+            let bytes = self.bytes.get() as *const u8;
+            slice::from_raw_parts(bytes.add(start), len)
+        }
+    }
+
+    /// Drop bytes from the front, after they were read with `contiguous`
+    pub fn consume(&self, len: usize) {
+        let len = len.min(self.len());
+        let tail = self.tail.load(Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(len), Ordering::Release);
+    }
+}
+
+impl Default for RingBuffer {
+    fn default() -> RingBuffer {
+        RingBuffer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RingBuffer, RING_LEN};
+
+    #[test]
+    fn ring_push_pop() {
+        let ring = RingBuffer::new();
+        assert_eq!(ring.pop(), None);
+
+        ring.push(1).unwrap();
+        ring.push(2).unwrap();
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.pop(), Some(1));
+        assert_eq!(ring.pop(), Some(2));
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn ring_full() {
+        let ring = RingBuffer::new();
+        for i in 0..RING_LEN {
+            ring.push(i as u8).unwrap();
+        }
+        assert_eq!(ring.push(0), Err(0));
+        assert_eq!(ring.free(), 0);
+        assert_eq!(ring.pop(), Some(0));
+        assert!(ring.push(0).is_ok());
+    }
+
+    #[test]
+    fn ring_contiguous_wraps() {
+        let ring = RingBuffer::new();
+        for _ in 0..RING_LEN - 2 {
+            ring.push(0).unwrap();
+        }
+        ring.consume(RING_LEN - 2);
+
+        for i in 0..5 {
+            ring.push(i).unwrap();
+        }
+
+        assert_eq!(ring.contiguous(), &[0, 1]);
+        ring.consume(2);
+        assert_eq!(ring.contiguous(), &[2, 3, 4]);
+        ring.consume(3);
+        assert!(ring.is_empty());
+    }
+}
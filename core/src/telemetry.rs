@@ -0,0 +1,601 @@
+/*!
+ *  Binary telemetry frames
+ *
+ *  Each message is a version byte, a type byte and a little endian body,
+ *  followed by a CRC-16 of all of that. The whole thing is COBS encoded so it
+ *  has no 0 bytes, and sent between two 0 bytes. The text console never sends
+ *  a 0, so a host can pull the frames out of the stream and treat everything
+ *  else as text.
+ */
+use core::str;
+
+use crate::crc::crc16;
+
+/// Bump this whenever a message changes
+pub const TELEMETRY_VERSION: u8 = 5;
+
+/// The largest message before framing, log text is cut to fit
+pub const MAX_PAYLOAD: usize = 96;
+
+/// The largest frame, with the COBS overhead, CRC and both delimiters
+pub const MAX_FRAME: usize = MAX_PAYLOAD + 2 + MAX_PAYLOAD / 254 + 1 + 2;
+
+const STATE: u8 = 1;
+const SENSORS: u8 = 2;
+const MOVE_EVENT: u8 = 3;
+const LOG: u8 = 4;
+const WALLS: u8 = 5;
+
+#[derive(Debug, PartialEq)]
+pub enum TelemetryError {
+    BufferTooSmall,
+    BadFrame,
+    BadCrc,
+    BadVersion(u8),
+    BadType(u8),
+    BadLength,
+}
+
+/// Where the bot is and what the wheels are doing
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct State {
+    pub time: u32,
+    pub left_pos: f32,
+    pub right_pos: f32,
+    pub left_velocity: f32,
+    pub right_velocity: f32,
+    pub left_target: f32,
+    pub right_target: f32,
+    /// What the motors are asked for in volts, and the PWM counts for that
+    pub left_volts: f32,
+    pub right_volts: f32,
+    pub left_duty: i16,
+    pub right_duty: i16,
+    pub x: f32,
+    pub y: f32,
+    pub heading: f32,
+}
+
+/// The latest distance sensor readings and the battery voltage, in mm and mV
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Sensors {
+    pub time: u32,
+    pub front: u16,
+    pub left: u16,
+    pub right: u16,
+    pub front_status: u8,
+    pub left_status: u8,
+    pub right_status: u8,
+    pub battery: u16,
+}
+
+/// What the wall detector last decided, with confidences from 0 to 1
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Walls {
+    pub time: u32,
+    pub front: bool,
+    pub left: bool,
+    pub right: bool,
+    pub front_confidence: f32,
+    pub left_confidence: f32,
+    pub right_confidence: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MoveKind {
+    Idle,
+    Spin,
+    Linear,
+    Path,
+}
+
+impl MoveKind {
+    /// From the names `Control::current_move_name` gives
+    pub fn from_name(name: &str) -> MoveKind {
+        match name {
+            "spin" => MoveKind::Spin,
+            "linear" => MoveKind::Linear,
+            "path" => MoveKind::Path,
+            _ => MoveKind::Idle,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            MoveKind::Idle => 0,
+            MoveKind::Spin => 1,
+            MoveKind::Linear => 2,
+            MoveKind::Path => 3,
+        }
+    }
+
+    fn from_u8(v: u8) -> Result<MoveKind, TelemetryError> {
+        match v {
+            0 => Ok(MoveKind::Idle),
+            1 => Ok(MoveKind::Spin),
+            2 => Ok(MoveKind::Linear),
+            3 => Ok(MoveKind::Path),
+            _ => Err(TelemetryError::BadFrame),
+        }
+    }
+}
+
+/// A move starting or finishing
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MoveEvent {
+    pub time: u32,
+    pub kind: MoveKind,
+    pub started: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Message<'a> {
+    State(State),
+    Sensors(Sensors),
+    MoveEvent(MoveEvent),
+    Log(&'a str),
+    Walls(Walls),
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), TelemetryError> {
+        let end = self.len + bytes.len();
+        if end > self.buf.len() {
+            return Err(TelemetryError::BufferTooSmall);
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn u8(&mut self, v: u8) -> Result<(), TelemetryError> {
+        self.bytes(&[v])
+    }
+
+    fn u16(&mut self, v: u16) -> Result<(), TelemetryError> {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    fn u32(&mut self, v: u32) -> Result<(), TelemetryError> {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    fn f32(&mut self, v: f32) -> Result<(), TelemetryError> {
+        self.u32(v.to_bits())
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], TelemetryError> {
+        if len > self.buf.len() {
+            return Err(TelemetryError::BadLength);
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, TelemetryError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, TelemetryError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, TelemetryError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, TelemetryError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+}
+
+/// Cut a str to at most len bytes without splitting a character
+fn truncate(s: &str, len: usize) -> &str {
+    if s.len() <= len {
+        return s;
+    }
+
+    let mut end = len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+impl<'a> Message<'a> {
+    /// Write the message, without framing. Returns the length.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, TelemetryError> {
+        let mut w = Writer { buf, len: 0 };
+
+        w.u8(TELEMETRY_VERSION)?;
+
+        match self {
+            Message::State(s) => {
+                w.u8(STATE)?;
+                w.u32(s.time)?;
+                w.f32(s.left_pos)?;
+                w.f32(s.right_pos)?;
+                w.f32(s.left_velocity)?;
+                w.f32(s.right_velocity)?;
+                w.f32(s.left_target)?;
+                w.f32(s.right_target)?;
+                w.f32(s.left_volts)?;
+                w.f32(s.right_volts)?;
+                w.u16(s.left_duty as u16)?;
+                w.u16(s.right_duty as u16)?;
+                w.f32(s.x)?;
+                w.f32(s.y)?;
+                w.f32(s.heading)?;
+            }
+            Message::Sensors(s) => {
+                w.u8(SENSORS)?;
+                w.u32(s.time)?;
+                w.u16(s.front)?;
+                w.u16(s.left)?;
+                w.u16(s.right)?;
+                w.u8(s.front_status)?;
+                w.u8(s.left_status)?;
+                w.u8(s.right_status)?;
+                w.u16(s.battery)?;
+            }
+            Message::MoveEvent(e) => {
+                w.u8(MOVE_EVENT)?;
+                w.u32(e.time)?;
+                w.u8(e.kind.to_u8())?;
+                w.u8(e.started as u8)?;
+            }
+            Message::Log(text) => {
+                w.u8(LOG)?;
+                let room = MAX_PAYLOAD.min(w.buf.len()) - w.len;
+                w.bytes(truncate(text, room).as_bytes())?;
+            }
+            Message::Walls(walls) => {
+                w.u8(WALLS)?;
+                w.u32(walls.time)?;
+                w.u8(walls.front as u8)?;
+                w.u8(walls.left as u8)?;
+                w.u8(walls.right as u8)?;
+                w.f32(walls.front_confidence)?;
+                w.f32(walls.left_confidence)?;
+                w.f32(walls.right_confidence)?;
+            }
+        }
+
+        Ok(w.len)
+    }
+
+    /// Read back a message written by `encode`
+    pub fn decode(buf: &'a [u8]) -> Result<Message<'a>, TelemetryError> {
+        let mut r = Reader { buf };
+
+        let version = r.u8()?;
+        if version != TELEMETRY_VERSION {
+            return Err(TelemetryError::BadVersion(version));
+        }
+
+        let message = match r.u8()? {
+            STATE => Message::State(State {
+                time: r.u32()?,
+                left_pos: r.f32()?,
+                right_pos: r.f32()?,
+                left_velocity: r.f32()?,
+                right_velocity: r.f32()?,
+                left_target: r.f32()?,
+                right_target: r.f32()?,
+                left_volts: r.f32()?,
+                right_volts: r.f32()?,
+                left_duty: r.u16()? as i16,
+                right_duty: r.u16()? as i16,
+                x: r.f32()?,
+                y: r.f32()?,
+                heading: r.f32()?,
+            }),
+            SENSORS => Message::Sensors(Sensors {
+                time: r.u32()?,
+                front: r.u16()?,
+                left: r.u16()?,
+                right: r.u16()?,
+                front_status: r.u8()?,
+                left_status: r.u8()?,
+                right_status: r.u8()?,
+                battery: r.u16()?,
+            }),
+            MOVE_EVENT => Message::MoveEvent(MoveEvent {
+                time: r.u32()?,
+                kind: MoveKind::from_u8(r.u8()?)?,
+                started: r.u8()? != 0,
+            }),
+            LOG => {
+                let text = r.bytes(r.buf.len())?;
+                Message::Log(
+                    str::from_utf8(text)
+                        .map_err(|_| TelemetryError::BadFrame)?,
+                )
+            }
+            WALLS => Message::Walls(Walls {
+                time: r.u32()?,
+                front: r.u8()? != 0,
+                left: r.u8()? != 0,
+                right: r.u8()? != 0,
+                front_confidence: r.f32()?,
+                left_confidence: r.f32()?,
+                right_confidence: r.f32()?,
+            }),
+            t => return Err(TelemetryError::BadType(t)),
+        };
+
+        if !r.buf.is_empty() {
+            return Err(TelemetryError::BadLength);
+        }
+
+        Ok(message)
+    }
+}
+
+/**
+ *  Consistent overhead byte stuffing
+ *
+ *  Replaces every 0 with the distance to the next one, so the output has no
+ *  0 bytes. Returns the length written.
+ */
+pub fn cobs_encode(
+    input: &[u8],
+    output: &mut [u8],
+) -> Result<usize, TelemetryError> {
+    let mut code_index = 0;
+    let mut code = 1u8;
+    let mut len = 1;
+
+    for byte in input {
+        if len >= output.len() {
+            return Err(TelemetryError::BufferTooSmall);
+        }
+
+        if *byte == 0 {
+            output[code_index] = code;
+            code_index = len;
+            code = 1;
+            len += 1;
+        } else {
+            output[len] = *byte;
+            len += 1;
+            code += 1;
+
+            if code == 0xff {
+                if len >= output.len() {
+                    return Err(TelemetryError::BufferTooSmall);
+                }
+                output[code_index] = code;
+                code_index = len;
+                code = 1;
+                len += 1;
+            }
+        }
+    }
+
+    if code_index >= output.len() {
+        return Err(TelemetryError::BufferTooSmall);
+    }
+    output[code_index] = code;
+
+    Ok(len)
+}
+
+/// Undo `cobs_encode`, returns the length written
+pub fn cobs_decode(
+    input: &[u8],
+    output: &mut [u8],
+) -> Result<usize, TelemetryError> {
+    let mut i = 0;
+    let mut len = 0;
+
+    while i < input.len() {
+        let code = input[i];
+        if code == 0 || i + code as usize > input.len() {
+            return Err(TelemetryError::BadFrame);
+        }
+        i += 1;
+
+        for _ in 1..code {
+            if len >= output.len() {
+                return Err(TelemetryError::BufferTooSmall);
+            }
+            output[len] = input[i];
+            len += 1;
+            i += 1;
+        }
+
+        if code < 0xff && i < input.len() {
+            if len >= output.len() {
+                return Err(TelemetryError::BufferTooSmall);
+            }
+            output[len] = 0;
+            len += 1;
+        }
+    }
+
+    Ok(len)
+}
+
+/// Encode a message as a complete frame, ready to send
+pub fn encode_frame(
+    message: &Message,
+    frame: &mut [u8],
+) -> Result<usize, TelemetryError> {
+    let mut payload = [0; MAX_PAYLOAD + 2];
+    let len = message.encode(&mut payload[..MAX_PAYLOAD])?;
+
+    let crc = crc16(&payload[..len]);
+    payload[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+
+    if frame.len() < 2 {
+        return Err(TelemetryError::BufferTooSmall);
+    }
+
+    let end = frame.len() - 1;
+    let encoded = cobs_encode(&payload[..len + 2], &mut frame[1..end])?;
+
+    frame[0] = 0;
+    frame[encoded + 1] = 0;
+
+    Ok(encoded + 2)
+}
+
+/**
+ *  Decode a frame received from `encode_frame`
+ *
+ *  The frame is the bytes between the 0 delimiters. The message may borrow
+ *  from buf, which has to be big enough for the decoded payload.
+ */
+pub fn decode_frame<'a>(
+    frame: &[u8],
+    buf: &'a mut [u8],
+) -> Result<Message<'a>, TelemetryError> {
+    let len = cobs_decode(frame, buf)?;
+    if len < 2 {
+        return Err(TelemetryError::BadLength);
+    }
+
+    let (payload, crc) = buf[..len].split_at(len - 2);
+    if u16::from_le_bytes([crc[0], crc[1]]) != crc16(payload) {
+        return Err(TelemetryError::BadCrc);
+    }
+
+    Message::decode(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: Message) {
+        let mut frame = [0; MAX_FRAME];
+        let len = encode_frame(&message, &mut frame).unwrap();
+
+        assert_eq!(frame[0], 0);
+        assert_eq!(frame[len - 1], 0);
+        assert!(frame[1..len - 1].iter().all(|b| *b != 0));
+
+        let mut buf = [0; MAX_FRAME];
+        assert_eq!(decode_frame(&frame[1..len - 1], &mut buf), Ok(message));
+    }
+
+    #[test]
+    fn telemetry_state() {
+        round_trip(Message::State(State {
+            time: 1234,
+            left_pos: 10.0,
+            right_pos: -10.0,
+            left_volts: 3.5,
+            right_volts: -1.25,
+            left_duty: 4729,
+            right_duty: -1689,
+            heading: 1.5,
+            ..State::default()
+        }));
+    }
+
+    #[test]
+    fn telemetry_sensors() {
+        round_trip(Message::Sensors(Sensors {
+            time: 0x0100_0000,
+            front: 0,
+            left: 120,
+            right: 765,
+            front_status: 11,
+            battery: 7400,
+            ..Sensors::default()
+        }));
+    }
+
+    #[test]
+    fn telemetry_move_event() {
+        round_trip(Message::MoveEvent(MoveEvent {
+            time: 5,
+            kind: MoveKind::Linear,
+            started: true,
+        }));
+    }
+
+    #[test]
+    fn telemetry_walls() {
+        round_trip(Message::Walls(Walls {
+            time: 42,
+            front: true,
+            right: true,
+            front_confidence: 0.75,
+            left_confidence: 1.0,
+            right_confidence: 0.5,
+            ..Walls::default()
+        }));
+    }
+
+    #[test]
+    fn telemetry_log() {
+        round_trip(Message::Log("hello\n"));
+    }
+
+    #[test]
+    fn telemetry_long_log() {
+        let mut bytes = [0; 8 * 18];
+        for chunk in bytes.chunks_mut(18) {
+            chunk.copy_from_slice("0123456789abcdef\u{e9}".as_bytes());
+        }
+        let text = str::from_utf8(&bytes).unwrap();
+        let mut frame = [0; MAX_FRAME];
+        let len = encode_frame(&Message::Log(text), &mut frame).unwrap();
+
+        let mut buf = [0; MAX_FRAME];
+        match decode_frame(&frame[1..len - 1], &mut buf) {
+            Ok(Message::Log(t)) => {
+                assert!(text.starts_with(t));
+                assert!(t.len() <= MAX_PAYLOAD - 2);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn telemetry_bad_crc() {
+        let mut frame = [0; MAX_FRAME];
+        let message = Message::Log("hello");
+        let len = encode_frame(&message, &mut frame).unwrap();
+        frame[4] ^= 0x01;
+
+        let mut buf = [0; MAX_FRAME];
+        assert_eq!(
+            decode_frame(&frame[1..len - 1], &mut buf),
+            Err(TelemetryError::BadCrc)
+        );
+    }
+
+    #[test]
+    fn cobs_zeros_and_long_runs() {
+        let mut input = [1u8; 600];
+        input[0] = 0;
+        input[300] = 0;
+        input[599] = 0;
+
+        let mut encoded = [0; 700];
+        let len = cobs_encode(&input, &mut encoded).unwrap();
+        assert!(encoded[..len].iter().all(|b| *b != 0));
+
+        let mut decoded = [0; 700];
+        let decoded_len = cobs_decode(&encoded[..len], &mut decoded).unwrap();
+        assert_eq!(&decoded[..decoded_len], &input[..]);
+    }
+}
@@ -0,0 +1,1652 @@
+use core::convert::TryFrom;
+
+use embedded_hal::blocking::i2c;
+
+use crate::distance::{
+    AmbientSample, DistanceSample, DistanceSensor, SensorCalibration,
+};
+use crate::i2c::Transaction;
+
+pub const DEFAULT_ADDRESS: u8 = 0x29;
+
+/// How many times to check for a sample before giving up on it
+const MAX_POLLS: u32 = 1000;
+
+/// Bit in RESULT__INTERRUPT_STATUS_GPIO set when a range sample is ready
+const RANGE_SAMPLE_READY: u8 = 0x04;
+
+/// Bit in RESULT__INTERRUPT_STATUS_GPIO set when an ALS sample is ready
+const ALS_SAMPLE_READY: u8 = 0x20;
+
+/// The result registers read in one go for each sample, from
+/// RESULT__RANGE_STATUS to RESULT__RANGE_VAL
+const RESULTS_START: u16 = registers::RESULT__RANGE_STATUS;
+const RESULTS_LEN: usize = (registers::RESULT__RANGE_VAL
+    - registers::RESULT__RANGE_STATUS) as usize
+    + 1;
+
+/// How many ranges to average over when calibrating
+const CALIBRATION_SAMPLES: u32 = 16;
+
+/// Lux per ALS count at a gain of 1 and 100 ms integration, from the
+/// datasheet
+const LUX_RESOLUTION: f32 = 0.32;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Vl6180xError {
+    /// The sensor didn't acknowledge, or the bus failed some other way
+    Bus,
+    /// A sample never became ready
+    Timeout,
+    /// No new sample is ready yet
+    NotReady,
+    /// Scaling can only be 1, 2 or 3
+    InvalidScaling,
+    /// The ALS integration period must be 1 to 512 ms
+    InvalidPeriod,
+    /// Too few valid ranges to calibrate from
+    Calibration,
+}
+
+/**
+ *  What RESULT__RANGE_STATUS says about a range sample
+ *
+ *  Anything but `NoError` means the range can't be trusted. `NoTargetIgnore`,
+ *  `MaxSignalToNoiseRatio` and the overflows usually just mean there is
+ *  nothing in range, the others are faults.
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RangeStatus {
+    NoError,
+    VcselContinuityTest,
+    VcselWatchdogTest,
+    VcselWatchdog,
+    Pll1Lock,
+    Pll2Lock,
+    EarlyConvergenceEstimate,
+    MaxConvergence,
+    NoTargetIgnore,
+    MaxSignalToNoiseRatio,
+    RawRangingAlgoUnderflow,
+    RawRangingAlgoOverflow,
+    RangingAlgoUnderflow,
+    RangingAlgoOverflow,
+    /// Codes 9 and 10 are reserved
+    Unknown(u8),
+}
+
+impl RangeStatus {
+    /// From the error code in the top four bits of RESULT__RANGE_STATUS
+    pub fn from_code(code: u8) -> RangeStatus {
+        match code {
+            0 => RangeStatus::NoError,
+            1 => RangeStatus::VcselContinuityTest,
+            2 => RangeStatus::VcselWatchdogTest,
+            3 => RangeStatus::VcselWatchdog,
+            4 => RangeStatus::Pll1Lock,
+            5 => RangeStatus::Pll2Lock,
+            6 => RangeStatus::EarlyConvergenceEstimate,
+            7 => RangeStatus::MaxConvergence,
+            8 => RangeStatus::NoTargetIgnore,
+            11 => RangeStatus::MaxSignalToNoiseRatio,
+            12 => RangeStatus::RawRangingAlgoUnderflow,
+            13 => RangeStatus::RawRangingAlgoOverflow,
+            14 => RangeStatus::RangingAlgoUnderflow,
+            15 => RangeStatus::RangingAlgoOverflow,
+            c => RangeStatus::Unknown(c),
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            RangeStatus::NoError => 0,
+            RangeStatus::VcselContinuityTest => 1,
+            RangeStatus::VcselWatchdogTest => 2,
+            RangeStatus::VcselWatchdog => 3,
+            RangeStatus::Pll1Lock => 4,
+            RangeStatus::Pll2Lock => 5,
+            RangeStatus::EarlyConvergenceEstimate => 6,
+            RangeStatus::MaxConvergence => 7,
+            RangeStatus::NoTargetIgnore => 8,
+            RangeStatus::MaxSignalToNoiseRatio => 11,
+            RangeStatus::RawRangingAlgoUnderflow => 12,
+            RangeStatus::RawRangingAlgoOverflow => 13,
+            RangeStatus::RangingAlgoUnderflow => 14,
+            RangeStatus::RangingAlgoOverflow => 15,
+            RangeStatus::Unknown(c) => c,
+        }
+    }
+
+    /// Whether the status just means there is nothing in range
+    pub fn is_no_target(self) -> bool {
+        matches!(
+            self,
+            RangeStatus::NoTargetIgnore
+                | RangeStatus::MaxSignalToNoiseRatio
+                | RangeStatus::RawRangingAlgoOverflow
+                | RangeStatus::RangingAlgoOverflow
+        )
+    }
+}
+
+/**
+ *  The analogue gain of the ambient light sensor
+ *
+ *  Higher gains are for darker rooms, the ALS count saturates in bright
+ *  light at high gain.
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AlsGain {
+    Gain1,
+    Gain1_25,
+    Gain1_67,
+    Gain2_5,
+    Gain5,
+    Gain10,
+    Gain20,
+    Gain40,
+}
+
+impl AlsGain {
+    pub const ALL: [AlsGain; 8] = [
+        AlsGain::Gain1,
+        AlsGain::Gain1_25,
+        AlsGain::Gain1_67,
+        AlsGain::Gain2_5,
+        AlsGain::Gain5,
+        AlsGain::Gain10,
+        AlsGain::Gain20,
+        AlsGain::Gain40,
+    ];
+
+    /// The gain it is called by
+    pub fn nominal(self) -> f32 {
+        match self {
+            AlsGain::Gain1 => 1.0,
+            AlsGain::Gain1_25 => 1.25,
+            AlsGain::Gain1_67 => 1.67,
+            AlsGain::Gain2_5 => 2.5,
+            AlsGain::Gain5 => 5.0,
+            AlsGain::Gain10 => 10.0,
+            AlsGain::Gain20 => 20.0,
+            AlsGain::Gain40 => 40.0,
+        }
+    }
+
+    /// The gain it really has, from table 14 in the datasheet
+    pub fn actual(self) -> f32 {
+        match self {
+            AlsGain::Gain1 => 1.01,
+            AlsGain::Gain1_25 => 1.28,
+            AlsGain::Gain1_67 => 1.72,
+            AlsGain::Gain2_5 => 2.60,
+            AlsGain::Gain5 => 5.21,
+            AlsGain::Gain10 => 10.32,
+            AlsGain::Gain20 => 20.0,
+            AlsGain::Gain40 => 40.0,
+        }
+    }
+
+    /// The value for the bottom bits of SYSALS__ANALOGUE_GAIN
+    fn code(self) -> u8 {
+        match self {
+            AlsGain::Gain20 => 0,
+            AlsGain::Gain10 => 1,
+            AlsGain::Gain5 => 2,
+            AlsGain::Gain2_5 => 3,
+            AlsGain::Gain1_67 => 4,
+            AlsGain::Gain1_25 => 5,
+            AlsGain::Gain1 => 6,
+            AlsGain::Gain40 => 7,
+        }
+    }
+
+    /// The gain with this nominal value, like `2.5`
+    pub fn from_nominal(gain: f32) -> Option<AlsGain> {
+        AlsGain::ALL.iter().cloned().find(|g| g.nominal() == gain)
+    }
+}
+
+/// How the ambient light sensor is set up
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AlsSettings {
+    pub gain: AlsGain,
+    /// How long each ALS sample integrates for, in ms
+    pub integration_period: u16,
+    /// The period between samples in ms when interleaved with ranging,
+    /// `None` when only ranging
+    pub interleaved: Option<u16>,
+}
+
+impl Default for AlsSettings {
+    /// What `init_default` sets up
+    fn default() -> AlsSettings {
+        AlsSettings {
+            gain: AlsGain::Gain1,
+            integration_period: 100,
+            interleaved: None,
+        }
+    }
+}
+
+impl AlsSettings {
+    /// Convert an ALS count to lux, from section 2.13.4 of the datasheet
+    pub fn lux(&self, count: u16) -> f32 {
+        LUX_RESOLUTION * f32::from(count) / self.gain.actual() * 100.0
+            / f32::from(self.integration_period)
+    }
+}
+
+/// How far through reading a sample in the background the sensor is
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Pending {
+    Idle,
+    /// Reading the results, for a sample that was ready at `timestamp`
+    Results {
+        timestamp: u32,
+    },
+    /// Clearing the interrupts, then starting the next range if `restart`
+    Clear {
+        restart: bool,
+    },
+    /// Starting the next range
+    Start,
+}
+
+/// How many times each kind of error has happened since startup
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Vl6180xErrors {
+    pub bus: u32,
+    pub timeout: u32,
+}
+
+mod registers {
+    #![allow(dead_code)]
+    pub const IDENTIFICATION__MODEL_ID: u16 = 0x000;
+    pub const IDENTIFICATION__MODEL_REV_MAJOR: u16 = 0x001;
+    pub const IDENTIFICATION__MODEL_REV_MINOR: u16 = 0x002;
+    pub const IDENTIFICATION__MODULE_REV_MAJOR: u16 = 0x003;
+    pub const IDENTIFICATION__MODULE_REV_MINOR: u16 = 0x004;
+    pub const IDENTIFICATION__DATE_HI: u16 = 0x006;
+    pub const IDENTIFICATION__DATE_LO: u16 = 0x007;
+    pub const IDENTIFICATION__TIME: u16 = 0x008; // 16-bit
+    pub const SYSTEM__MODE_GPIO0: u16 = 0x010;
+    pub const SYSTEM__MODE_GPIO1: u16 = 0x011;
+    pub const SYSTEM__HISTORY_CTRL: u16 = 0x012;
+    pub const SYSTEM__INTERRUPT_CONFIG_GPIO: u16 = 0x014;
+    pub const SYSTEM__INTERRUPT_CLEAR: u16 = 0x015;
+    pub const SYSTEM__FRESH_OUT_OF_RESET: u16 = 0x016;
+    pub const SYSTEM__GROUPED_PARAMETER_HOLD: u16 = 0x017;
+    pub const SYSRANGE__START: u16 = 0x018;
+    pub const SYSRANGE__THRESH_HIGH: u16 = 0x019;
+    pub const SYSRANGE__THRESH_LOW: u16 = 0x01A;
+    pub const SYSRANGE__INTERMEASUREMENT_PERIOD: u16 = 0x01B;
+    pub const SYSRANGE__MAX_CONVERGENCE_TIME: u16 = 0x01C;
+    pub const SYSRANGE__CROSSTALK_COMPENSATION_RATE: u16 = 0x01E; // 16-bit
+    pub const SYSRANGE__CROSSTALK_VALID_HEIGHT: u16 = 0x021;
+    pub const SYSRANGE__EARLY_CONVERGENCE_ESTIMATE: u16 = 0x022; // 16-bit
+    pub const SYSRANGE__PART_TO_PART_RANGE_OFFSET: u16 = 0x024;
+    pub const SYSRANGE__RANGE_IGNORE_VALID_HEIGHT: u16 = 0x025;
+    pub const SYSRANGE__RANGE_IGNORE_THRESHOLD: u16 = 0x026; // 16-bit
+    pub const SYSRANGE__MAX_AMBIENT_LEVEL_MULT: u16 = 0x02C;
+    pub const SYSRANGE__RANGE_CHECK_ENABLES: u16 = 0x02D;
+    pub const SYSRANGE__VHV_RECALIBRATE: u16 = 0x02E;
+    pub const SYSRANGE__VHV_REPEAT_RATE: u16 = 0x031;
+    pub const SYSALS__START: u16 = 0x038;
+    pub const SYSALS__THRESH_HIGH: u16 = 0x03A;
+    pub const SYSALS__THRESH_LOW: u16 = 0x03C;
+    pub const SYSALS__INTERMEASUREMENT_PERIOD: u16 = 0x03E;
+    pub const SYSALS__ANALOGUE_GAIN: u16 = 0x03F;
+    pub const SYSALS__INTEGRATION_PERIOD: u16 = 0x040;
+    pub const RESULT__RANGE_STATUS: u16 = 0x04D;
+    pub const RESULT__ALS_STATUS: u16 = 0x04E;
+    pub const RESULT__INTERRUPT_STATUS_GPIO: u16 = 0x04F;
+    pub const RESULT__ALS_VAL: u16 = 0x050; // 16-bit
+    pub const RESULT__HISTORY_BUFFER_0: u16 = 0x052; // 16-bit
+    pub const RESULT__HISTORY_BUFFER_1: u16 = 0x054; // 16-bit
+    pub const RESULT__HISTORY_BUFFER_2: u16 = 0x056; // 16-bit
+    pub const RESULT__HISTORY_BUFFER_3: u16 = 0x058; // 16-bit
+    pub const RESULT__HISTORY_BUFFER_4: u16 = 0x05A; // 16-bit
+    pub const RESULT__HISTORY_BUFFER_5: u16 = 0x05C; // 16-bit
+    pub const RESULT__HISTORY_BUFFER_6: u16 = 0x05E; // 16-bit
+    pub const RESULT__HISTORY_BUFFER_7: u16 = 0x060; // 16-bit
+    pub const RESULT__RANGE_VAL: u16 = 0x062;
+    pub const RESULT__RANGE_RAW: u16 = 0x064;
+    pub const RESULT__RANGE_RETURN_RATE: u16 = 0x066; // 16-bit
+    pub const RESULT__RANGE_REFERENCE_RATE: u16 = 0x068; // 16-bit
+    pub const RESULT__RANGE_RETURN_SIGNAL_COUNT: u16 = 0x06C; // 32-bit
+    pub const RESULT__RANGE_REFERENCE_SIGNAL_COUNT: u16 = 0x070; // 32-bit
+    pub const RESULT__RANGE_RETURN_AMB_COUNT: u16 = 0x074; // 32-bit
+    pub const RESULT__RANGE_REFERENCE_AMB_COUNT: u16 = 0x078; // 32-bit
+    pub const RESULT__RANGE_RETURN_CONV_TIME: u16 = 0x07C; // 32-bit
+    pub const RESULT__RANGE_REFERENCE_CONV_TIME: u16 = 0x080; // 32-bit
+    pub const RANGE_SCALER: u16 = 0x096; // 16-bit - see STSW-IMG003 core/inc/vl6180x_def.h
+    pub const READOUT__AVERAGING_SAMPLE_PERIOD: u16 = 0x10A;
+    pub const FIRMWARE__BOOTUP: u16 = 0x119;
+    pub const FIRMWARE__RESULT_SCALER: u16 = 0x120;
+    pub const I2C_SLAVE__DEVICE_ADDRESS: u16 = 0x212;
+    pub const INTERLEAVED_MODE__ENABLE: u16 = 0x2A3;
+
+    pub const SCALAR_VALUES: [u16; 4] = [0, 253, 127, 84];
+}
+
+pub struct VL6180x<I2C>
+where
+    I2C: i2c::Read + i2c::Write + i2c::WriteRead + Transaction,
+{
+    i2c: I2C,
+
+    address: u8,
+    scaling: u8,
+    /// The part to part offset in use, in mm
+    ptp_offset: i16,
+    /// The part to part offset the sensor came with, in mm
+    factory_offset: i16,
+    calibration: SensorCalibration,
+
+    sample: DistanceSample,
+
+    als: AlsSettings,
+    ambient: Option<AmbientSample>,
+
+    pending: Pending,
+    /// How many updates the current background transaction has taken
+    pending_polls: u32,
+
+    errors: Vl6180xErrors,
+}
+
+impl<I2C> VL6180x<I2C>
+where
+    I2C: i2c::Read + i2c::Write + i2c::WriteRead + Transaction,
+{
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        VL6180x {
+            i2c,
+            address,
+            scaling: 1,
+            ptp_offset: 0,
+            factory_offset: 0,
+            calibration: SensorCalibration::default(),
+            sample: DistanceSample {
+                range: 255,
+                status: RangeStatus::NoTargetIgnore,
+                timestamp: 0,
+            },
+            als: AlsSettings::default(),
+            ambient: None,
+            pending: Pending::Idle,
+            pending_polls: 0,
+            errors: Vl6180xErrors::default(),
+        }
+    }
+
+    pub fn errors(&self) -> Vl6180xErrors {
+        self.errors
+    }
+
+    fn count<T>(
+        &mut self,
+        result: Result<T, Vl6180xError>,
+    ) -> Result<T, Vl6180xError> {
+        match result {
+            Err(Vl6180xError::Bus) => self.errors.bus += 1,
+            Err(Vl6180xError::Timeout) => self.errors.timeout += 1,
+            _ => {}
+        }
+
+        result
+    }
+
+    fn write_u8(&mut self, reg: u16, data: u8) -> Result<(), Vl6180xError> {
+        self.settle();
+        let buf = [((reg >> 8) & 0xff) as u8, (reg & 0xff) as u8, data];
+        let result = self
+            .i2c
+            .write(self.address, &buf)
+            .map_err(|_| Vl6180xError::Bus);
+        self.count(result)
+    }
+
+    fn write_u16(&mut self, reg: u16, data: u16) -> Result<(), Vl6180xError> {
+        self.settle();
+        let buf = [
+            ((reg >> 8) & 0xff) as u8,
+            (reg & 0xff) as u8,
+            ((data >> 8) & 0xff) as u8,
+            (data & 0xff) as u8,
+        ];
+        let result = self
+            .i2c
+            .write(self.address, &buf)
+            .map_err(|_| Vl6180xError::Bus);
+        self.count(result)
+    }
+
+    fn read(&mut self, reg: u16, buf: &mut [u8]) -> Result<(), Vl6180xError> {
+        self.settle();
+        let address = self.address;
+        let result = match self.i2c.write(address, &reg.to_be_bytes()) {
+            Ok(()) => {
+                self.i2c.read(address, buf).map_err(|_| Vl6180xError::Bus)
+            }
+            Err(_) => Err(Vl6180xError::Bus),
+        };
+        self.count(result)
+    }
+
+    fn read_u8(&mut self, reg: u16) -> Result<u8, Vl6180xError> {
+        let mut buf = [0; 1];
+        self.read(reg, &mut buf)?;
+        Ok(u8::from_be_bytes(buf))
+    }
+
+    fn read_u16(&mut self, reg: u16) -> Result<u16, Vl6180xError> {
+        let mut buf = [0; 2];
+        self.read(reg, &mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    pub fn get_id_bytes(&mut self) -> Result<[u8; 8], Vl6180xError> {
+        let mut buf = [0xde; 8];
+
+        for (reg, byte) in buf.iter_mut().enumerate() {
+            *byte = self.read_u8(reg as u16)?;
+        }
+
+        Ok(buf)
+    }
+
+    /// Set up a sensor that was just powered on and start ranging
+    pub fn init(&mut self) -> Result<(), Vl6180xError> {
+        self.init_private_registers()?;
+        self.init_default()?;
+        self.start_ranging()
+    }
+
+    pub fn init_private_registers(&mut self) -> Result<(), Vl6180xError> {
+        // Store part-to-part range offset so it can be adjusted if scaling is changed
+        // The offset is signed
+        self.ptp_offset = i16::from(
+            self.read_u8(registers::SYSRANGE__PART_TO_PART_RANGE_OFFSET)? as i8,
+        );
+
+        if self.read_u8(registers::SYSTEM__FRESH_OUT_OF_RESET)? == 1 {
+            self.scaling = 1;
+
+            self.write_u8(0x207, 0x01)?;
+            self.write_u8(0x208, 0x01)?;
+            self.write_u8(0x096, 0x00)?;
+            self.write_u8(0x097, 0xFD)?; // RANGE_SCALER = 253
+            self.write_u8(0x0E3, 0x00)?;
+            self.write_u8(0x0E4, 0x04)?;
+            self.write_u8(0x0E5, 0x02)?;
+            self.write_u8(0x0E6, 0x01)?;
+            self.write_u8(0x0E7, 0x03)?;
+            self.write_u8(0x0F5, 0x02)?;
+            self.write_u8(0x0D9, 0x05)?;
+            self.write_u8(0x0DB, 0xCE)?;
+            self.write_u8(0x0DC, 0x03)?;
+            self.write_u8(0x0DD, 0xF8)?;
+            self.write_u8(0x09F, 0x00)?;
+            self.write_u8(0x0A3, 0x3C)?;
+            self.write_u8(0x0B7, 0x00)?;
+            self.write_u8(0x0BB, 0x3C)?;
+            self.write_u8(0x0B2, 0x09)?;
+            self.write_u8(0x0CA, 0x09)?;
+            self.write_u8(0x198, 0x01)?;
+            self.write_u8(0x1B0, 0x17)?;
+            self.write_u8(0x1AD, 0x00)?;
+            self.write_u8(0x0FF, 0x05)?;
+            self.write_u8(0x100, 0x05)?;
+            self.write_u8(0x199, 0x05)?;
+            self.write_u8(0x1A6, 0x1B)?;
+            self.write_u8(0x1AC, 0x3E)?;
+            self.write_u8(0x1A7, 0x1F)?;
+            self.write_u8(0x030, 0x00)?;
+
+            self.write_u8(registers::SYSTEM__FRESH_OUT_OF_RESET, 0)?;
+        } else {
+            // Sensor has already been initialized, so try to get scaling settings by
+            // reading registers.
+
+            self.scaling = {
+                let s = self.read_u16(registers::RANGE_SCALER)?;
+
+                if s == registers::SCALAR_VALUES[3] {
+                    3
+                } else if s == registers::SCALAR_VALUES[2] {
+                    2
+                } else {
+                    1
+                }
+            };
+
+            // Adjust the part-to-part range offset value read earlier to account for
+            // existing scaling. If the sensor was already in 2x or 3x scaling mode,
+            // precision will be lost calculating the original (1x) offset, but this can
+            // be resolved by resetting the sensor and Arduino again.
+            self.ptp_offset *= i16::from(self.scaling);
+        }
+
+        // If only the micro was reset, this is whatever calibration was
+        // applied before, so it gets applied on top again. Power cycle to
+        // get back to the real factory offset.
+        self.factory_offset = self.ptp_offset;
+        self.calibration = SensorCalibration::default();
+
+        Ok(())
+    }
+
+    pub fn read_range_status(&mut self) -> Result<RangeStatus, Vl6180xError> {
+        let status = self.read_u8(registers::RESULT__RANGE_STATUS)?;
+        Ok(RangeStatus::from_code(status >> 4))
+    }
+
+    pub fn init_default(&mut self) -> Result<(), Vl6180xError> {
+        // "Recommended : Public registers"
+
+        // readout__averaging_sample_period = 48
+        self.write_u8(registers::READOUT__AVERAGING_SAMPLE_PERIOD, 0x30)?;
+
+        // sysals__analogue_gain_light = 6
+        // (ALS gain = 1 nominal, actually 1.01 according to Table 14 in datasheet)
+        self.write_u8(registers::SYSALS__ANALOGUE_GAIN, 0x46)?;
+
+        // sysrange__vhv_repeat_rate = 255
+        // (auto Very High Voltage temperature recalibration
+        // after every 255 range measurements)
+        self.write_u8(registers::SYSRANGE__VHV_REPEAT_RATE, 0xFF)?;
+
+        // sysals__integration_period = 99 (100 ms)
+        // AN4545 incorrectly recommends writing to register 0x040;
+        // 0x63 should go in the lower byte, which is register 0x041.
+        self.write_u16(registers::SYSALS__INTEGRATION_PERIOD, 0x0063)?;
+
+        // sysrange__vhv_recalibrate = 1 (manually trigger a VHV recalibration)
+        self.write_u8(registers::SYSRANGE__VHV_RECALIBRATE, 0x01)?;
+
+        // "Optional: Public registers"
+
+        // sysrange__intermeasurement_period = 9 (100 ms)
+        self.write_u8(registers::SYSRANGE__INTERMEASUREMENT_PERIOD, 0x09)?;
+
+        // sysals__intermeasurement_period = 49 (500 ms)
+        self.write_u8(registers::SYSALS__INTERMEASUREMENT_PERIOD, 0x31)?;
+
+        // GPIO1 is the interrupt output, active low
+        self.write_u8(registers::SYSTEM__MODE_GPIO1, 0x10)?;
+
+        // als_int_mode = 4 (ALS new sample ready interrupt);
+        // range_int_mode = 4 (range new sample ready interrupt)
+        self.write_u8(registers::SYSTEM__INTERRUPT_CONFIG_GPIO, 0x24)?;
+
+        // Reset other settings to power-on defaults
+
+        // sysrange__max_convergence_time = 49 (49 ms)
+        self.write_u8(registers::SYSRANGE__MAX_CONVERGENCE_TIME, 0x31)?;
+
+        // disable interleaved mode
+        self.write_u8(registers::INTERLEAVED_MODE__ENABLE, 0)?;
+
+        self.als = AlsSettings::default();
+        self.ambient = None;
+
+        // reset range scaling factor to 1x
+        self.set_scaling(1)
+    }
+
+    /**
+     *  Set the range scaling to 1x, 2x or 3x
+     *
+     *  At 1x the range tops out around 255 mm with 1 mm resolution, at 3x
+     *  it reaches around 765 mm with 3 mm resolution. Ranges are always
+     *  returned in mm, whatever the scaling.
+     */
+    // Implemented using ST's VL6180X API as a reference (STSW-IMG003); see
+    // VL6180x_UpscaleSetScaling() in vl6180x_api.c.
+    pub fn set_scaling(&mut self, new_scaling: u8) -> Result<(), Vl6180xError> {
+        // default value of SYSRANGE__CROSSTALK_VALID_HEIGHT
+        let default_crosstalk_valid_height = 20;
+
+        if !(1..=3).contains(&new_scaling) {
+            return Err(Vl6180xError::InvalidScaling);
+        }
+
+        self.scaling = new_scaling;
+
+        let scaling = self.scaling;
+        self.write_u16(
+            registers::RANGE_SCALER,
+            registers::SCALAR_VALUES[scaling as usize],
+        )?;
+
+        // apply scaling on part-to-part offset
+        self.write_offset()?;
+
+        // apply scaling on CrossTalkValidHeight
+        self.write_u8(
+            registers::SYSRANGE__CROSSTALK_VALID_HEIGHT,
+            default_crosstalk_valid_height / scaling,
+        )?;
+
+        // This function does not apply scaling to RANGE_IGNORE_VALID_HEIGHT.
+
+        // enable early convergence estimate only at 1x scaling
+        let rce = self.read_u8(registers::SYSRANGE__RANGE_CHECK_ENABLES)?;
+        self.write_u8(
+            registers::SYSRANGE__RANGE_CHECK_ENABLES,
+            (rce & 0xFE) | (scaling == 1) as u8,
+        )
+    }
+
+    pub fn scaling(&self) -> u8 {
+        self.scaling
+    }
+
+    /// The longest range that can be measured at the current scaling, in mm
+    pub fn max_range(&self) -> u16 {
+        255 * u16::from(self.scaling)
+    }
+
+    // Performs a single-shot ranging measurement
+    pub fn read_range_single(&mut self) -> Result<u16, Vl6180xError> {
+        self.write_u8(registers::SYSRANGE__START, 0x01)?;
+        self.read_range_continuous()
+    }
+
+    // Starts continuous ranging measurements with the given period in ms
+    // (10 ms resolution; defaults to 100 ms if not specified).
+    //
+    // The period must be greater than the time it takes to perform a
+    // measurement. See section 2.4.4 ("Continuous mode limits") in the datasheet
+    // for details.
+    pub fn start_range_continuous(
+        &mut self,
+        period: u16,
+    ) -> Result<(), Vl6180xError> {
+        let period_reg = ((period as i16 / 10) - 1).clamp(0, 254);
+
+        self.write_u8(
+            registers::SYSRANGE__INTERMEASUREMENT_PERIOD,
+            period_reg as u8,
+        )?;
+        self.write_u8(registers::SYSRANGE__START, 0x03)
+    }
+
+    /// Read the range in mm if a sample is ready, without waiting for one
+    pub fn read_range_ready(&mut self) -> Result<u16, Vl6180xError> {
+        let status = self.read_u8(registers::RESULT__INTERRUPT_STATUS_GPIO)?;
+        if status & RANGE_SAMPLE_READY == 0 {
+            return Err(Vl6180xError::NotReady);
+        }
+
+        // The result is in units of the scaling
+        let range = self.read_u8(registers::RESULT__RANGE_VAL)?;
+        self.write_u8(registers::SYSTEM__INTERRUPT_CLEAR, 0x01)?;
+
+        Ok(u16::from(range) * u16::from(self.scaling))
+    }
+
+    // Returns a range reading when continuous mode is activated
+    // (readRangeSingle() also calls this function after starting a single-shot
+    // range measurement)
+    pub fn read_range_continuous(&mut self) -> Result<u16, Vl6180xError> {
+        for _ in 0..MAX_POLLS {
+            match self.read_range_ready() {
+                Err(Vl6180xError::NotReady) => {}
+                result => return result,
+            }
+        }
+
+        self.count(Err(Vl6180xError::Timeout))
+    }
+
+    pub fn start_ranging(&mut self) -> Result<(), Vl6180xError> {
+        self.write_u8(registers::SYSRANGE__START, 0x01)
+    }
+
+    /// Write the part to part offset, which is in units of the scaling
+    fn write_offset(&mut self) -> Result<(), Vl6180xError> {
+        let offset = self.ptp_offset / i16::from(self.scaling);
+        let offset = i8::try_from(offset).unwrap_or(if offset < 0 {
+            i8::MIN
+        } else {
+            i8::MAX
+        });
+
+        self.write_u8(
+            registers::SYSRANGE__PART_TO_PART_RANGE_OFFSET,
+            offset as u8,
+        )
+    }
+
+    /// Write the crosstalk compensation in Mcps, which is 9.7 fixed point
+    fn write_crosstalk(&mut self, crosstalk: f32) -> Result<(), Vl6180xError> {
+        self.write_u16(
+            registers::SYSRANGE__CROSSTALK_COMPENSATION_RATE,
+            (crosstalk.max(0.0) * 128.0) as u16,
+        )
+    }
+
+    pub fn calibration(&self) -> SensorCalibration {
+        self.calibration
+    }
+
+    pub fn set_calibration(
+        &mut self,
+        calibration: SensorCalibration,
+    ) -> Result<(), Vl6180xError> {
+        self.ptp_offset = self.factory_offset + calibration.offset as i16;
+        self.write_offset()?;
+        self.write_crosstalk(calibration.crosstalk)?;
+
+        self.calibration = calibration;
+        Ok(())
+    }
+
+    /**
+     *  Get ready to take calibration ranges
+     *
+     *  Calibration is done at 1x scaling with single shot ranges, so this
+     *  stops interleaved mode and waits out the range `update` started.
+     */
+    fn start_calibration(&mut self) -> Result<(), Vl6180xError> {
+        if self.als.interleaved.is_some() {
+            self.stop_interleaved()?;
+        }
+
+        for _ in 0..MAX_POLLS {
+            match self.read_range_ready() {
+                Err(Vl6180xError::NotReady) => {}
+                Ok(_) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.set_scaling(1)
+    }
+
+    /// Put the scaling and calibration back and carry on ranging
+    fn finish_calibration(&mut self, scaling: u8) -> Result<(), Vl6180xError> {
+        self.set_scaling(scaling)?;
+        self.set_calibration(self.calibration)?;
+        self.start_ranging()
+    }
+
+    /// Average range in mm and return rate in Mcps over the valid samples
+    fn average_ranges(&mut self) -> Result<(f32, f32), Vl6180xError> {
+        let mut count = 0;
+        let mut range_sum = 0;
+        let mut rate_sum = 0;
+
+        for _ in 0..CALIBRATION_SAMPLES {
+            let range = self.read_range_single()?;
+            if self.read_range_status()? == RangeStatus::NoError {
+                let rate =
+                    self.read_u16(registers::RESULT__RANGE_RETURN_RATE)?;
+
+                count += 1;
+                range_sum += u32::from(range);
+                rate_sum += u32::from(rate);
+            }
+        }
+
+        if count < CALIBRATION_SAMPLES / 2 {
+            return Err(Vl6180xError::Calibration);
+        }
+
+        let range = range_sum as f32 / count as f32;
+        let rate = rate_sum as f32 / count as f32 / 128.0;
+
+        Ok((range, rate))
+    }
+
+    /**
+     *  Find the part to part offset from ranges to a target `distance` mm
+     *  away, and apply it
+     *
+     *  ST recommend a white target 50 mm away. Crosstalk compensation is
+     *  turned off while measuring, so calibrate the offset first.
+     */
+    pub fn calibrate_offset(
+        &mut self,
+        distance: u16,
+    ) -> Result<SensorCalibration, Vl6180xError> {
+        let scaling = self.scaling;
+        let result = self.try_calibrate_offset(distance);
+        self.finish_calibration(scaling)?;
+        result.map(|()| self.calibration)
+    }
+
+    fn try_calibrate_offset(
+        &mut self,
+        distance: u16,
+    ) -> Result<(), Vl6180xError> {
+        self.start_calibration()?;
+
+        self.ptp_offset = 0;
+        self.write_offset()?;
+        self.write_crosstalk(0.0)?;
+
+        let (range, _) = self.average_ranges()?;
+
+        let offset = f32::from(distance) - range;
+        let offset = if offset < 0.0 {
+            offset - 0.5
+        } else {
+            offset + 0.5
+        } as i32;
+
+        self.calibration.offset = offset - i32::from(self.factory_offset);
+
+        Ok(())
+    }
+
+    /**
+     *  Find the crosstalk from ranges to a target `distance` mm away, and
+     *  apply it
+     *
+     *  ST recommend a black target 100 mm away, where the light reflected
+     *  off the cover makes the range read short.
+     */
+    pub fn calibrate_crosstalk(
+        &mut self,
+        distance: u16,
+    ) -> Result<SensorCalibration, Vl6180xError> {
+        let scaling = self.scaling;
+        let result = self.try_calibrate_crosstalk(distance);
+        self.finish_calibration(scaling)?;
+        result.map(|()| self.calibration)
+    }
+
+    fn try_calibrate_crosstalk(
+        &mut self,
+        distance: u16,
+    ) -> Result<(), Vl6180xError> {
+        self.start_calibration()?;
+        self.write_crosstalk(0.0)?;
+
+        if distance == 0 {
+            return Err(Vl6180xError::Calibration);
+        }
+
+        let (range, rate) = self.average_ranges()?;
+
+        // Reading long means no crosstalk to speak of
+        self.calibration.crosstalk =
+            (rate * (1.0 - range / f32::from(distance))).max(0.0);
+
+        Ok(())
+    }
+
+    pub fn als_settings(&self) -> AlsSettings {
+        self.als
+    }
+
+    /// The latest ambient light sample, if interleaved mode is measuring it
+    pub fn ambient(&self) -> Option<AmbientSample> {
+        self.ambient
+    }
+
+    pub fn set_als_gain(&mut self, gain: AlsGain) -> Result<(), Vl6180xError> {
+        // The top bits must be 0x40
+        self.write_u8(registers::SYSALS__ANALOGUE_GAIN, 0x40 | gain.code())?;
+        self.als.gain = gain;
+        Ok(())
+    }
+
+    /**
+     *  Set how long each ALS sample integrates for, from 1 to 512 ms
+     *
+     *  Longer periods are less noisy, but in interleaved mode the period
+     *  between samples has to fit the integration and a range measurement.
+     */
+    pub fn set_als_integration_period(
+        &mut self,
+        period: u16,
+    ) -> Result<(), Vl6180xError> {
+        if !(1..=512).contains(&period) {
+            return Err(Vl6180xError::InvalidPeriod);
+        }
+
+        self.write_u16(registers::SYSALS__INTEGRATION_PERIOD, period - 1)?;
+        self.als.integration_period = period;
+        Ok(())
+    }
+
+    /// Convert an ALS count to lux with the current settings
+    pub fn lux(&self, count: u16) -> f32 {
+        self.als.lux(count)
+    }
+
+    // Performs a single-shot ambient light measurement, in lux. Ranging
+    // has to be idle, this can't be used in interleaved mode.
+    pub fn read_ambient_single(&mut self) -> Result<f32, Vl6180xError> {
+        self.write_u8(registers::SYSALS__START, 0x01)?;
+
+        for _ in 0..MAX_POLLS {
+            match self.read_ambient_ready() {
+                Err(Vl6180xError::NotReady) => {}
+                result => return result,
+            }
+        }
+
+        self.count(Err(Vl6180xError::Timeout))
+    }
+
+    /// Read the ambient light in lux if a sample is ready
+    pub fn read_ambient_ready(&mut self) -> Result<f32, Vl6180xError> {
+        let status = self.read_u8(registers::RESULT__INTERRUPT_STATUS_GPIO)?;
+        if status & ALS_SAMPLE_READY == 0 {
+            return Err(Vl6180xError::NotReady);
+        }
+
+        let count = self.read_u16(registers::RESULT__ALS_VAL)?;
+        self.write_u8(registers::SYSTEM__INTERRUPT_CLEAR, 0x02)?;
+
+        Ok(self.lux(count))
+    }
+
+    /**
+     *  Measure ambient light every `period` ms, with a range measurement
+     *  straight after each one
+     *
+     *  The sensor starts each range itself in this mode, so ranges only
+     *  come as often as the ambient light samples. The period has 10 ms
+     *  resolution.
+     */
+    pub fn start_interleaved(
+        &mut self,
+        period: u16,
+    ) -> Result<(), Vl6180xError> {
+        let period_reg = (period / 10).saturating_sub(1).min(254);
+
+        self.write_u8(registers::INTERLEAVED_MODE__ENABLE, 0x01)?;
+        self.write_u8(
+            registers::SYSALS__INTERMEASUREMENT_PERIOD,
+            period_reg as u8,
+        )?;
+        self.write_u8(registers::SYSALS__START, 0x03)?;
+
+        self.als.interleaved = Some(period);
+        Ok(())
+    }
+
+    /// Go back to only ranging
+    pub fn stop_interleaved(&mut self) -> Result<(), Vl6180xError> {
+        // Writing start again stops continuous mode
+        self.write_u8(registers::SYSALS__START, 0x01)?;
+        self.write_u8(registers::INTERLEAVED_MODE__ENABLE, 0x00)?;
+
+        self.als.interleaved = None;
+        self.ambient = None;
+
+        self.start_ranging()
+    }
+
+    fn start_transfer(
+        &mut self,
+        bytes: &[u8],
+        read: usize,
+    ) -> Result<(), Vl6180xError> {
+        self.pending_polls = 0;
+        let result = self
+            .i2c
+            .start(self.address, bytes, read)
+            .map_err(|_| Vl6180xError::Bus);
+        self.count(result)
+    }
+
+    fn finish_transfer(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> nb::Result<(), Vl6180xError> {
+        let result = match self.i2c.finish(buffer) {
+            Ok(()) => Ok(()),
+            Err(nb::Error::WouldBlock) => {
+                self.pending_polls += 1;
+                if self.pending_polls < MAX_POLLS {
+                    return Err(nb::Error::WouldBlock);
+                }
+
+                self.i2c.cancel();
+                Err(Vl6180xError::Timeout)
+            }
+            Err(nb::Error::Other(_)) => Err(Vl6180xError::Bus),
+        };
+
+        self.count(result).map_err(nb::Error::Other)
+    }
+
+    /**
+     *  Take the next step in reading a sample in the background
+     *
+     *  The results are read in one transaction, then the interrupts are
+     *  cleared and the next range started, each step waiting for the one
+     *  before to finish. This returns `Ok(true)` when back to idle.
+     */
+    fn step(&mut self, now: u32) -> nb::Result<bool, Vl6180xError> {
+        match self.pending {
+            Pending::Idle => {
+                self.start_transfer(&RESULTS_START.to_be_bytes(), RESULTS_LEN)?;
+                self.pending = Pending::Results { timestamp: now };
+            }
+            Pending::Results { timestamp } => {
+                let mut results = [0; RESULTS_LEN];
+                self.finish_transfer(&mut results)?;
+
+                let result = |reg: u16| results[(reg - RESULTS_START) as usize];
+
+                let ready = result(registers::RESULT__INTERRUPT_STATUS_GPIO);
+                let range_ready = ready & RANGE_SAMPLE_READY != 0;
+                let als_ready = self.als.interleaved.is_some()
+                    && ready & ALS_SAMPLE_READY != 0;
+
+                if als_ready {
+                    let count = u16::from_be_bytes([
+                        result(registers::RESULT__ALS_VAL),
+                        result(registers::RESULT__ALS_VAL + 1),
+                    ]);
+                    self.ambient = Some(AmbientSample {
+                        lux: self.lux(count),
+                        timestamp,
+                    });
+                }
+
+                if range_ready {
+                    let range = result(registers::RESULT__RANGE_VAL);
+                    let status = RangeStatus::from_code(
+                        result(registers::RESULT__RANGE_STATUS) >> 4,
+                    );
+
+                    // Nothing in range is as far as the sensor can see
+                    let range = if status.is_no_target() {
+                        self.max_range()
+                    } else {
+                        u16::from(range) * u16::from(self.scaling)
+                    };
+
+                    self.sample = DistanceSample {
+                        range,
+                        status,
+                        timestamp,
+                    };
+                }
+
+                if !range_ready && !als_ready {
+                    self.pending = Pending::Idle;
+                    return Ok(true);
+                }
+
+                let clear = range_ready as u8 | (als_ready as u8) << 1;
+                let [hi, lo] = registers::SYSTEM__INTERRUPT_CLEAR.to_be_bytes();
+                self.start_transfer(&[hi, lo, clear], 0)?;
+
+                // Interleaved mode starts the next range by itself
+                self.pending = Pending::Clear {
+                    restart: range_ready && self.als.interleaved.is_none(),
+                };
+            }
+            Pending::Clear { restart } => {
+                self.finish_transfer(&mut [])?;
+
+                if !restart {
+                    self.pending = Pending::Idle;
+                    return Ok(true);
+                }
+
+                let [hi, lo] = registers::SYSRANGE__START.to_be_bytes();
+                self.start_transfer(&[hi, lo, 0x01], 0)?;
+                self.pending = Pending::Start;
+            }
+            Pending::Start => {
+                self.finish_transfer(&mut [])?;
+                self.pending = Pending::Idle;
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Read a sample in the background, as far as it can go without waiting
+    fn poll(&mut self, now: u32) -> nb::Result<(), Vl6180xError> {
+        loop {
+            match self.step(now) {
+                Ok(false) => {}
+                Ok(true) => return Ok(()),
+                Err(nb::Error::WouldBlock) => {
+                    return Err(nb::Error::WouldBlock)
+                }
+                Err(nb::Error::Other(e)) => {
+                    // Counted already, start again next time
+                    self.pending = Pending::Idle;
+                    return Err(nb::Error::Other(e));
+                }
+            }
+        }
+    }
+
+    /// Finish any background read, before talking to the sensor directly
+    fn settle(&mut self) {
+        while self.pending != Pending::Idle {
+            self.poll(0).ok();
+        }
+    }
+}
+
+/**
+ *  The rest of what a VL6180x can do, beyond sampling
+ *
+ *  This is for the `bot sensor` commands, so they can get at the sensor
+ *  through whatever it is wrapped in.
+ */
+pub trait Vl6180xExt: DistanceSensor<Error = Vl6180xError> {
+    /// The longest range the sensor can currently measure, in mm
+    fn max_range(&self) -> u16;
+
+    fn scaling(&self) -> u8;
+
+    /// Trade range resolution for a longer maximum range
+    fn set_scaling(&mut self, scaling: u8) -> Result<(), Vl6180xError>;
+
+    /// The latest ambient light sample, if ambient light is being measured
+    fn ambient(&self) -> Option<AmbientSample>;
+
+    fn als_settings(&self) -> AlsSettings;
+
+    fn set_als_gain(&mut self, gain: AlsGain) -> Result<(), Vl6180xError>;
+
+    /// How long each ambient light sample integrates for, in ms
+    fn set_als_integration_period(
+        &mut self,
+        period: u16,
+    ) -> Result<(), Vl6180xError>;
+
+    fn calibration(&self) -> SensorCalibration;
+
+    fn set_calibration(
+        &mut self,
+        calibration: SensorCalibration,
+    ) -> Result<(), Vl6180xError>;
+
+    /// Find the offset with a target `distance` mm away, and apply it
+    fn calibrate_offset(
+        &mut self,
+        distance: u16,
+    ) -> Result<SensorCalibration, Vl6180xError>;
+
+    /// Find the crosstalk with a dark target `distance` mm away, and apply it
+    fn calibrate_crosstalk(
+        &mut self,
+        distance: u16,
+    ) -> Result<SensorCalibration, Vl6180xError>;
+
+    /// Measure ambient light every `period` ms between ranges, or stop
+    fn set_interleaved(
+        &mut self,
+        period: Option<u16>,
+    ) -> Result<(), Vl6180xError>;
+}
+
+impl<I2C> DistanceSensor for VL6180x<I2C>
+where
+    I2C: i2c::Read + i2c::Write + i2c::WriteRead + Transaction,
+{
+    type Error = Vl6180xError;
+
+    fn update(&mut self, now: u32) -> nb::Result<(), Vl6180xError> {
+        // Errors are counted where they happen, and the last good sample is
+        // kept until a new one can be read
+        self.poll(now)
+    }
+
+    fn sample(&self) -> DistanceSample {
+        self.sample
+    }
+
+    fn error_count(&self) -> u32 {
+        self.errors.bus + self.errors.timeout
+    }
+}
+
+impl<I2C> Vl6180xExt for VL6180x<I2C>
+where
+    I2C: i2c::Read + i2c::Write + i2c::WriteRead + Transaction,
+{
+    fn max_range(&self) -> u16 {
+        VL6180x::max_range(self)
+    }
+
+    fn scaling(&self) -> u8 {
+        VL6180x::scaling(self)
+    }
+
+    fn set_scaling(&mut self, scaling: u8) -> Result<(), Vl6180xError> {
+        VL6180x::set_scaling(self, scaling)
+    }
+
+    fn ambient(&self) -> Option<AmbientSample> {
+        VL6180x::ambient(self)
+    }
+
+    fn als_settings(&self) -> AlsSettings {
+        VL6180x::als_settings(self)
+    }
+
+    fn set_als_gain(&mut self, gain: AlsGain) -> Result<(), Vl6180xError> {
+        VL6180x::set_als_gain(self, gain)
+    }
+
+    fn set_als_integration_period(
+        &mut self,
+        period: u16,
+    ) -> Result<(), Vl6180xError> {
+        VL6180x::set_als_integration_period(self, period)
+    }
+
+    fn calibration(&self) -> SensorCalibration {
+        VL6180x::calibration(self)
+    }
+
+    fn set_calibration(
+        &mut self,
+        calibration: SensorCalibration,
+    ) -> Result<(), Vl6180xError> {
+        VL6180x::set_calibration(self, calibration)
+    }
+
+    fn calibrate_offset(
+        &mut self,
+        distance: u16,
+    ) -> Result<SensorCalibration, Vl6180xError> {
+        VL6180x::calibrate_offset(self, distance)
+    }
+
+    fn calibrate_crosstalk(
+        &mut self,
+        distance: u16,
+    ) -> Result<SensorCalibration, Vl6180xError> {
+        VL6180x::calibrate_crosstalk(self, distance)
+    }
+
+    fn set_interleaved(
+        &mut self,
+        period: Option<u16>,
+    ) -> Result<(), Vl6180xError> {
+        match period {
+            Some(period) => self.start_interleaved(period),
+            None => self.stop_interleaved(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::blocking::i2c;
+
+    use super::{
+        registers, AlsGain, AlsSettings, RangeStatus, VL6180x, Vl6180xError,
+        Vl6180xErrors, MAX_POLLS,
+    };
+    use crate::distance::DistanceSensor;
+    use crate::i2c::Transaction;
+
+    /// Registers that can be read and written, like the real sensor
+    struct MockI2c {
+        registers: [u8; 0x300],
+        pointer: usize,
+        nack: bool,
+        /// How many times `finish` waits on each transaction
+        delay: u32,
+        waits: u32,
+        started: bool,
+        result: [u8; 32],
+    }
+
+    impl MockI2c {
+        fn new() -> MockI2c {
+            MockI2c {
+                registers: [0; 0x300],
+                pointer: 0,
+                nack: false,
+                delay: 0,
+                waits: 0,
+                started: false,
+                result: [0; 32],
+            }
+        }
+    }
+
+    /// Done straight away, but only handed back after `delay` waits
+    impl Transaction for MockI2c {
+        type Error = ();
+
+        fn start(
+            &mut self,
+            address: u8,
+            bytes: &[u8],
+            read: usize,
+        ) -> Result<(), ()> {
+            i2c::Write::write(self, address, bytes)?;
+
+            let mut result = [0; 32];
+            i2c::Read::read(self, address, &mut result[..read])?;
+
+            self.result = result;
+            self.waits = self.delay;
+            self.started = true;
+
+            Ok(())
+        }
+
+        fn finish(&mut self, buffer: &mut [u8]) -> nb::Result<(), ()> {
+            if !self.started {
+                return Err(nb::Error::Other(()));
+            }
+
+            if self.waits > 0 {
+                self.waits -= 1;
+                return Err(nb::Error::WouldBlock);
+            }
+
+            self.started = false;
+            buffer.copy_from_slice(&self.result[..buffer.len()]);
+
+            Ok(())
+        }
+
+        fn cancel(&mut self) {
+            self.started = false;
+        }
+    }
+
+    impl i2c::Write for MockI2c {
+        type Error = ();
+
+        fn write(&mut self, _address: u8, bytes: &[u8]) -> Result<(), ()> {
+            if self.nack {
+                return Err(());
+            }
+
+            self.pointer = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+            for (i, byte) in bytes[2..].iter().enumerate() {
+                self.registers[self.pointer + i] = *byte;
+            }
+
+            Ok(())
+        }
+    }
+
+    impl i2c::Read for MockI2c {
+        type Error = ();
+
+        fn read(&mut self, _address: u8, buffer: &mut [u8]) -> Result<(), ()> {
+            if self.nack {
+                return Err(());
+            }
+
+            let end = self.pointer + buffer.len();
+            buffer.copy_from_slice(&self.registers[self.pointer..end]);
+
+            Ok(())
+        }
+    }
+
+    impl i2c::WriteRead for MockI2c {
+        type Error = ();
+
+        fn write_read(
+            &mut self,
+            address: u8,
+            bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), ()> {
+            i2c::Write::write(self, address, bytes)?;
+            i2c::Read::read(self, address, buffer)
+        }
+    }
+
+    fn sensor() -> VL6180x<MockI2c> {
+        VL6180x::new(MockI2c::new(), 0x29)
+    }
+
+    #[test]
+    fn init_starts_ranging() {
+        let mut sensor = sensor();
+
+        assert_eq!(sensor.init(), Ok(()));
+        assert_eq!(
+            sensor.i2c.registers[registers::SYSRANGE__START as usize],
+            0x01
+        );
+    }
+
+    #[test]
+    fn update_reads_ready_sample() {
+        let mut sensor = sensor();
+        sensor.i2c.registers
+            [registers::RESULT__INTERRUPT_STATUS_GPIO as usize] = 0x04;
+        sensor.i2c.registers[registers::RESULT__RANGE_VAL as usize] = 42;
+        sensor.i2c.registers[registers::RESULT__RANGE_STATUS as usize] = 0x61;
+
+        assert_eq!(sensor.update(100), Ok(()));
+
+        let sample = sensor.sample();
+        assert_eq!(sample.range, 42);
+        assert_eq!(sample.status, RangeStatus::EarlyConvergenceEstimate);
+        assert_eq!(sample.timestamp, 100);
+        assert!(!sample.is_valid());
+        assert_eq!(
+            sensor.i2c.registers[registers::SYSTEM__INTERRUPT_CLEAR as usize],
+            0x01
+        );
+    }
+
+    #[test]
+    fn nothing_in_range_reads_as_max_range() {
+        let mut sensor = sensor();
+        sensor.i2c.registers
+            [registers::RESULT__INTERRUPT_STATUS_GPIO as usize] = 0x04;
+        sensor.i2c.registers[registers::RESULT__RANGE_VAL as usize] = 12;
+        sensor.i2c.registers[registers::RESULT__RANGE_STATUS as usize] = 0xb0;
+
+        assert_eq!(sensor.update(100), Ok(()));
+
+        let sample = sensor.sample();
+        assert_eq!(sample.range, sensor.max_range());
+        assert!(!sample.is_valid());
+        assert!(sample.is_no_target());
+    }
+
+    #[test]
+    fn update_runs_in_the_background() {
+        let mut sensor = sensor();
+        sensor.i2c.delay = 1;
+        sensor.i2c.registers
+            [registers::RESULT__INTERRUPT_STATUS_GPIO as usize] = 0x04;
+        sensor.i2c.registers[registers::RESULT__RANGE_VAL as usize] = 42;
+
+        // Reading the results
+        assert_eq!(sensor.update(100), Err(nb::Error::WouldBlock));
+        assert_eq!(sensor.sample().range, 255);
+
+        // Clearing the interrupt
+        assert_eq!(sensor.update(101), Err(nb::Error::WouldBlock));
+        assert_eq!(sensor.sample().range, 42);
+        assert_eq!(sensor.sample().timestamp, 100);
+
+        // Starting the next range
+        assert_eq!(sensor.update(102), Err(nb::Error::WouldBlock));
+        assert_eq!(sensor.update(103), Ok(()));
+        assert_eq!(
+            sensor.i2c.registers[registers::SYSRANGE__START as usize],
+            0x01
+        );
+    }
+
+    #[test]
+    fn stuck_transactions_time_out() {
+        let mut sensor = sensor();
+        sensor.i2c.delay = u32::MAX;
+
+        let mut result = Ok(());
+        for now in 0..MAX_POLLS {
+            result = sensor.update(now);
+        }
+
+        assert_eq!(result, Err(nb::Error::Other(Vl6180xError::Timeout)));
+        assert_eq!(sensor.errors().timeout, 1);
+    }
+
+    #[test]
+    fn update_waits_for_range_bit() {
+        let mut sensor = sensor();
+        // Only the ALS and low threshold bits, not a new range sample
+        sensor.i2c.registers
+            [registers::RESULT__INTERRUPT_STATUS_GPIO as usize] = 0x09;
+        sensor.i2c.registers[registers::RESULT__RANGE_VAL as usize] = 42;
+
+        assert_eq!(sensor.update(100), Ok(()));
+
+        assert_eq!(sensor.sample().range, 255);
+        assert_eq!(sensor.sample().timestamp, 0);
+    }
+
+    #[test]
+    fn scaled_range_is_in_mm() {
+        let mut sensor = sensor();
+        assert_eq!(sensor.set_scaling(4), Err(Vl6180xError::InvalidScaling));
+        assert_eq!(sensor.set_scaling(3), Ok(()));
+        assert_eq!(sensor.scaling(), 3);
+        assert_eq!(sensor.max_range(), 765);
+
+        sensor.i2c.registers
+            [registers::RESULT__INTERRUPT_STATUS_GPIO as usize] = 0x04;
+        sensor.i2c.registers[registers::RESULT__RANGE_VAL as usize] = 200;
+
+        assert_eq!(sensor.update(100), Ok(()));
+
+        assert_eq!(sensor.sample().range, 600);
+    }
+
+    #[test]
+    fn als_counts_to_lux() {
+        let mut settings = AlsSettings::default();
+        assert!((settings.lux(1010) - 320.0).abs() < 0.01);
+
+        settings.gain = AlsGain::Gain20;
+        settings.integration_period = 50;
+        assert!((settings.lux(1000) - 32.0).abs() < 0.01);
+
+        assert_eq!(AlsGain::from_nominal(2.5), Some(AlsGain::Gain2_5));
+        assert_eq!(AlsGain::from_nominal(3.0), None);
+    }
+
+    #[test]
+    fn interleaved_update_reads_ambient() {
+        let mut sensor = sensor();
+        assert_eq!(sensor.set_als_gain(AlsGain::Gain10), Ok(()));
+        assert_eq!(
+            sensor.set_als_integration_period(513),
+            Err(Vl6180xError::InvalidPeriod)
+        );
+        assert_eq!(sensor.start_interleaved(200), Ok(()));
+
+        let regs = &mut sensor.i2c.registers;
+        assert_eq!(regs[registers::SYSALS__ANALOGUE_GAIN as usize], 0x41);
+        assert_eq!(
+            regs[registers::SYSALS__INTERMEASUREMENT_PERIOD as usize],
+            19
+        );
+        assert_eq!(regs[registers::INTERLEAVED_MODE__ENABLE as usize], 1);
+        assert_eq!(regs[registers::SYSALS__START as usize], 0x03);
+
+        regs[registers::RESULT__INTERRUPT_STATUS_GPIO as usize] = 0x24;
+        regs[registers::RESULT__ALS_VAL as usize] = 0x04;
+        regs[registers::RESULT__ALS_VAL as usize + 1] = 0x08;
+        regs[registers::RESULT__RANGE_VAL as usize] = 50;
+
+        assert_eq!(sensor.update(100), Ok(()));
+
+        let ambient = sensor.ambient().unwrap();
+        assert!((ambient.lux - 0.32 * 1032.0 / 10.32).abs() < 0.01);
+        assert_eq!(ambient.timestamp, 100);
+        assert_eq!(sensor.sample().range, 50);
+        // The sensor starts the next range itself
+        assert_eq!(
+            sensor.i2c.registers[registers::SYSRANGE__START as usize],
+            0
+        );
+    }
+
+    #[test]
+    fn calibration_is_on_top_of_factory_offset() {
+        let mut sensor = sensor();
+        let regs = &mut sensor.i2c.registers;
+        regs[registers::SYSRANGE__PART_TO_PART_RANGE_OFFSET as usize] = 0xfe;
+        assert_eq!(sensor.init_private_registers(), Ok(()));
+
+        let regs = &mut sensor.i2c.registers;
+        regs[registers::RESULT__INTERRUPT_STATUS_GPIO as usize] = 0x04;
+        regs[registers::RESULT__RANGE_VAL as usize] = 45;
+        regs[registers::RESULT__RANGE_RETURN_RATE as usize] = 0x01;
+        regs[registers::RESULT__RANGE_RETURN_RATE as usize + 1] = 0x00;
+
+        let calibration = sensor.calibrate_offset(50).unwrap();
+        assert_eq!(calibration.offset, 7);
+        assert_eq!(
+            sensor.i2c.registers
+                [registers::SYSRANGE__PART_TO_PART_RANGE_OFFSET as usize],
+            5
+        );
+
+        let calibration = sensor.calibrate_crosstalk(60).unwrap();
+        assert_eq!(calibration.offset, 7);
+        assert!((calibration.crosstalk - 0.5).abs() < 0.001);
+        assert_eq!(
+            sensor.i2c.registers
+                [registers::SYSRANGE__CROSSTALK_COMPENSATION_RATE as usize + 1],
+            64
+        );
+
+        // Invalid ranges can't be calibrated from
+        sensor.i2c.registers[registers::RESULT__RANGE_STATUS as usize] = 0xb0;
+        assert_eq!(sensor.calibrate_offset(50), Err(Vl6180xError::Calibration));
+        assert_eq!(sensor.calibration().offset, 7);
+    }
+
+    #[test]
+    fn read_range_times_out() {
+        let mut sensor = sensor();
+        assert_eq!(sensor.read_range_continuous(), Err(Vl6180xError::Timeout));
+        assert_eq!(sensor.errors().timeout, 1);
+    }
+
+    #[test]
+    fn bus_errors_are_counted() {
+        let mut sensor = sensor();
+        sensor.i2c.nack = true;
+
+        assert_eq!(sensor.init(), Err(Vl6180xError::Bus));
+        assert_eq!(sensor.get_id_bytes(), Err(Vl6180xError::Bus));
+        assert_eq!(
+            sensor.update(100),
+            Err(nb::Error::Other(Vl6180xError::Bus))
+        );
+
+        assert_eq!(sensor.errors(), Vl6180xErrors { bus: 3, timeout: 0 });
+        assert_eq!(sensor.error_count(), 3);
+        assert_eq!(sensor.sample().range, 255);
+    }
+
+    #[test]
+    fn range_status_codes() {
+        for code in 0..16 {
+            assert_eq!(RangeStatus::from_code(code).code(), code);
+        }
+        assert_eq!(RangeStatus::from_code(0), RangeStatus::NoError);
+        assert_eq!(RangeStatus::from_code(9), RangeStatus::Unknown(9));
+    }
+}
@@ -0,0 +1,309 @@
+/*!
+ *  Wall detection
+ *
+ *  Rather than trusting one reading when a move finishes, the detector takes
+ *  a vote over every reading from the last part of the cell. Each reading is
+ *  compared against the wall threshold with some hysteresis, so one that
+ *  wobbles around the threshold sticks with what the one before it said.
+ *  Stale readings are left out of both, so a dead sensor gives no
+ *  confidence either way.
+ */
+use crate::config::BotConfig;
+use crate::filter::FilteredDistance;
+
+/// Whether there is a wall on one side, and how sure the detector is
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct WallObservation {
+    pub present: bool,
+    /// The fraction of readings that agree, 0 when there were none
+    pub confidence: f32,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Walls {
+    pub front: WallObservation,
+    pub left: WallObservation,
+    pub right: WallObservation,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Side {
+    /// What the last reading said, for the hysteresis
+    wall: Option<bool>,
+    wall_votes: u32,
+    open_votes: u32,
+}
+
+impl Side {
+    fn start(&mut self) {
+        self.wall_votes = 0;
+        self.open_votes = 0;
+    }
+
+    fn update(&mut self, reading: FilteredDistance, config: &BotConfig) {
+        if reading.stale {
+            return;
+        }
+
+        let range = f64::from(reading.range);
+        let threshold = config.wall_threshold;
+        let hysteresis = config.wall_hysteresis;
+
+        let wall = if range > threshold + hysteresis {
+            false
+        } else if range < threshold - hysteresis {
+            true
+        } else {
+            self.wall.unwrap_or(range <= threshold)
+        };
+
+        self.wall = Some(wall);
+
+        if wall {
+            self.wall_votes += 1;
+        } else {
+            self.open_votes += 1;
+        }
+    }
+
+    fn observation(&self) -> WallObservation {
+        let total = self.wall_votes + self.open_votes;
+        if total == 0 {
+            return WallObservation::default();
+        }
+
+        let present = self.wall_votes > self.open_votes;
+        let agree = if present {
+            self.wall_votes
+        } else {
+            self.open_votes
+        };
+
+        WallObservation {
+            present,
+            confidence: agree as f32 / total as f32,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct WallDetector {
+    front: Side,
+    left: Side,
+    right: Side,
+}
+
+impl WallDetector {
+    pub fn new() -> WallDetector {
+        WallDetector::default()
+    }
+
+    /// Start counting again for a new cell, heading the same way
+    pub fn start(&mut self) {
+        self.front.start();
+        self.left.start();
+        self.right.start();
+    }
+
+    /// Forget everything, after turning
+    pub fn reset(&mut self) {
+        *self = WallDetector::new();
+    }
+
+    /// Count one set of readings
+    pub fn update(
+        &mut self,
+        config: &BotConfig,
+        front: FilteredDistance,
+        left: FilteredDistance,
+        right: FilteredDistance,
+    ) {
+        self.front.update(front, config);
+        self.left.update(left, config);
+        self.right.update(right, config);
+    }
+
+    /// What the readings so far say
+    pub fn walls(&self) -> Walls {
+        Walls {
+            front: self.front.observation(),
+            left: self.left.observation(),
+            right: self.right.observation(),
+        }
+    }
+}
+
+/**
+ *  Where a wall ahead says a forward move should end, in ticks
+ *
+ *  `distance` is how far the move goes and `position` how far it has got,
+ *  both in ticks. A fresh reading of a wall within the look-ahead knows
+ *  better than the encoders where the move should end, as long as that is
+ *  short of where it was going to anyway. A wall past the end of the move
+ *  has nothing to say about it.
+ */
+pub fn front_wall_target(
+    config: &BotConfig,
+    distance: f64,
+    position: f64,
+    front: FilteredDistance,
+) -> Option<f64> {
+    let range = f64::from(front.fresh_range()?);
+    if distance <= 0.0 || range > config.front_wall_lookahead {
+        return None;
+    }
+
+    let ticks_per_mm = f64::from(config.mouse.ticks_per_mm());
+    let target = position + (range - config.front_wall_distance) * ticks_per_mm;
+
+    if target < distance {
+        Some(target)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{front_wall_target, WallDetector, WallObservation};
+    use crate::config::BotConfig;
+    use crate::filter::FilteredDistance;
+
+    fn reading(range: f32) -> FilteredDistance {
+        FilteredDistance {
+            range,
+            variance: 1.0,
+            timestamp: 0,
+            stale: false,
+        }
+    }
+
+    fn stale() -> FilteredDistance {
+        FilteredDistance {
+            stale: true,
+            ..reading(0.0)
+        }
+    }
+
+    fn config() -> BotConfig {
+        BotConfig {
+            wall_threshold: 120.0,
+            wall_hysteresis: 10.0,
+            ..BotConfig::default()
+        }
+    }
+
+    #[test]
+    fn no_readings_is_no_wall() {
+        let detector = WallDetector::new();
+        assert_eq!(detector.walls().left, WallObservation::default());
+    }
+
+    #[test]
+    fn votes_over_the_readings() {
+        let config = config();
+        let mut detector = WallDetector::new();
+
+        for &range in [50.0, 55.0, 200.0, 52.0].iter() {
+            detector.update(&config, reading(range), stale(), reading(200.0));
+        }
+
+        let walls = detector.walls();
+        assert!(walls.front.present);
+        assert_eq!(walls.front.confidence, 0.75);
+        assert_eq!(walls.left, WallObservation::default());
+        assert!(!walls.right.present);
+        assert_eq!(walls.right.confidence, 1.0);
+    }
+
+    #[test]
+    fn stale_readings_are_left_out() {
+        let config = config();
+        let mut detector = WallDetector::new();
+
+        // A dead sensor says nothing either way
+        for _ in 0..3 {
+            detector.update(&config, stale(), stale(), stale());
+        }
+        assert_eq!(detector.walls().front, WallObservation::default());
+
+        // Nor does it upset the hysteresis or the vote around it
+        detector.update(&config, reading(100.0), stale(), stale());
+        detector.update(&config, stale(), stale(), stale());
+        detector.update(&config, reading(125.0), stale(), stale());
+
+        let front = detector.walls().front;
+        assert!(front.present);
+        assert_eq!(front.confidence, 1.0);
+    }
+
+    #[test]
+    fn hysteresis_keeps_the_last_decision() {
+        let config = config();
+        let mut detector = WallDetector::new();
+
+        // Near the threshold with a wall before
+        detector.update(&config, reading(100.0), reading(100.0), stale());
+        detector.start();
+        for _ in 0..3 {
+            detector.update(&config, reading(125.0), reading(125.0), stale());
+        }
+        assert!(detector.walls().front.present);
+
+        // And without one
+        detector.reset();
+        detector.update(&config, reading(200.0), reading(200.0), stale());
+        detector.start();
+        for _ in 0..3 {
+            detector.update(&config, reading(115.0), reading(115.0), stale());
+        }
+        assert!(!detector.walls().front.present);
+    }
+
+    #[test]
+    fn threshold_decides_without_history() {
+        let config = config();
+        let mut detector = WallDetector::new();
+
+        detector.update(&config, reading(115.0), reading(125.0), stale());
+
+        assert!(detector.walls().front.present);
+        assert!(!detector.walls().left.present);
+    }
+
+    #[test]
+    fn front_wall_ends_the_move_short() {
+        let config = config();
+        let ticks_per_mm = f64::from(config.mouse.ticks_per_mm());
+        let range = config.front_wall_distance as f32 + 20.0;
+
+        let target = front_wall_target(&config, 1000.0, 500.0, reading(range));
+        assert_eq!(target, Some(500.0 + 20.0 * ticks_per_mm));
+    }
+
+    #[test]
+    fn front_wall_past_the_end_is_ignored() {
+        let config = config();
+        let ticks_per_mm = f64::from(config.mouse.ticks_per_mm());
+        let range = config.front_wall_distance as f32 + 20.0;
+
+        // Within the look-ahead, but the move ends 10 mm short of the wall
+        let distance = 500.0 + 10.0 * ticks_per_mm;
+        assert!(f64::from(range) <= config.front_wall_lookahead);
+        assert_eq!(
+            front_wall_target(&config, distance, 500.0, reading(range)),
+            None
+        );
+    }
+
+    #[test]
+    fn front_wall_needs_a_fresh_reading_ahead() {
+        let config = config();
+        let range = config.front_wall_distance as f32 + 20.0;
+        let far = config.front_wall_lookahead as f32 + 1.0;
+
+        assert_eq!(front_wall_target(&config, 1000.0, 0.0, stale()), None);
+        assert_eq!(front_wall_target(&config, 1000.0, 0.0, reading(far)), None);
+        assert_eq!(front_wall_target(&config, 0.0, 0.0, reading(range)), None);
+    }
+}
@@ -4,8 +4,10 @@ MEMORY
   /* TODO Adjust these memory regions to match your device memory layout */
   /* These values correspond to the LM3S6965, one of the few devices QEMU can emulate */
   //FLASH : ORIGIN = 0x08000000, LENGTH = 64K
-  FLASH : ORIGIN = 0x08000000, LENGTH = 1024K
-  RAM : ORIGIN = 0x20000000, LENGTH = 12K
+  /* The last 128K sector (0x080E0000) is kept for the saved config */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 896K
+  /* SRAM1 and SRAM2 of the STM32F405, one after the other */
+  RAM : ORIGIN = 0x20000000, LENGTH = 128K
 }
 
 /* This is where the call stack will be allocated. */
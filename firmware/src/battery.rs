@@ -1,12 +1,35 @@
+/*!
+ *  Battery voltage and charge
+ *
+ *  The pack is a 2S LiPo, read on PB1 through a divider. The voltage is
+ *  averaged over a fraction of a second so the motors pulling it down for a
+ *  moment don't set off the warnings.
+ */
+use core::fmt::Write;
+
+use ignore_result::Ignore;
+
 use stm32f4xx_hal::stm32 as stm32f405;
 
-const DEAD_VOLTAGE: u16 = 2000;
-const DEAD_TIME: u32 = 5000;
+use crate::uart::Command;
+use crate::uart::Uart;
+
+use micromouse_core::battery::{
+    pack_mv, state_of_charge, BatteryConfig, BatteryLevel,
+};
+
+/// How many samples go into the average, and how far apart they are in ms
+const SAMPLES: usize = 32;
+const SAMPLE_PERIOD: u32 = 10;
 
 pub struct Battery {
     adc: stm32f405::ADC1,
-    last_alive: Option<u32>,
-    last_update: Option<u32>,
+    samples: [u16; SAMPLES],
+    len: usize,
+    next: usize,
+    last_sample: Option<u32>,
+    pack_mv: u32,
+    level: BatteryLevel,
 }
 
 impl Battery {
@@ -28,8 +51,12 @@ impl Battery {
 
         Battery {
             adc,
-            last_alive: None,
-            last_update: None,
+            samples: [0; SAMPLES],
+            len: 0,
+            next: 0,
+            last_sample: None,
+            pack_mv: 0,
+            level: BatteryLevel::Ok,
         }
     }
 
@@ -39,18 +66,58 @@ impl Battery {
         raw
     }
 
-    pub fn update(&mut self, now: u32) {
-        if self.raw() > DEAD_VOLTAGE {
-            self.last_alive = Some(now);
+    pub fn update(&mut self, now: u32, config: &BatteryConfig) {
+        if let Some(last) = self.last_sample {
+            if now - last < SAMPLE_PERIOD {
+                return;
+            }
         }
 
-        self.last_update = Some(now);
+        self.samples[self.next] = self.raw();
+        self.next = (self.next + 1) % SAMPLES;
+        self.len = (self.len + 1).min(SAMPLES);
+        self.last_sample = Some(now);
+
+        let sum: u32 =
+            self.samples[..self.len].iter().map(|&s| u32::from(s)).sum();
+        let average = (sum / self.len as u32) as u16;
+
+        self.pack_mv = pack_mv(average, config);
+        self.level = self.level.next(self.pack_mv, config);
     }
 
-    pub fn is_dead(&self) -> bool {
-        match (self.last_alive, self.last_update) {
-            (Some(alive), Some(update)) => update - alive > DEAD_TIME,
-            _ => true,
-        }
+    /// The averaged pack voltage in mV
+    pub fn pack_mv(&self) -> u32 {
+        self.pack_mv
+    }
+
+    pub fn state_of_charge(&self) -> f32 {
+        state_of_charge(self.pack_mv)
+    }
+
+    pub fn level(&self) -> BatteryLevel {
+        self.level
+    }
+}
+
+impl Command for Battery {
+    fn keyword_command(&self) -> &str {
+        "battery"
+    }
+
+    fn handle_command<'a, I: Iterator<Item = &'a str>>(
+        &mut self,
+        uart: &mut Uart,
+        _args: I,
+    ) {
+        writeln!(
+            uart,
+            "battery: {} mV {:.0} % {:?} raw: {}",
+            self.pack_mv,
+            self.state_of_charge(),
+            self.level,
+            self.raw()
+        )
+        .ignore();
     }
 }
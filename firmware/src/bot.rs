@@ -1,114 +1,134 @@
+use core::f32::consts::FRAC_PI_2;
 use core::fmt::Write;
 
-use stm32f4xx_hal::gpio::{gpioa, gpiob, gpioc, Alternate, AF4};
-use stm32f4xx_hal::i2c::I2c;
-use stm32f4xx_hal::stm32 as stm32f405;
-
 use ignore_result::Ignore;
 
-use pid_control::Controller;
-use pid_control::PIDController;
-
-use crate::motors::left::LeftEncoder;
-use crate::motors::left::LeftMotor;
-use crate::motors::right::RightEncoder;
-use crate::motors::right::RightMotor;
-
+use crate::motors::control::gains_command;
+use crate::motors::control::motor_control;
+use crate::motors::control::MotorControl;
+use crate::motors::velocity::WheelVelocity;
 use crate::motors::Encoder;
 use crate::motors::Motor;
 
-use crate::vl6180x::VL6180x;
+use micromouse_core::characterize::Characterize;
+use micromouse_core::characterize::Phase;
+use micromouse_core::distance::AmbientSample;
+use micromouse_core::distance::DistanceSample;
+use micromouse_core::distance::DistanceSensor;
+use micromouse_core::distance::SensorCalibration;
+use micromouse_core::filter::DistanceFilter;
+use micromouse_core::filter::FilteredDistance;
+use micromouse_core::vl6180x::AlsGain;
+use micromouse_core::vl6180x::Vl6180xExt;
 
 use crate::uart::Command;
 use crate::uart::Uart;
 
-use crate::config::BotConfig;
-
-type FrontDistance = VL6180x<
-    I2c<
-        stm32f405::I2C1,
-        (gpiob::PB8<Alternate<AF4>>, gpiob::PB9<Alternate<AF4>>),
-    >,
->;
-
-type LeftDistance = VL6180x<
-    I2c<
-        stm32f405::I2C2,
-        (gpiob::PB10<Alternate<AF4>>, gpiob::PB11<Alternate<AF4>>),
-    >,
->;
-
-type RightDistance = VL6180x<
-    I2c<
-        stm32f405::I2C3,
-        (gpioa::PA8<Alternate<AF4>>, gpioc::PC9<Alternate<AF4>>),
-    >,
->;
-
-pub struct Bot {
-    left_pid: PIDController,
-    left_motor: LeftMotor,
-    left_encoder: LeftEncoder,
-    left_velocity: f64,
-    left_power: f64,
-    last_left_pos: f64,
-
-    right_pid: PIDController,
-    right_motor: RightMotor,
-    right_encoder: RightEncoder,
-    right_velocity: f64,
-    right_power: f64,
-    last_right_pos: f64,
-
-    front_distance: FrontDistance,
-    left_distance: LeftDistance,
-    right_distance: RightDistance,
+use micromouse_core::config::BotConfig;
+
+use crate::odometry::Odometry;
+use crate::odometry::Pose;
+
+/// The default period between ambient light samples in interleaved mode, in
+/// ms. It has to fit the integration period and a range measurement.
+const ALS_PERIOD: u16 = 500;
+
+/// The most the velocity controllers ask of a motor, in volts. This is about
+/// half power on a charged battery.
+const MAX_MOTOR_VOLTS: f64 = 4.0;
+
+/// What `bot characterize start` ramps the motors up to, in volts
+const CHARACTERIZE_VOLTS: f32 = 3.0;
+
+pub struct Bot<LM, LE, RM, RE, FD, LD, RD>
+where
+    LM: Motor,
+    LE: Encoder,
+    RM: Motor,
+    RE: Encoder,
+    FD: DistanceSensor,
+    LD: DistanceSensor,
+    RD: DistanceSensor,
+{
+    left: MotorControl<LM, LE>,
+    right: MotorControl<RM, RE>,
+
+    front_distance: FD,
+    left_distance: LD,
+    right_distance: RD,
+
+    front_filter: DistanceFilter,
+    left_filter: DistanceFilter,
+    right_filter: DistanceFilter,
+
+    odometry: Odometry,
 
     last_update: u32,
 
+    characterize: Option<Characterize>,
+
     pub config: BotConfig,
 }
 
-impl Bot {
+impl<LM, LE, RM, RE, FD, LD, RD> Bot<LM, LE, RM, RE, FD, LD, RD>
+where
+    LM: Motor,
+    LE: Encoder,
+    RM: Motor,
+    RE: Encoder,
+    FD: DistanceSensor,
+    LD: DistanceSensor,
+    RD: DistanceSensor,
+{
     pub fn new(
-        left_motor: LeftMotor,
-        left_encoder: LeftEncoder,
-        right_motor: RightMotor,
-        right_encoder: RightEncoder,
-        mut front_distance: FrontDistance,
-        mut left_distance: LeftDistance,
-        mut right_distance: RightDistance,
+        left_motor: LM,
+        left_encoder: LE,
+        right_motor: RM,
+        right_encoder: RE,
+        front_distance: FD,
+        left_distance: LD,
+        right_distance: RD,
         config: BotConfig,
-    ) -> Bot {
-        let mut left_pid =
-            PIDController::new(config.left_p, config.left_i, config.left_d);
-        left_pid.set_limits(-5000.0, 5000.0);
-
-        let mut right_pid =
-            PIDController::new(config.right_p, config.right_i, config.right_d);
-        right_pid.set_limits(-5000.0, 5000.0);
-
-        front_distance.start_ranging();
-        left_distance.start_ranging();
-        right_distance.start_ranging();
-
-        Bot {
-            left_pid,
+    ) -> Bot<LM, LE, RM, RE, FD, LD, RD> {
+        let mut left = motor_control(
+            config.left_p,
+            config.left_i,
+            config.left_d,
+            MAX_MOTOR_VOLTS,
             left_motor,
             left_encoder,
-            left_velocity: 0.0,
-            left_power: 0.0,
-            last_left_pos: 0.0,
-            right_pid,
+        );
+        left.set_feedforward(config.left_feedforward);
+        left.set_velocity(0.0);
+
+        let mut right = motor_control(
+            config.right_p,
+            config.right_i,
+            config.right_d,
+            MAX_MOTOR_VOLTS,
             right_motor,
             right_encoder,
-            right_velocity: 0.0,
-            right_power: 0.0,
+        );
+        right.set_feedforward(config.right_feedforward);
+        right.set_velocity(0.0);
+
+        Bot {
+            left,
+            right,
             front_distance,
             left_distance,
             right_distance,
-            last_right_pos: 0.0,
+            front_filter: DistanceFilter::new(),
+            left_filter: DistanceFilter::new(),
+            right_filter: DistanceFilter::new(),
+            // Start out facing north, into the maze
+            odometry: Odometry::new(Pose {
+                x: 0.0,
+                y: 0.0,
+                heading: FRAC_PI_2,
+            }),
             last_update: 0,
+            characterize: None,
             config,
         }
     }
@@ -118,71 +138,100 @@ impl Bot {
         linear_velocity: f64,
         rotational_velocity: f64,
     ) {
-        self.left_pid
-            .set_target(linear_velocity + rotational_velocity / 2.0);
-        self.right_pid
-            .set_target(linear_velocity - rotational_velocity / 2.0);
-
-        if linear_velocity == 0.0 && rotational_velocity == 0.0 {
-            self.left_pid.reset();
-            self.right_pid.reset();
-        }
+        self.left
+            .set_velocity(linear_velocity + rotational_velocity / 2.0);
+        self.right
+            .set_velocity(linear_velocity - rotational_velocity / 2.0);
     }
 
     pub fn update(&mut self, now: u32) {
         let delta_time = now - self.last_update;
 
-        self.front_distance.update();
-        self.left_distance.update();
-        self.right_distance.update();
+        // Failures are counted by the sensors, which keep the last good
+        // sample
+        self.front_distance.update(now).ignore();
+        self.left_distance.update(now).ignore();
+        self.right_distance.update(now).ignore();
+
+        let filter = &self.config.distance_filter;
+        self.front_filter
+            .update(filter, self.front_distance.sample(), now);
+        self.left_filter
+            .update(filter, self.left_distance.sample(), now);
+        self.right_filter
+            .update(filter, self.right_distance.sample(), now);
 
         if delta_time > 10 {
-            self.left_pid.p_gain = self.config.left_p;
-            self.left_pid.i_gain = self.config.left_i;
-            self.left_pid.d_gain = self.config.left_d;
+            let config = &self.config;
+            self.left.pid.set_gains(
+                config.left_p,
+                config.left_i,
+                config.left_d,
+            );
+            self.left.set_feedforward(config.left_feedforward);
+            self.left.set_velocity_filter(config.velocity_filter);
+            self.right.pid.set_gains(
+                config.right_p,
+                config.right_i,
+                config.right_d,
+            );
+            self.right.set_feedforward(config.right_feedforward);
+            self.right.set_velocity_filter(config.velocity_filter);
+
+            self.left.update(delta_time);
+            self.right.update(delta_time);
+
+            // The test drives the motors itself until it is done
+            if let Some(ref mut test) = self.characterize {
+                if test.phase() != Phase::Done {
+                    let volts = test.update(
+                        now,
+                        self.left.velocity(),
+                        self.right.velocity(),
+                    );
+                    self.left.set_volts(volts);
+                    self.right.set_volts(volts);
+
+                    if test.phase() == Phase::Done {
+                        self.left.set_velocity(0.0);
+                        self.right.set_velocity(0.0);
+                    }
+                }
+            }
 
             let left_pos = self.left_pos();
-
-            self.left_velocity =
-                (left_pos - self.last_left_pos) / delta_time as f64;
-
-            self.left_power =
-                self.left_pid.update(self.left_velocity, delta_time as f64);
-
-            self.left_motor.change_power(self.left_power as i32);
-
-            self.last_left_pos = left_pos;
-
-            self.right_pid.p_gain = self.config.right_p;
-            self.right_pid.i_gain = self.config.right_i;
-            self.right_pid.d_gain = self.config.right_d;
-
             let right_pos = self.right_pos();
 
-            self.right_velocity =
-                (right_pos - self.last_right_pos) / delta_time as f64;
-
-            self.right_power = self
-                .right_pid
-                .update(self.right_velocity, delta_time as f64);
-
-            self.right_motor.change_power(self.right_power as i32);
-
-            self.last_right_pos = right_pos;
+            self.odometry.update(
+                &self.config.mouse,
+                left_pos as f32,
+                right_pos as f32,
+            );
 
             self.last_update = now;
         }
     }
 
     pub fn reset(&mut self) {
-        self.last_left_pos = 0.0;
-        self.left_encoder.reset();
+        // Catch the pose up before the encoder counts are lost
+        self.odometry.update(
+            &self.config.mouse,
+            self.left_pos() as f32,
+            self.right_pos() as f32,
+        );
+        self.odometry.encoders_reset();
+
+        self.left.reset();
+        self.right.reset();
+    }
 
-        self.last_right_pos = 0.0;
-        self.right_encoder.reset();
+    /// The pose of the mouse since it was turned on, or last corrected
+    pub fn pose(&self) -> Pose {
+        self.odometry.pose()
+    }
 
-        self.left_pid.reset();
-        self.right_pid.reset();
+    pub fn odometry(&mut self) -> &mut Odometry {
+        &mut self.odometry
     }
 
     pub fn linear_pos(&self) -> f64 {
@@ -194,59 +243,408 @@ impl Bot {
     }
 
     pub fn linear_velocity(&self) -> f64 {
-        (self.left_velocity + self.right_velocity) / 2.0
+        (self.left.velocity() + self.right.velocity()) / 2.0
     }
 
     pub fn spin_velocity(&self) -> f64 {
-        self.left_velocity - self.right_velocity
+        self.left.velocity() - self.right.velocity()
     }
 
     pub fn left_pos(&self) -> f64 {
-        self.left_encoder.count() as f64
+        f64::from(self.left.position())
     }
 
     pub fn right_pos(&self) -> f64 {
-        self.right_encoder.count() as f64
+        f64::from(self.right.position())
     }
 
     pub fn left_velocity(&self) -> f64 {
-        self.left_velocity
+        self.left.velocity()
     }
 
     pub fn right_velocity(&self) -> f64 {
-        self.right_velocity
+        self.right.velocity()
+    }
+
+    /// The left wheel velocity in mm/s, and how far to trust it
+    pub fn left_wheel_velocity(&self) -> WheelVelocity {
+        self.left
+            .velocity_estimator()
+            .wheel_velocity(&self.config.mouse)
+    }
+
+    pub fn right_wheel_velocity(&self) -> WheelVelocity {
+        self.right
+            .velocity_estimator()
+            .wheel_velocity(&self.config.mouse)
     }
 
     pub fn left_target(&self) -> f64 {
-        self.left_pid.target()
+        self.left.target()
     }
 
     pub fn right_target(&self) -> f64 {
-        self.right_pid.target()
+        self.right.target()
+    }
+
+    /// The voltage asked of the left motor
+    pub fn left_volts(&self) -> f64 {
+        self.left.volts()
+    }
+
+    pub fn right_volts(&self) -> f64 {
+        self.right.volts()
+    }
+
+    /// The PWM count the left motor is driven with, out of `PWM_MAX`
+    pub fn left_duty(&self) -> i32 {
+        self.left.duty()
     }
 
-    pub fn left_power(&self) -> f64 {
-        self.left_power
+    pub fn right_duty(&self) -> i32 {
+        self.right.duty()
     }
 
-    pub fn right_power(&self) -> f64 {
-        self.right_power
+    /**
+     *  Keep the motor voltages right as the battery runs down
+     *
+     *  Until this is called, or if the battery reads too low to be there at
+     *  all, a nominal voltage is used instead.
+     */
+    pub fn set_battery_mv(&mut self, battery_mv: u32) {
+        let battery_volts = f64::from(battery_mv) / 1000.0;
+        self.left.set_battery_volts(battery_volts);
+        self.right.set_battery_volts(battery_volts);
     }
 
+    /// The filtered front range in mm, stale or not
     pub fn front_distance(&self) -> f64 {
-        self.front_distance.range() as f64
+        f64::from(self.front_filter.output().range)
     }
 
     pub fn left_distance(&self) -> f64 {
-        self.left_distance.range() as f64
+        f64::from(self.left_filter.output().range)
     }
 
     pub fn right_distance(&self) -> f64 {
-        self.right_distance.range() as f64
+        f64::from(self.right_filter.output().range)
+    }
+
+    pub fn front_filtered(&self) -> FilteredDistance {
+        self.front_filter.output()
+    }
+
+    pub fn left_filtered(&self) -> FilteredDistance {
+        self.left_filter.output()
+    }
+
+    pub fn right_filtered(&self) -> FilteredDistance {
+        self.right_filter.output()
+    }
+
+    /// The latest raw front sample, before filtering
+    pub fn front_sample(&self) -> DistanceSample {
+        self.front_distance.sample()
+    }
+
+    pub fn left_sample(&self) -> DistanceSample {
+        self.left_distance.sample()
+    }
+
+    pub fn right_sample(&self) -> DistanceSample {
+        self.right_distance.sample()
+    }
+
+    /**
+     *  Find the motor constants from how the wheels follow a voltage
+     *
+     *  - `start [volts]` ramps both motors up to `volts`, then steps them
+     *  - `stop` ends the test early
+     *  - `save` puts the fit in the config, and `config save` keeps it
+     *
+     *  Otherwise it prints how far the test has got and the fit so far.
+     *  Nothing else should move the mouse while it runs.
+     */
+    fn characterize_command<'a, I: Iterator<Item = &'a str>>(
+        &mut self,
+        uart: &mut Uart,
+        mut args: I,
+    ) {
+        match args.next() {
+            Some("start") => match args.next().map(str::parse::<f32>) {
+                Some(Ok(volts))
+                    if volts > 0.0 && f64::from(volts) <= MAX_MOTOR_VOLTS =>
+                {
+                    self.change_velocity(0.0, 0.0);
+                    self.characterize = Some(Characterize::new(volts));
+                }
+                None => {
+                    self.change_velocity(0.0, 0.0);
+                    self.characterize =
+                        Some(Characterize::new(CHARACTERIZE_VOLTS));
+                }
+                _ => {
+                    writeln!(
+                        uart,
+                        "bot: characterize start [volts up to {}]",
+                        MAX_MOTOR_VOLTS
+                    )
+                    .ignore();
+                    return;
+                }
+            },
+            Some("stop") => {
+                self.characterize = None;
+                self.change_velocity(0.0, 0.0);
+            }
+            Some("save") => match self.characterize {
+                Some(ref test) if test.phase() == Phase::Done => {
+                    match (test.fit_left(), test.fit_right()) {
+                        (Some(left), Some(right)) => {
+                            self.config.left_feedforward = left;
+                            self.config.right_feedforward = right;
+                        }
+                        _ => {
+                            writeln!(uart, "bot: not enough data to fit")
+                                .ignore();
+                            return;
+                        }
+                    }
+                }
+                _ => {
+                    writeln!(uart, "bot: characterize hasn't finished")
+                        .ignore();
+                    return;
+                }
+            },
+            None => {}
+            Some(_) => {
+                writeln!(uart, "bot: characterize [start [volts]|stop|save]")
+                    .ignore();
+                return;
+            }
+        }
+
+        let test = match self.characterize {
+            Some(ref test) => test,
+            None => {
+                writeln!(uart, "characterize: not running").ignore();
+                return;
+            }
+        };
+
+        writeln!(
+            uart,
+            "characterize: {:?} samples: {}",
+            test.phase(),
+            test.samples()
+        )
+        .ignore();
+
+        let fits = [("left", test.fit_left()), ("right", test.fit_right())];
+        for (name, fit) in fits.iter() {
+            match fit {
+                Some(fit) => writeln!(
+                    uart,
+                    "{}: ks: {} kv: {} ka: {}",
+                    name, fit.ks, fit.kv, fit.ka
+                )
+                .ignore(),
+                None => writeln!(uart, "{}: not enough data", name).ignore(),
+            }
+        }
+    }
+}
+
+impl<LM, LE, RM, RE, FD, LD, RD> Bot<LM, LE, RM, RE, FD, LD, RD>
+where
+    LM: Motor,
+    LE: Encoder,
+    RM: Motor,
+    RE: Encoder,
+    FD: Vl6180xExt,
+    LD: Vl6180xExt,
+    RD: Vl6180xExt,
+{
+    /// Give the sensors the calibration in the config, if they don't have it
+    pub fn calibrate_sensors(&mut self) {
+        calibrate(&mut self.front_distance, self.config.front_calibration);
+        calibrate(&mut self.left_distance, self.config.left_calibration);
+        calibrate(&mut self.right_distance, self.config.right_calibration);
+    }
+
+    pub fn front_ambient(&self) -> Option<AmbientSample> {
+        self.front_distance.ambient()
+    }
+
+    pub fn left_ambient(&self) -> Option<AmbientSample> {
+        self.left_distance.ambient()
+    }
+
+    pub fn right_ambient(&self) -> Option<AmbientSample> {
+        self.right_distance.ambient()
+    }
+}
+
+/// Apply a calibration if the sensor doesn't have it yet
+fn calibrate<D: Vl6180xExt>(sensor: &mut D, calibration: SensorCalibration) {
+    if sensor.calibration() != calibration {
+        // Failures are counted by the sensor, and tried again after the next
+        // config command
+        sensor.set_calibration(calibration).ignore();
     }
 }
 
-impl Command for Bot {
+/**
+ *  Calibrate one sensor with the mouse a known distance from a wall
+ *
+ *  - `offset <mm>` with a white wall, ideally 50 mm away
+ *  - `crosstalk <mm>` with a dark wall, ideally 100 mm away, after the
+ *    offset
+ *
+ *  The result goes in the config, and `config save` keeps it.
+ */
+fn calibrate_command<'a, D: Vl6180xExt, I: Iterator<Item = &'a str>>(
+    uart: &mut Uart,
+    name: &str,
+    sensor: &mut D,
+    calibration: &mut SensorCalibration,
+    mut args: I,
+) {
+    let result = match (args.next(), args.next().map(str::parse::<u16>)) {
+        (Some("offset"), Some(Ok(distance))) if distance > 0 => {
+            sensor.calibrate_offset(distance)
+        }
+        (Some("crosstalk"), Some(Ok(distance))) if distance > 0 => {
+            sensor.calibrate_crosstalk(distance)
+        }
+        (None, None) => Ok(sensor.calibration()),
+        _ => {
+            writeln!(
+                uart,
+                "bot: calibrate {} [offset <mm>|crosstalk <mm>]",
+                name
+            )
+            .ignore();
+            return;
+        }
+    };
+
+    match result {
+        Ok(new) => {
+            *calibration = new;
+            writeln!(
+                uart,
+                "{}: offset: {} mm crosstalk: {} Mcps",
+                name, new.offset, new.crosstalk
+            )
+            .ignore();
+        }
+        Err(e) => writeln!(uart, "bot: {} calibration: {:?}", name, e).ignore(),
+    }
+}
+
+/**
+ *  Settings for one sensor
+ *
+ *  - `scaling [1|2|3]` sets or prints the range scaling
+ *  - `als` prints the ambient light settings and the latest reading
+ *  - `als gain <gain>` and `als period <ms>` set up the ambient light
+ *  - `als on [period]` and `als off` interleave it with ranging or stop
+ */
+fn sensor_command<'a, D: Vl6180xExt, I: Iterator<Item = &'a str>>(
+    uart: &mut Uart,
+    name: &str,
+    sensor: &mut D,
+    mut args: I,
+) {
+    let result = match args.next() {
+        Some("scaling") => match args.next().map(str::parse::<u8>) {
+            Some(Ok(scaling)) => sensor.set_scaling(scaling),
+            Some(Err(_)) => {
+                writeln!(uart, "bot: scaling must be 1, 2 or 3").ignore();
+                return;
+            }
+            None => Ok(()),
+        },
+        Some("als") => match (args.next(), args.next()) {
+            (Some("gain"), Some(gain)) => {
+                match gain.parse().ok().and_then(AlsGain::from_nominal) {
+                    Some(gain) => sensor.set_als_gain(gain),
+                    None => {
+                        write!(uart, "bot: gain must be one of").ignore();
+                        for gain in AlsGain::ALL.iter() {
+                            write!(uart, " {}", gain.nominal()).ignore();
+                        }
+                        writeln!(uart).ignore();
+                        return;
+                    }
+                }
+            }
+            (Some("period"), Some(period)) => match period.parse() {
+                Ok(period) => sensor.set_als_integration_period(period),
+                Err(_) => {
+                    writeln!(uart, "bot: invalid period").ignore();
+                    return;
+                }
+            },
+            (Some("on"), period) => match period.map(str::parse) {
+                Some(Ok(period)) => sensor.set_interleaved(Some(period)),
+                Some(Err(_)) => {
+                    writeln!(uart, "bot: invalid period").ignore();
+                    return;
+                }
+                None => sensor.set_interleaved(Some(ALS_PERIOD)),
+            },
+            (Some("off"), None) => sensor.set_interleaved(None),
+            (None, None) => Ok(()),
+            _ => {
+                writeln!(
+                    uart,
+                    "bot: sensor {} als [gain <gain>|period <ms>|on [ms]|off]",
+                    name
+                )
+                .ignore();
+                return;
+            }
+        },
+        _ => {
+            writeln!(uart, "bot: sensor {} <scaling|als>", name).ignore();
+            return;
+        }
+    };
+
+    if let Err(e) = result {
+        writeln!(uart, "bot: {}: {:?}", name, e).ignore();
+        return;
+    }
+
+    let als = sensor.als_settings();
+    writeln!(
+        uart,
+        "{}: scaling: {}x max range: {} mm als gain: {} integration: {} ms \
+         interleaved: {:?} ambient: {:?}",
+        name,
+        sensor.scaling(),
+        sensor.max_range(),
+        als.gain.nominal(),
+        als.integration_period,
+        als.interleaved,
+        sensor.ambient(),
+    )
+    .ignore();
+}
+
+impl<LM, LE, RM, RE, FD, LD, RD> Command for Bot<LM, LE, RM, RE, FD, LD, RD>
+where
+    LM: Motor,
+    LE: Encoder,
+    RM: Motor,
+    RE: Encoder,
+    FD: Vl6180xExt,
+    LD: Vl6180xExt,
+    RD: Vl6180xExt,
+{
     fn keyword_command(&self) -> &str {
         "bot"
     }
@@ -260,6 +658,24 @@ impl Command for Bot {
 
         if command == Some(self.config.keyword_command()) {
             self.config.handle_command(uart, args);
+
+            // Pick up calibration changed through the config
+            self.calibrate_sensors();
+        } else if command == Some("left") {
+            gains_command(uart, &mut self.left.pid, args);
+
+            // Keep the gains for `config save`
+            let (p, i, d) = self.left.pid.gains();
+            self.config.left_p = p;
+            self.config.left_i = i;
+            self.config.left_d = d;
+        } else if command == Some("right") {
+            gains_command(uart, &mut self.right.pid, args);
+
+            let (p, i, d) = self.right.pid.gains();
+            self.config.right_p = p;
+            self.config.right_i = i;
+            self.config.right_d = d;
         } else {
             match command {
                 Some("spin") => {
@@ -280,6 +696,126 @@ impl Command for Bot {
                         writeln!(uart, "bot: value needed").ignore();
                     }
                 }
+                Some("pose") => match args.next() {
+                    Some("set") => {
+                        let mut values = args.map(|s| s.parse::<f32>().ok());
+
+                        if let (
+                            Some(Some(x)),
+                            Some(Some(y)),
+                            Some(Some(heading)),
+                        ) = (values.next(), values.next(), values.next())
+                        {
+                            self.odometry.set_pose(Pose { x, y, heading });
+                        } else {
+                            writeln!(uart, "bot: pose set <x> <y> <heading>")
+                                .ignore();
+                        }
+                    }
+                    None => {
+                        let pose = self.pose();
+                        writeln!(
+                            uart,
+                            "x: {} y: {} heading: {}",
+                            pose.x, pose.y, pose.heading
+                        )
+                        .ignore();
+                    }
+                    Some(c) => {
+                        writeln!(uart, "bot: unknown pose command: {}", c)
+                            .ignore()
+                    }
+                },
+                Some("sensors") => {
+                    let sensors: [(&str, &dyn Vl6180xExt, _); 3] = [
+                        ("front", &self.front_distance, self.front_filtered()),
+                        ("left", &self.left_distance, self.left_filtered()),
+                        ("right", &self.right_distance, self.right_filtered()),
+                    ];
+
+                    for (name, sensor, filtered) in sensors.iter() {
+                        let sample = sensor.sample();
+                        writeln!(
+                            uart,
+                            "{}: range: {} status: {:?} time: {} errors: {} \
+                             scaling: {}x filtered: {} variance: {} \
+                             stale: {}",
+                            name,
+                            sample.range,
+                            sample.status,
+                            sample.timestamp,
+                            sensor.error_count(),
+                            sensor.scaling(),
+                            filtered.range,
+                            filtered.variance,
+                            filtered.stale,
+                        )
+                        .ignore();
+                    }
+                }
+                Some("calibrate") => match args.next() {
+                    Some("front") => calibrate_command(
+                        uart,
+                        "front",
+                        &mut self.front_distance,
+                        &mut self.config.front_calibration,
+                        args,
+                    ),
+                    Some("left") => calibrate_command(
+                        uart,
+                        "left",
+                        &mut self.left_distance,
+                        &mut self.config.left_calibration,
+                        args,
+                    ),
+                    Some("right") => calibrate_command(
+                        uart,
+                        "right",
+                        &mut self.right_distance,
+                        &mut self.config.right_calibration,
+                        args,
+                    ),
+                    _ => writeln!(uart, "bot: calibrate <front|left|right>")
+                        .ignore(),
+                },
+                Some("sensor") => match args.next() {
+                    Some("front") => sensor_command(
+                        uart,
+                        "front",
+                        &mut self.front_distance,
+                        args,
+                    ),
+                    Some("left") => sensor_command(
+                        uart,
+                        "left",
+                        &mut self.left_distance,
+                        args,
+                    ),
+                    Some("right") => sensor_command(
+                        uart,
+                        "right",
+                        &mut self.right_distance,
+                        args,
+                    ),
+                    _ => writeln!(uart, "bot: sensor <front|left|right>")
+                        .ignore(),
+                },
+                Some("velocity") => {
+                    let wheels = [
+                        ("left", self.left_wheel_velocity()),
+                        ("right", self.right_wheel_velocity()),
+                    ];
+
+                    for (name, wheel) in wheels.iter() {
+                        writeln!(
+                            uart,
+                            "{}: {} mm/s variance: {}",
+                            name, wheel.velocity, wheel.variance
+                        )
+                        .ignore();
+                    }
+                }
+                Some("characterize") => self.characterize_command(uart, args),
                 Some(c) => {
                     writeln!(uart, "bot: unknown command: {}", c).ignore()
                 }
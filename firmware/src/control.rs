@@ -3,20 +3,39 @@ use core::fmt::Write;
 
 use ignore_result::Ignore;
 
+use arrayvec::{ArrayVec, CapacityError};
+
 use pid_control::Controller;
 use pid_control::DerivativeMode;
 use pid_control::PIDController;
 
 use crate::bot::Bot;
-use crate::config::BotConfig;
+use micromouse_core::config::BotConfig;
+
+use crate::motors::Encoder;
+use crate::motors::Motor;
+
+use micromouse_core::distance::DistanceSensor;
+use micromouse_core::filter::FilteredDistance;
+
+use crate::pid::Pid;
+
+use micromouse_core::path::Path;
+use micromouse_core::path::Segment;
+use micromouse_core::path::PATH_BUF_LEN;
+use micromouse_core::profile::Profile;
+use micromouse_core::vl6180x::Vl6180xExt;
+use micromouse_core::walls::front_wall_target;
 
 use crate::uart::Command;
 use crate::uart::Uart;
 
 pub struct SpinMove {
     spin_pid: PIDController,
+    profile: Profile,
     err: f64,
     settle: u32,
+    start: Option<u32>,
     last_ok: u32,
     last_update: u32,
 }
@@ -27,12 +46,22 @@ impl SpinMove {
             PIDController::new(config.spin_p, config.spin_i, config.spin_d);
         spin_pid.set_limits(-2.0, 2.0);
         spin_pid.d_mode = DerivativeMode::OnMeasurement;
-        spin_pid.set_target(target);
+        spin_pid.set_target(0.0);
+
+        let profile = Profile::new(
+            target,
+            0.0,
+            0.0,
+            config.spin_max_velocity,
+            config.spin_max_accel,
+        );
 
         SpinMove {
             spin_pid,
+            profile,
             err: config.spin_err,
             settle: config.spin_settle,
+            start: None,
             last_update: 0,
             last_ok: 0,
         }
@@ -44,12 +73,32 @@ impl SpinMove {
      *  Returns true if the spin controller is done,
      *  false if it is not done.
      */
-    pub fn update(&mut self, now: u32, bot: &mut Bot) -> bool {
+    pub fn update<LM, LE, RM, RE, FD, LD, RD>(
+        &mut self,
+        now: u32,
+        bot: &mut Bot<LM, LE, RM, RE, FD, LD, RD>,
+    ) -> bool
+    where
+        LM: Motor,
+        LE: Encoder,
+        RM: Motor,
+        RE: Encoder,
+        FD: DistanceSensor,
+        LD: DistanceSensor,
+        RD: DistanceSensor,
+    {
+        let start = *self.start.get_or_insert(now);
+        let time = (now - start) as f64;
+
+        let (target_pos, target_vel) = self.profile.setpoint(time);
+        self.spin_pid.set_target(target_pos);
+
         let spin_pos = bot.spin_pos();
 
-        let error = spin_pos - self.spin_pid.target();
+        let error = spin_pos - self.profile.distance();
 
-        if error > self.err || error < -self.err {
+        if !self.profile.is_done(time) || error > self.err || error < -self.err
+        {
             self.last_ok = now;
         }
 
@@ -58,7 +107,12 @@ impl SpinMove {
             true
         } else {
             let delta_time = now - self.last_update;
-            let spin_vel = self.spin_pid.update(spin_pos, delta_time as f64);
+
+            // The spin velocity given to the bot is the difference between
+            // the wheels, which is twice the rate of change of spin_pos
+            let spin_vel = 2.0 * target_vel
+                + self.spin_pid.update(spin_pos, delta_time as f64);
+
             bot.change_velocity(0.0, spin_vel);
             self.last_update = now;
             false
@@ -69,7 +123,8 @@ impl SpinMove {
 pub struct LinearMove {
     linear_pid: PIDController,
     spin_pid: PIDController,
-    linear_target: f64,
+    profile: Profile,
+    start: Option<u32>,
     last_linear_ok: bool,
     last_spin_ok: bool,
     err: f64,
@@ -79,7 +134,17 @@ pub struct LinearMove {
 }
 
 impl LinearMove {
-    pub fn new(target: f64, config: &BotConfig) -> LinearMove {
+    /**
+     *  Create a new linear move
+     *
+     *  The start velocity should be the velocity the bot is already moving
+     *  at, so that moves with a non-zero end velocity can be chained.
+     */
+    pub fn new(
+        target: f64,
+        start_velocity: f64,
+        config: &BotConfig,
+    ) -> LinearMove {
         let mut linear_pid = PIDController::new(
             config.linear_p,
             config.linear_i,
@@ -88,7 +153,7 @@ impl LinearMove {
 
         linear_pid.set_limits(-2.0, 2.0);
         linear_pid.d_mode = DerivativeMode::OnMeasurement;
-        linear_pid.set_target(target);
+        linear_pid.set_target(0.0);
 
         let mut spin_pid = PIDController::new(
             config.linear_spin_p,
@@ -100,10 +165,19 @@ impl LinearMove {
         spin_pid.d_mode = DerivativeMode::OnMeasurement;
         spin_pid.set_target(0.0);
 
+        let profile = Profile::new(
+            target,
+            start_velocity,
+            config.linear_end_velocity,
+            config.linear_max_velocity,
+            config.linear_max_accel,
+        );
+
         LinearMove {
             linear_pid,
             spin_pid,
-            linear_target: target,
+            profile,
+            start: None,
             last_linear_ok: false,
             last_spin_ok: false,
             err: config.linear_err,
@@ -119,24 +193,56 @@ impl LinearMove {
      *  Returns true if the linear controller is done,
      *  false if it is not done.
      */
-    pub fn update(&mut self, now: u32, bot: &mut Bot) -> bool {
-        let front_distance = bot.front_distance();
-
-        let (linear_pos, linear_target, linear_err) =
-            if front_distance <= bot.config.cell_width {
-                (
-                    -front_distance * 9.0,
-                    -bot.config.front_wall_distance * 9.0,
-                    bot.config.linear_front_err * 9.0,
-                )
-            } else {
-                (bot.linear_pos(), self.linear_target, bot.config.linear_err)
-            };
+    pub fn update<LM, LE, RM, RE, FD, LD, RD>(
+        &mut self,
+        now: u32,
+        bot: &mut Bot<LM, LE, RM, RE, FD, LD, RD>,
+    ) -> bool
+    where
+        LM: Motor,
+        LE: Encoder,
+        RM: Motor,
+        RE: Encoder,
+        FD: DistanceSensor,
+        LD: DistanceSensor,
+        RD: DistanceSensor,
+    {
+        let start = *self.start.get_or_insert(now);
+        let time = (now - start) as f64;
+
+        let (mut target_pos, mut target_vel) = self.profile.setpoint(time);
+
+        let linear_pos = bot.linear_pos();
+        let ticks_per_mm = bot.config.mouse.ticks_per_mm() as f64;
+
+        // If there is a wall in front of where the move ends, don't let the
+        // setpoint go past it. With the front sensor scaled up, walls further
+        // than a cell ahead can be seen.
+        let wall_target = front_wall_target(
+            &bot.config,
+            self.profile.distance(),
+            linear_pos,
+            bot.front_filtered(),
+        );
 
-        self.linear_pid.set_target(linear_target);
+        let (linear_target, linear_err) = match wall_target {
+            Some(wall_target) => {
+                if target_pos > wall_target {
+                    target_pos = wall_target;
+                    target_vel = 0.0;
+                }
 
-        let linear_error = linear_pos - self.linear_pid.target();
-        let linear_ok = linear_error < linear_err && linear_error > -linear_err;
+                (wall_target, bot.config.linear_front_err * ticks_per_mm)
+            }
+            None => (self.profile.distance(), bot.config.linear_err),
+        };
+
+        self.linear_pid.set_target(target_pos);
+
+        let linear_error = linear_pos - linear_target;
+        let linear_ok = self.profile.is_done(time)
+            && linear_error < linear_err
+            && linear_error > -linear_err;
 
         if linear_ok && !self.last_linear_ok {
             self.linear_pid.reset();
@@ -144,8 +250,15 @@ impl LinearMove {
 
         self.last_linear_ok = linear_ok;
 
-        let left_distance = bot.left_distance();
-        let right_distance = bot.right_distance();
+        // Treat stale side readings as no wall at all
+        let side_distance = |filtered: FilteredDistance| {
+            filtered
+                .fresh_range()
+                .map_or(core::f64::INFINITY, f64::from)
+        };
+
+        let left_distance = side_distance(bot.left_filtered());
+        let right_distance = side_distance(bot.right_filtered());
 
         let width = left_distance + right_distance;
 
@@ -177,14 +290,20 @@ impl LinearMove {
             self.last_ok = now;
         }
 
-        if now - self.last_ok > self.settle {
+        let end_velocity = self.profile.end_velocity();
+
+        if end_velocity != 0.0 && self.profile.is_done(time) {
+            // Keep rolling into the next move instead of settling
+            bot.change_velocity(end_velocity, 0.0);
+            true
+        } else if now - self.last_ok > self.settle {
             bot.change_velocity(0.0, 0.0);
             true
         } else {
             let delta_time = now - self.last_update;
 
-            let linear_vel =
-                self.linear_pid.update(linear_pos, delta_time as f64);
+            let linear_vel = target_vel
+                + self.linear_pid.update(linear_pos, delta_time as f64);
 
             let spin_vel = self.spin_pid.update(spin_pos, delta_time as f64);
 
@@ -195,10 +314,86 @@ impl LinearMove {
     }
 }
 
+pub struct PathMove {
+    path: Path<Pid>,
+}
+
+impl PathMove {
+    pub fn new(
+        now: u32,
+        left_pos: f64,
+        right_pos: f64,
+        config: &BotConfig,
+    ) -> PathMove {
+        let left = config.mouse.ticks_to_mm(left_pos as f32);
+        let right = config.mouse.ticks_to_mm(right_pos as f32);
+
+        let mut pid =
+            PIDController::new(config.path_p, config.path_i, config.path_d);
+        pid.d_mode = DerivativeMode::OnError;
+
+        PathMove {
+            path: Path::new(Pid(pid), now, left, right),
+        }
+    }
+
+    pub fn add_segments(
+        &mut self,
+        segments: &[Segment],
+    ) -> Result<usize, CapacityError> {
+        self.path.add_segments(segments)
+    }
+
+    /**
+     *  Update the path controller
+     *
+     *  Returns true if there are no segments left to drive,
+     *  false if it is not done.
+     */
+    pub fn update<LM, LE, RM, RE, FD, LD, RD>(
+        &mut self,
+        now: u32,
+        bot: &mut Bot<LM, LE, RM, RE, FD, LD, RD>,
+    ) -> bool
+    where
+        LM: Motor,
+        LE: Encoder,
+        RM: Motor,
+        RE: Encoder,
+        FD: DistanceSensor,
+        LD: DistanceSensor,
+        RD: DistanceSensor,
+    {
+        let mouse = bot.config.mouse;
+
+        let left = mouse.ticks_to_mm(bot.left_pos() as f32);
+        let right = mouse.ticks_to_mm(bot.right_pos() as f32);
+
+        let (curvature, remaining) = self.path.update(&mouse, now, left, right);
+
+        if remaining == 0 {
+            bot.change_velocity(0.0, 0.0);
+            true
+        } else {
+            // path_velocity is in mm/s, the bot wants ticks/ms
+            let linear_vel =
+                mouse.mm_to_ticks(bot.config.path_velocity as f32) / 1000.0;
+
+            // The wheels are a wheelbase apart, so the difference between
+            // them is the linear velocity scaled by the curvature
+            let spin_vel = linear_vel * curvature * mouse.wheelbase;
+
+            bot.change_velocity(linear_vel as f64, spin_vel as f64);
+            false
+        }
+    }
+}
+
 enum CurrentMove {
     Idle,
     SpinMove(SpinMove),
     LinearMove(LinearMove),
+    Path(PathMove),
 }
 
 impl CurrentMove {
@@ -210,15 +405,35 @@ impl CurrentMove {
     }
 }
 
-pub struct Control {
-    bot: Bot,
+pub struct Control<LM, LE, RM, RE, FD, LD, RD>
+where
+    LM: Motor,
+    LE: Encoder,
+    RM: Motor,
+    RE: Encoder,
+    FD: DistanceSensor,
+    LD: DistanceSensor,
+    RD: DistanceSensor,
+{
+    bot: Bot<LM, LE, RM, RE, FD, LD, RD>,
     current_move: CurrentMove,
 
     last_update: u32,
 }
 
-impl Control {
-    pub fn new(bot: Bot) -> Control {
+impl<LM, LE, RM, RE, FD, LD, RD> Control<LM, LE, RM, RE, FD, LD, RD>
+where
+    LM: Motor,
+    LE: Encoder,
+    RM: Motor,
+    RE: Encoder,
+    FD: DistanceSensor,
+    LD: DistanceSensor,
+    RD: DistanceSensor,
+{
+    pub fn new(
+        bot: Bot<LM, LE, RM, RE, FD, LD, RD>,
+    ) -> Control<LM, LE, RM, RE, FD, LD, RD> {
         Control {
             bot,
             current_move: CurrentMove::Idle,
@@ -235,11 +450,45 @@ impl Control {
 
     pub fn linear(&mut self, linear_target: f64) {
         if self.current_move.is_idle() {
-            let linear_move = LinearMove::new(linear_target, &self.bot.config);
+            let start_velocity =
+                (self.bot.left_target() + self.bot.right_target()) / 2.0;
+            let linear_move = LinearMove::new(
+                linear_target,
+                start_velocity,
+                &self.bot.config,
+            );
             self.current_move = CurrentMove::LinearMove(linear_move);
         }
     }
 
+    /**
+     *  Drive along the segments
+     *
+     *  If a path is already being followed, the segments are added to the end
+     *  of it so the mouse does not stop in between. If they don't all fit,
+     *  none of them are driven.
+     */
+    pub fn path(
+        &mut self,
+        now: u32,
+        segments: &[Segment],
+    ) -> Result<(), CapacityError> {
+        if self.current_move.is_idle() {
+            let mut path_move = PathMove::new(
+                now,
+                self.bot.left_pos(),
+                self.bot.right_pos(),
+                &self.bot.config,
+            );
+            path_move.add_segments(segments)?;
+            self.current_move = CurrentMove::Path(path_move);
+        } else if let CurrentMove::Path(ref mut path_move) = self.current_move {
+            path_move.add_segments(segments)?;
+        }
+
+        Ok(())
+    }
+
     pub fn update(&mut self, now: u32) {
         let delta_time = now - self.last_update;
 
@@ -251,6 +500,9 @@ impl Control {
                 CurrentMove::LinearMove(ref mut linear_move) => {
                     linear_move.update(now, &mut self.bot)
                 }
+                CurrentMove::Path(ref mut path_move) => {
+                    path_move.update(now, &mut self.bot)
+                }
                 CurrentMove::Idle => false,
             };
 
@@ -269,14 +521,19 @@ impl Control {
         self.current_move.is_idle()
     }
 
-    pub fn bot(&self) -> &Bot {
+    pub fn bot(&self) -> &Bot<LM, LE, RM, RE, FD, LD, RD> {
         &self.bot
     }
 
+    pub fn bot_mut(&mut self) -> &mut Bot<LM, LE, RM, RE, FD, LD, RD> {
+        &mut self.bot
+    }
+
     pub fn current_move_name(&self) -> &str {
         match self.current_move {
             CurrentMove::SpinMove(_) => "spin",
             CurrentMove::LinearMove(_) => "linear",
+            CurrentMove::Path(_) => "path",
             CurrentMove::Idle => "idle",
         }
     }
@@ -288,7 +545,16 @@ impl Control {
     }
 }
 
-impl Command for Control {
+impl<LM, LE, RM, RE, FD, LD, RD> Command for Control<LM, LE, RM, RE, FD, LD, RD>
+where
+    LM: Motor,
+    LE: Encoder,
+    RM: Motor,
+    RE: Encoder,
+    FD: Vl6180xExt,
+    LD: Vl6180xExt,
+    RD: Vl6180xExt,
+{
     fn keyword_command(&self) -> &str {
         "control"
     }
@@ -330,6 +596,50 @@ impl Command for Control {
                     }
                 }
 
+                Some("path") => {
+                    let mut segments: ArrayVec<[Segment; PATH_BUF_LEN]> =
+                        ArrayVec::new();
+
+                    while let Some(kind) = args.next() {
+                        let value = args.next().and_then(|s| s.parse().ok());
+
+                        let segment = match (kind, value) {
+                            ("line", Some(d)) => Segment::Line(d),
+                            ("arc45", Some(r)) => Segment::Arc45(r),
+                            ("arc90", Some(r)) => Segment::Arc90(r),
+                            (_, None) => {
+                                writeln!(uart, "control: {}: no value", kind)
+                                    .ignore();
+                                return;
+                            }
+                            (_, Some(_)) => {
+                                writeln!(
+                                    uart,
+                                    "control: unknown segment: {}",
+                                    kind
+                                )
+                                .ignore();
+                                return;
+                            }
+                        };
+
+                        if segments.try_push(segment).is_err() {
+                            writeln!(uart, "control: too many segments")
+                                .ignore();
+                            return;
+                        }
+                    }
+
+                    if segments.is_empty() {
+                        writeln!(uart, "control: no segments").ignore();
+                    } else {
+                        let now = self.last_update;
+                        if self.path(now, &segments).is_err() {
+                            writeln!(uart, "control: path full").ignore();
+                        }
+                    }
+                }
+
                 Some("turn") => match args.next() {
                     Some("left") => {
                         self.spin(-self.bot.config.ticks_per_spin / 4.0)
@@ -0,0 +1,286 @@
+/*!
+ *  Sample ready interrupts from the distance sensors
+ *
+ *  Each VL6180x pulls its GPIO1 line low when a sample is ready, and keeps
+ *  it low until the sample is read. The falling edge interrupts, which
+ *  notes the time so the sample can be stamped with when it was measured
+ *  rather than when the main loop got to it.
+ */
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+use cortex_m::interrupt::Mutex;
+use cortex_m_rt_macros::interrupt as isr;
+
+use stm32f4xx_hal::stm32 as stm32f405;
+use stm32f4xx_hal::stm32::Interrupt as interrupt;
+
+use crate::time;
+
+use micromouse_core::distance::{
+    AmbientSample, DistanceSample, DistanceSensor, SensorCalibration,
+};
+use micromouse_core::vl6180x::{
+    AlsGain, AlsSettings, Vl6180xError, Vl6180xExt,
+};
+
+static EXTI: Mutex<RefCell<Option<stm32f405::EXTI>>> =
+    Mutex::new(RefCell::new(None));
+
+static PENDING: [AtomicBool; 3] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+/// The raw ms counter when each line last fell
+static STAMPS: [AtomicU16; 3] =
+    [AtomicU16::new(0), AtomicU16::new(0), AtomicU16::new(0)];
+
+/// The GPIO1 line of each sensor, all on port C
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReadyLine {
+    /// PC1
+    Front,
+    /// PC3
+    Left,
+    /// PC5
+    Right,
+}
+
+impl ReadyLine {
+    fn index(self) -> usize {
+        match self {
+            ReadyLine::Front => 0,
+            ReadyLine::Left => 1,
+            ReadyLine::Right => 2,
+        }
+    }
+
+    fn pin(self) -> u32 {
+        match self {
+            ReadyLine::Front => 1,
+            ReadyLine::Left => 3,
+            ReadyLine::Right => 5,
+        }
+    }
+
+    /// Whether the line is being held low by the sensor
+    fn is_active(self) -> bool {
+        let idr = unsafe { (*stm32f405::GPIOC::ptr()).idr.read().bits() };
+        idr & (1 << self.pin()) == 0
+    }
+
+    /**
+     *  When the last sample became ready, if there is one to read
+     *
+     *  If an edge was missed, say because a read failed and the sample was
+     *  never cleared, the line is still low and the sample is read anyway,
+     *  stamped with `now`.
+     */
+    fn take(self, now: u32) -> Option<u32> {
+        let i = self.index();
+
+        if PENDING[i].swap(false, Ordering::Relaxed) {
+            let stamp = STAMPS[i].load(Ordering::Relaxed);
+            let age = time::counter().wrapping_sub(stamp);
+            Some(now.saturating_sub(u32::from(age)))
+        } else if self.is_active() {
+            Some(now)
+        } else {
+            None
+        }
+    }
+
+    fn fell(self) {
+        STAMPS[self.index()].store(time::counter(), Ordering::Relaxed);
+        PENDING[self.index()].store(true, Ordering::Relaxed);
+    }
+}
+
+/// Interrupt on the falling edge of PC1, PC3 and PC5
+pub fn setup(
+    rcc: &stm32f405::RCC,
+    nvic: &mut stm32f405::NVIC,
+    syscfg: &stm32f405::SYSCFG,
+    exti: stm32f405::EXTI,
+) {
+    rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+
+    // Port C is 0b0010
+    syscfg
+        .exticr1
+        .modify(|_, w| unsafe { w.exti1().bits(0b0010).exti3().bits(0b0010) });
+    syscfg
+        .exticr2
+        .modify(|_, w| unsafe { w.exti5().bits(0b0010) });
+
+    exti.ftsr
+        .modify(|_, w| w.tr1().set_bit().tr3().set_bit().tr5().set_bit());
+    exti.imr
+        .modify(|_, w| w.mr1().set_bit().mr3().set_bit().mr5().set_bit());
+
+    cortex_m::interrupt::free(|cs| {
+        EXTI.borrow(cs).replace(Some(exti));
+    });
+
+    nvic.enable(interrupt::EXTI1);
+    nvic.enable(interrupt::EXTI3);
+    nvic.enable(interrupt::EXTI9_5);
+}
+
+/**
+ *  A distance sensor that is only read when its ready line says so
+ *
+ *  This saves checking the status of every sensor over I2C on every pass
+ *  of the main loop.
+ */
+pub struct Triggered<D: DistanceSensor> {
+    sensor: D,
+    line: ReadyLine,
+    /// Whether the last update left a sample being read
+    reading: bool,
+}
+
+impl<D: DistanceSensor> Triggered<D> {
+    pub fn new(sensor: D, line: ReadyLine) -> Triggered<D> {
+        Triggered {
+            sensor,
+            line,
+            reading: false,
+        }
+    }
+}
+
+impl<D: DistanceSensor> DistanceSensor for Triggered<D> {
+    type Error = D::Error;
+
+    fn update(&mut self, now: u32) -> nb::Result<(), D::Error> {
+        let result = if let Some(stamp) = self.line.take(now) {
+            self.sensor.update(stamp)
+        } else if self.reading {
+            // Carry on with the last sample
+            self.sensor.update(now)
+        } else {
+            return Ok(());
+        };
+
+        self.reading = match result {
+            Err(nb::Error::WouldBlock) => true,
+            _ => false,
+        };
+        result
+    }
+
+    fn sample(&self) -> DistanceSample {
+        self.sensor.sample()
+    }
+
+    fn error_count(&self) -> u32 {
+        self.sensor.error_count()
+    }
+}
+
+impl<D: Vl6180xExt> Vl6180xExt for Triggered<D> {
+    fn max_range(&self) -> u16 {
+        self.sensor.max_range()
+    }
+
+    fn scaling(&self) -> u8 {
+        self.sensor.scaling()
+    }
+
+    fn set_scaling(&mut self, scaling: u8) -> Result<(), Vl6180xError> {
+        self.sensor.set_scaling(scaling)
+    }
+
+    fn ambient(&self) -> Option<AmbientSample> {
+        self.sensor.ambient()
+    }
+
+    fn als_settings(&self) -> AlsSettings {
+        self.sensor.als_settings()
+    }
+
+    fn set_als_gain(&mut self, gain: AlsGain) -> Result<(), Vl6180xError> {
+        self.sensor.set_als_gain(gain)
+    }
+
+    fn set_als_integration_period(
+        &mut self,
+        period: u16,
+    ) -> Result<(), Vl6180xError> {
+        self.sensor.set_als_integration_period(period)
+    }
+
+    fn calibration(&self) -> SensorCalibration {
+        self.sensor.calibration()
+    }
+
+    fn set_calibration(
+        &mut self,
+        calibration: SensorCalibration,
+    ) -> Result<(), Vl6180xError> {
+        self.sensor.set_calibration(calibration)
+    }
+
+    fn calibrate_offset(
+        &mut self,
+        distance: u16,
+    ) -> Result<SensorCalibration, Vl6180xError> {
+        self.sensor.calibrate_offset(distance)
+    }
+
+    fn calibrate_crosstalk(
+        &mut self,
+        distance: u16,
+    ) -> Result<SensorCalibration, Vl6180xError> {
+        self.sensor.calibrate_crosstalk(distance)
+    }
+
+    fn set_interleaved(
+        &mut self,
+        period: Option<u16>,
+    ) -> Result<(), Vl6180xError> {
+        self.sensor.set_interleaved(period)
+    }
+}
+
+#[isr]
+fn EXTI1() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(exti) = EXTI.borrow(cs).borrow().as_ref() {
+            exti.pr.write(|w| w.pr1().set_bit());
+        }
+    });
+
+    ReadyLine::Front.fell();
+}
+
+#[isr]
+fn EXTI3() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(exti) = EXTI.borrow(cs).borrow().as_ref() {
+            exti.pr.write(|w| w.pr3().set_bit());
+        }
+    });
+
+    ReadyLine::Left.fell();
+}
+
+#[isr]
+fn EXTI9_5() {
+    let fell = cortex_m::interrupt::free(|cs| {
+        if let Some(exti) = EXTI.borrow(cs).borrow().as_ref() {
+            if exti.pr.read().pr5().bit() {
+                exti.pr.write(|w| w.pr5().set_bit());
+                return true;
+            }
+        }
+        false
+    });
+
+    if fell {
+        ReadyLine::Right.fell();
+    }
+}
@@ -0,0 +1,150 @@
+use core::slice;
+
+use stm32f4xx_hal::stm32 as stm32f405;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+
+/**
+ *  The last 128K sector of flash, reserved for the config
+ *
+ *  memory.x leaves this out of the FLASH region so the program never ends up
+ *  in it.
+ */
+pub const CONFIG_SECTOR: u8 = 11;
+pub const CONFIG_ADDRESS: u32 = 0x080e_0000;
+pub const CONFIG_SIZE: usize = 128 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum FlashError {
+    WriteProtected,
+    Alignment,
+    Parallelism,
+    Sequence,
+    Operation,
+    OutOfRange,
+}
+
+/**
+ *  Erases and programs the internal flash
+ *
+ *  Programming is done a byte at a time, which works at any supply voltage.
+ */
+pub struct Flash {
+    flash: stm32f405::FLASH,
+}
+
+impl Flash {
+    pub fn new(flash: stm32f405::FLASH) -> Flash {
+        Flash { flash }
+    }
+
+    fn unlock(&mut self) {
+        if self.flash.cr.read().lock().bit_is_set() {
+            self.flash.keyr.write(|w| unsafe { w.key().bits(KEY1) });
+            self.flash.keyr.write(|w| unsafe { w.key().bits(KEY2) });
+        }
+    }
+
+    fn lock(&mut self) {
+        self.flash.cr.modify(|_, w| w.lock().set_bit());
+    }
+
+    fn wait(&mut self) -> Result<(), FlashError> {
+        while self.flash.sr.read().bsy().bit_is_set() {}
+
+        let sr = self.flash.sr.read();
+
+        let result = if sr.wrperr().bit_is_set() {
+            Err(FlashError::WriteProtected)
+        } else if sr.pgaerr().bit_is_set() {
+            Err(FlashError::Alignment)
+        } else if sr.pgperr().bit_is_set() {
+            Err(FlashError::Parallelism)
+        } else if sr.pgserr().bit_is_set() {
+            Err(FlashError::Sequence)
+        } else if sr.operr().bit_is_set() {
+            Err(FlashError::Operation)
+        } else {
+            Ok(())
+        };
+
+        // The flags are cleared by writing a 1 to them
+        self.flash.sr.write(|w| {
+            w.eop()
+                .set_bit()
+                .operr()
+                .set_bit()
+                .wrperr()
+                .set_bit()
+                .pgaerr()
+                .set_bit()
+                .pgperr()
+                .set_bit()
+                .pgserr()
+                .set_bit()
+        });
+
+        result
+    }
+
+    /// Erase the config sector back to all 0xff
+    pub fn erase_config(&mut self) -> Result<(), FlashError> {
+        self.unlock();
+        let result = self.wait().and_then(|_| {
+            self.flash.cr.modify(|_, w| unsafe {
+                w.ser()
+                    .set_bit()
+                    .snb()
+                    .bits(CONFIG_SECTOR)
+                    .psize()
+                    .bits(0b00)
+            });
+            self.flash.cr.modify(|_, w| w.strt().set_bit());
+            self.wait()
+        });
+        self.flash.cr.modify(|_, w| w.ser().clear_bit());
+        self.lock();
+        result
+    }
+
+    /// Program bytes into the config sector, which has to be erased first
+    pub fn program_config(
+        &mut self,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), FlashError> {
+        if offset + data.len() > CONFIG_SIZE {
+            return Err(FlashError::OutOfRange);
+        }
+
+        self.unlock();
+        let mut result = self.wait();
+        self.flash
+            .cr
+            .modify(|_, w| unsafe { w.pg().set_bit().psize().bits(0b00) });
+
+        for (i, byte) in data.iter().enumerate() {
+            if result.is_err() {
+                break;
+            }
+
+            let address = CONFIG_ADDRESS as usize + offset + i;
+            unsafe {
+                core::ptr::write_volatile(address as *mut u8, *byte);
+            }
+            result = self.wait();
+        }
+
+        self.flash.cr.modify(|_, w| w.pg().clear_bit());
+        self.lock();
+        result
+    }
+
+    /// The contents of the config sector
+    pub fn config(&self) -> &'static [u8] {
+        unsafe {
+            slice::from_raw_parts(CONFIG_ADDRESS as *const u8, CONFIG_SIZE)
+        }
+    }
+}
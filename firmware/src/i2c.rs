@@ -0,0 +1,696 @@
+/*!
+ *  Interrupt driven I2C for the distance sensor buses
+ *
+ *  Each bus runs one transaction at a time, a write followed by an optional
+ *  repeated start and read, all done from the event interrupt. The main loop
+ *  starts a transaction and checks back for the result later, so the three
+ *  buses run alongside each other and the control loop. The blocking
+ *  embedded-hal traits are implemented on top for setup and commands.
+ */
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use cortex_m_rt_macros::interrupt as isr;
+
+use embedded_hal::blocking::i2c;
+
+use micromouse_core::i2c::Transaction;
+
+use stm32f4xx_hal::stm32 as stm32f405;
+use stm32f4xx_hal::stm32::Interrupt as interrupt;
+
+/// The APB1 clock the buses run from, in MHz
+const PCLK1_MHZ: u32 = 16;
+
+/// The most bytes a transaction can write
+const MAX_WRITE: usize = 4;
+
+/// The most bytes a transaction can read
+const MAX_READ: usize = 32;
+
+/// How many times a blocking transaction checks for the result before
+/// giving up on it
+const MAX_SPINS: u32 = 100_000;
+
+/// Half an SCL period when recovering the bus, in cycles
+const RECOVERY_DELAY: u32 = 80;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum I2cError {
+    /// A transaction is already running
+    Busy,
+    /// `finish` was called without a transaction
+    NotStarted,
+    /// More bytes than a transaction can hold
+    TooLong,
+    /// The slave didn't acknowledge
+    Nack,
+    /// A misplaced start or stop, or the bus stayed busy after recovery
+    Bus,
+    /// Another master took the bus
+    Arbitration,
+    /// A received byte was lost
+    Overrun,
+    /// The transaction never finished
+    Timeout,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Speed {
+    /// 100 kHz
+    Standard,
+    /// 400 kHz
+    Fast,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Phase {
+    Idle,
+    Write,
+    Read,
+    Done(Result<(), I2cError>),
+}
+
+struct Transfer {
+    address: u8,
+    write: [u8; MAX_WRITE],
+    write_len: usize,
+    written: usize,
+    read: [u8; MAX_READ],
+    read_len: usize,
+    received: usize,
+    phase: Phase,
+}
+
+impl Transfer {
+    const fn new() -> Transfer {
+        Transfer {
+            address: 0,
+            write: [0; MAX_WRITE],
+            write_len: 0,
+            written: 0,
+            read: [0; MAX_READ],
+            read_len: 0,
+            received: 0,
+            phase: Phase::Idle,
+        }
+    }
+}
+
+static TRANSFERS: [Mutex<RefCell<Transfer>>; 3] = [
+    Mutex::new(RefCell::new(Transfer::new())),
+    Mutex::new(RefCell::new(Transfer::new())),
+    Mutex::new(RefCell::new(Transfer::new())),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Port {
+    A,
+    B,
+    C,
+}
+
+/// Run `$body` with `$gpio` as the registers of `$port`
+macro_rules! with_port {
+    ($port:expr, $gpio:ident => $body:expr) => {
+        match $port {
+            Port::A => {
+                let $gpio = unsafe { &*stm32f405::GPIOA::ptr() };
+                $body
+            }
+            Port::B => {
+                let $gpio = unsafe { &*stm32f405::GPIOB::ptr() };
+                $body
+            }
+            Port::C => {
+                let $gpio = unsafe { &*stm32f405::GPIOC::ptr() };
+                $body
+            }
+        }
+    };
+}
+
+/// A bus pin, driven by hand to recover the bus
+#[derive(Copy, Clone, Debug)]
+struct Pin {
+    port: Port,
+    pin: u32,
+}
+
+impl Pin {
+    fn new(port: Port, pin: u32) -> Pin {
+        Pin { port, pin }
+    }
+
+    fn set_mode(self, mode: u32) {
+        let shift = self.pin * 2;
+        with_port!(self.port, gpio => gpio.moder.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11 << shift)) | (mode << shift))
+        }));
+    }
+
+    fn output(self) {
+        self.set_mode(0b01);
+    }
+
+    fn alternate(self) {
+        self.set_mode(0b10);
+    }
+
+    fn open_drain(self) {
+        with_port!(self.port, gpio => gpio.otyper.modify(|r, w| unsafe {
+            w.bits(r.bits() | (1 << self.pin))
+        }));
+    }
+
+    /// Pick the alternate function the pin has in alternate mode
+    fn function(self, af: u32) {
+        let shift = (self.pin % 8) * 4;
+        if self.pin < 8 {
+            with_port!(self.port, gpio => gpio.afrl.modify(|r, w| unsafe {
+                w.bits((r.bits() & !(0b1111 << shift)) | (af << shift))
+            }));
+        } else {
+            with_port!(self.port, gpio => gpio.afrh.modify(|r, w| unsafe {
+                w.bits((r.bits() & !(0b1111 << shift)) | (af << shift))
+            }));
+        }
+    }
+
+    fn set(self, high: bool) {
+        let bit = if high { self.pin } else { self.pin + 16 };
+        with_port!(self.port, gpio => gpio.bsrr.write(|w| unsafe {
+            w.bits(1 << bit)
+        }));
+    }
+
+    fn is_high(self) -> bool {
+        with_port!(self.port, gpio => {
+            gpio.idr.read().bits() & (1 << self.pin) != 0
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Bus {
+    I2c1,
+    I2c2,
+    I2c3,
+}
+
+impl Bus {
+    fn index(self) -> usize {
+        match self {
+            Bus::I2c1 => 0,
+            Bus::I2c2 => 1,
+            Bus::I2c3 => 2,
+        }
+    }
+
+    fn registers(self) -> &'static stm32f405::i2c1::RegisterBlock {
+        unsafe {
+            match self {
+                Bus::I2c1 => &*stm32f405::I2C1::ptr(),
+                Bus::I2c2 => &*stm32f405::I2C2::ptr(),
+                Bus::I2c3 => &*stm32f405::I2C3::ptr(),
+            }
+        }
+    }
+
+    /// SCL and SDA
+    fn pins(self) -> (Pin, Pin) {
+        match self {
+            Bus::I2c1 => (Pin::new(Port::B, 8), Pin::new(Port::B, 9)),
+            Bus::I2c2 => (Pin::new(Port::B, 10), Pin::new(Port::B, 11)),
+            Bus::I2c3 => (Pin::new(Port::A, 8), Pin::new(Port::C, 9)),
+        }
+    }
+}
+
+/**
+ *  One of the I2C peripherals, as a master
+ *
+ *  Its pins are set up as open drain alternate functions along with it.
+ */
+pub struct I2c {
+    bus: Bus,
+    speed: Speed,
+}
+
+impl I2c {
+    pub fn i2c1(
+        rcc: &stm32f405::RCC,
+        nvic: &mut stm32f405::NVIC,
+        _i2c: stm32f405::I2C1,
+        speed: Speed,
+    ) -> I2c {
+        rcc.ahb1enr.modify(|_, w| w.gpioben().set_bit());
+        rcc.apb1enr.modify(|_, w| w.i2c1en().set_bit());
+        nvic.enable(interrupt::I2C1_EV);
+        nvic.enable(interrupt::I2C1_ER);
+        I2c::setup(Bus::I2c1, speed)
+    }
+
+    pub fn i2c2(
+        rcc: &stm32f405::RCC,
+        nvic: &mut stm32f405::NVIC,
+        _i2c: stm32f405::I2C2,
+        speed: Speed,
+    ) -> I2c {
+        rcc.ahb1enr.modify(|_, w| w.gpioben().set_bit());
+        rcc.apb1enr.modify(|_, w| w.i2c2en().set_bit());
+        nvic.enable(interrupt::I2C2_EV);
+        nvic.enable(interrupt::I2C2_ER);
+        I2c::setup(Bus::I2c2, speed)
+    }
+
+    pub fn i2c3(
+        rcc: &stm32f405::RCC,
+        nvic: &mut stm32f405::NVIC,
+        _i2c: stm32f405::I2C3,
+        speed: Speed,
+    ) -> I2c {
+        rcc.ahb1enr
+            .modify(|_, w| w.gpioaen().set_bit().gpiocen().set_bit());
+        rcc.apb1enr.modify(|_, w| w.i2c3en().set_bit());
+        nvic.enable(interrupt::I2C3_EV);
+        nvic.enable(interrupt::I2C3_ER);
+        I2c::setup(Bus::I2c3, speed)
+    }
+
+    fn setup(bus: Bus, speed: Speed) -> I2c {
+        let (scl, sda) = bus.pins();
+        for &pin in &[scl, sda] {
+            pin.open_drain();
+            pin.function(4);
+            pin.alternate();
+        }
+
+        let i2c = I2c { bus, speed };
+        i2c.configure();
+        i2c
+    }
+
+    /// Reset the peripheral and set the clock up for the speed
+    fn configure(&self) {
+        let i2c = self.bus.registers();
+
+        i2c.cr1.write(|w| w.swrst().set_bit());
+        i2c.cr1.write(|w| w.swrst().clear_bit());
+
+        i2c.cr2.write(|w| unsafe { w.freq().bits(PCLK1_MHZ as u8) });
+
+        match self.speed {
+            Speed::Standard => {
+                // Equal high and low times, and a 1000 ns max rise time
+                let ccr = PCLK1_MHZ * 1_000_000 / (2 * 100_000);
+                i2c.ccr.write(|w| unsafe { w.ccr().bits(ccr as u16) });
+                i2c.trise.write(|w| w.trise().bits(PCLK1_MHZ as u8 + 1));
+            }
+            Speed::Fast => {
+                // Low twice as long as high, rounded up to stay under
+                // 400 kHz, and a 300 ns max rise time
+                let ccr =
+                    (PCLK1_MHZ * 1_000_000 + 3 * 400_000 - 1) / (3 * 400_000);
+                i2c.ccr.write(|w| unsafe {
+                    w.f_s().set_bit().duty().clear_bit().ccr().bits(ccr as u16)
+                });
+                i2c.trise.write(|w| {
+                    w.trise().bits((PCLK1_MHZ * 300 / 1000 + 1) as u8)
+                });
+            }
+        }
+
+        i2c.cr1.write(|w| w.pe().set_bit());
+    }
+
+    /**
+     *  Free a bus that a slave is holding SDA low on
+     *
+     *  A slave that was reset partway through sending a byte is left
+     *  waiting to send the rest of it. Clocking SCL until it lets go of
+     *  SDA, then sending a stop, puts it back to idle.
+     */
+    pub fn recover(&mut self) {
+        let (scl, sda) = self.bus.pins();
+
+        self.bus.registers().cr1.modify(|_, w| w.pe().clear_bit());
+
+        // Already open drain, but driving the bus push pull would fight
+        // the slave holding SDA
+        scl.open_drain();
+        sda.open_drain();
+        scl.set(true);
+        sda.set(true);
+        scl.output();
+        sda.output();
+
+        for _ in 0..9 {
+            if sda.is_high() {
+                break;
+            }
+
+            scl.set(false);
+            cortex_m::asm::delay(RECOVERY_DELAY);
+            scl.set(true);
+            cortex_m::asm::delay(RECOVERY_DELAY);
+        }
+
+        // A stop is SDA going high while SCL is high
+        scl.set(false);
+        cortex_m::asm::delay(RECOVERY_DELAY);
+        sda.set(false);
+        cortex_m::asm::delay(RECOVERY_DELAY);
+        scl.set(true);
+        cortex_m::asm::delay(RECOVERY_DELAY);
+        sda.set(true);
+        cortex_m::asm::delay(RECOVERY_DELAY);
+
+        scl.alternate();
+        sda.alternate();
+
+        self.configure();
+    }
+
+    /// Start a transaction and wait for it to finish
+    fn transfer(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), I2cError> {
+        self.start(address, bytes, buffer.len())?;
+
+        for _ in 0..MAX_SPINS {
+            match self.finish(buffer) {
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(e)) => return Err(e),
+                Ok(()) => return Ok(()),
+            }
+        }
+
+        self.cancel();
+        Err(I2cError::Timeout)
+    }
+}
+
+impl Transaction for I2c {
+    type Error = I2cError;
+
+    fn start(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        read: usize,
+    ) -> Result<(), I2cError> {
+        if bytes.len() > MAX_WRITE
+            || read > MAX_READ
+            || (bytes.is_empty() && read == 0)
+        {
+            return Err(I2cError::TooLong);
+        }
+
+        let i2c = self.bus.registers();
+
+        cortex_m::interrupt::free(|cs| {
+            let mut t = TRANSFERS[self.bus.index()].borrow(cs).borrow_mut();
+
+            match t.phase {
+                Phase::Idle | Phase::Done(_) => {}
+                _ => return Err(I2cError::Busy),
+            }
+
+            t.address = address;
+            t.write[..bytes.len()].copy_from_slice(bytes);
+            t.write_len = bytes.len();
+            t.written = 0;
+            t.read_len = read;
+            t.received = 0;
+            t.phase = if bytes.is_empty() {
+                Phase::Read
+            } else {
+                Phase::Write
+            };
+
+            Ok(())
+        })?;
+
+        if i2c.sr2.read().busy().bit() {
+            self.recover();
+
+            if i2c.sr2.read().busy().bit() {
+                self.cancel();
+                return Err(I2cError::Bus);
+            }
+        }
+
+        i2c.cr1.modify(|_, w| w.ack().set_bit().pos().clear_bit());
+        i2c.cr2.modify(|_, w| {
+            w.itevten()
+                .set_bit()
+                .itbufen()
+                .set_bit()
+                .iterren()
+                .set_bit()
+        });
+        i2c.cr1.modify(|_, w| w.start().set_bit());
+
+        Ok(())
+    }
+
+    fn finish(&mut self, buffer: &mut [u8]) -> nb::Result<(), I2cError> {
+        cortex_m::interrupt::free(|cs| {
+            let mut t = TRANSFERS[self.bus.index()].borrow(cs).borrow_mut();
+
+            match t.phase {
+                Phase::Done(result) => {
+                    t.phase = Phase::Idle;
+                    let len = buffer.len().min(t.received);
+                    buffer[..len].copy_from_slice(&t.read[..len]);
+                    result.map_err(nb::Error::Other)
+                }
+                Phase::Idle => Err(nb::Error::Other(I2cError::NotStarted)),
+                _ => Err(nb::Error::WouldBlock),
+            }
+        })
+    }
+
+    fn cancel(&mut self) {
+        cortex_m::interrupt::free(|cs| {
+            TRANSFERS[self.bus.index()].borrow(cs).borrow_mut().phase =
+                Phase::Idle;
+        });
+
+        self.configure();
+    }
+}
+
+impl i2c::Write for I2c {
+    type Error = I2cError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), I2cError> {
+        self.transfer(address, bytes, &mut [])
+    }
+}
+
+impl i2c::Read for I2c {
+    type Error = I2cError;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), I2cError> {
+        self.transfer(address, &[], buffer)
+    }
+}
+
+impl i2c::WriteRead for I2c {
+    type Error = I2cError;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), I2cError> {
+        self.transfer(address, bytes, buffer)
+    }
+}
+
+/// Stop interrupting and record how the transaction ended
+fn done(
+    i2c: &stm32f405::i2c1::RegisterBlock,
+    t: &mut Transfer,
+    result: Result<(), I2cError>,
+) {
+    i2c.cr2.modify(|_, w| {
+        w.itevten()
+            .clear_bit()
+            .itbufen()
+            .clear_bit()
+            .iterren()
+            .clear_bit()
+    });
+    t.phase = Phase::Done(result);
+}
+
+/// Take the byte in DR, finishing the read if it was the last
+fn receive(i2c: &stm32f405::i2c1::RegisterBlock, t: &mut Transfer) {
+    let received = t.received;
+    t.read[received] = i2c.dr.read().bits() as u8;
+    t.received += 1;
+
+    if t.received == t.read_len {
+        done(i2c, t, Ok(()));
+    }
+}
+
+/**
+ *  Move the transaction along
+ *
+ *  The end of a read follows the reference manual, so it doesn't depend on
+ *  how quickly this interrupt is serviced. A single byte is NACKed and
+ *  stopped as the address is acknowledged. Otherwise the last two or three
+ *  bytes are left to pile up in DR and the shift register, which holds the
+ *  clock low until BTF is handled. With two left POS has already set up the
+ *  NACK, and with three the NACK and stop are set around reading the first.
+ */
+fn event(bus: Bus) {
+    cortex_m::interrupt::free(|cs| {
+        let i2c = bus.registers();
+        let mut t = TRANSFERS[bus.index()].borrow(cs).borrow_mut();
+        let sr1 = i2c.sr1.read();
+
+        if sr1.sb().bit() {
+            // Writing the address after reading SR1 clears SB
+            let read = (t.phase == Phase::Read) as u8;
+            let address = (t.address << 1) | read;
+            i2c.dr.write(|w| unsafe { w.bits(u32::from(address)) });
+        } else if sr1.addr().bit() {
+            let reading = t.phase == Phase::Read;
+            let single = reading && t.read_len == 1;
+            if single {
+                i2c.cr1.modify(|_, w| w.ack().clear_bit());
+            } else if reading && t.read_len == 2 {
+                // NACK the byte after the next one, rather than the next
+                i2c.cr1.modify(|_, w| w.pos().set_bit().ack().clear_bit());
+            }
+            if reading && (t.read_len == 2 || t.read_len == 3) {
+                i2c.cr2.modify(|_, w| w.itbufen().clear_bit());
+            }
+
+            // Reading SR2 after SR1 clears ADDR
+            i2c.sr2.read();
+
+            if single {
+                i2c.cr1.modify(|_, w| w.stop().set_bit());
+            }
+        } else if t.phase == Phase::Write && sr1.tx_e().bit() {
+            if t.written < t.write_len {
+                let byte = t.write[t.written];
+                i2c.dr.write(|w| unsafe { w.bits(u32::from(byte)) });
+                t.written += 1;
+            } else if sr1.btf().bit() {
+                if t.read_len > 0 {
+                    t.phase = Phase::Read;
+                    i2c.cr2.modify(|_, w| w.itbufen().set_bit());
+                    i2c.cr1.modify(|_, w| w.ack().set_bit().start().set_bit());
+                } else {
+                    i2c.cr1.modify(|_, w| w.stop().set_bit());
+                    done(i2c, &mut t, Ok(()));
+                }
+            } else {
+                // Only wake up again once the last byte is out
+                i2c.cr2.modify(|_, w| w.itbufen().clear_bit());
+            }
+        } else if t.phase == Phase::Read && sr1.rx_ne().bit() {
+            let left = t.read_len - t.received;
+
+            if left == 2 || left == 3 {
+                // Wait for BTF, with the next byte in the shift register
+                if !sr1.btf().bit() {
+                    i2c.cr2.modify(|_, w| w.itbufen().clear_bit());
+                } else if left == 2 {
+                    i2c.cr1.modify(|_, w| w.stop().set_bit());
+                    receive(i2c, &mut t);
+                    receive(i2c, &mut t);
+                } else {
+                    i2c.cr1.modify(|_, w| w.ack().clear_bit());
+                    receive(i2c, &mut t);
+                    i2c.cr1.modify(|_, w| w.stop().set_bit());
+                    receive(i2c, &mut t);
+                    i2c.cr2.modify(|_, w| w.itbufen().set_bit());
+                }
+            } else {
+                receive(i2c, &mut t);
+
+                if t.read_len - t.received == 3 {
+                    i2c.cr2.modify(|_, w| w.itbufen().clear_bit());
+                }
+            }
+        }
+    });
+}
+
+fn error(bus: Bus) {
+    cortex_m::interrupt::free(|cs| {
+        let i2c = bus.registers();
+        let mut t = TRANSFERS[bus.index()].borrow(cs).borrow_mut();
+        let sr1 = i2c.sr1.read();
+
+        let e = if sr1.af().bit() {
+            I2cError::Nack
+        } else if sr1.arlo().bit() {
+            I2cError::Arbitration
+        } else if sr1.ovr().bit() {
+            I2cError::Overrun
+        } else {
+            I2cError::Bus
+        };
+
+        i2c.sr1.modify(|_, w| {
+            w.af()
+                .clear_bit()
+                .arlo()
+                .clear_bit()
+                .ovr()
+                .clear_bit()
+                .berr()
+                .clear_bit()
+        });
+
+        // Losing arbitration already left the bus
+        if e != I2cError::Arbitration {
+            i2c.cr1.modify(|_, w| w.stop().set_bit());
+        }
+
+        done(i2c, &mut t, Err(e));
+    });
+}
+
+#[isr]
+fn I2C1_EV() {
+    event(Bus::I2c1);
+}
+
+#[isr]
+fn I2C1_ER() {
+    error(Bus::I2c1);
+}
+
+#[isr]
+fn I2C2_EV() {
+    event(Bus::I2c2);
+}
+
+#[isr]
+fn I2C2_ER() {
+    error(Bus::I2c2);
+}
+
+#[isr]
+fn I2C3_EV() {
+    event(Bus::I2c3);
+}
+
+#[isr]
+fn I2C3_ER() {
+    error(Bus::I2c3);
+}
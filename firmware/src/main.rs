@@ -27,13 +27,17 @@ pub mod battery;
 pub mod bot;
 pub mod config;
 pub mod control;
+pub mod exti;
+pub mod flash;
+pub mod i2c;
 pub mod motors;
 pub mod navigate;
+pub mod odometry;
+pub mod pid;
 pub mod plan;
+pub mod report;
 pub mod time;
 pub mod uart;
-pub mod vl6180x;
-pub mod mouse;
 
 use core::fmt::Write;
 use core::str;
@@ -54,14 +58,21 @@ use crate::motors::left::{LeftEncoder, LeftMotor};
 use crate::motors::right::{RightEncoder, RightMotor};
 
 use crate::bot::Bot;
-use crate::config::BotConfig;
+use crate::exti::{ReadyLine, Triggered};
+use crate::flash::Flash;
+use crate::i2c::{I2c, Speed};
 
 use crate::control::Control;
 
 use crate::plan::Plan;
+use crate::report::Report;
 
-use crate::navigate::LessRandomNavigate;
-use crate::navigate::RandomNavigate;
+use micromouse_core::config::BotConfig;
+use micromouse_core::navigate::LessRandomNavigate;
+use micromouse_core::telemetry::{
+    Message, MoveEvent, MoveKind, Sensors, State, Walls,
+};
+use micromouse_core::vl6180x;
 
 // Setup the master clock out
 pub fn mco2_setup(rcc: &stm32f405::RCC, gpioc: &stm32f405::GPIOC) {
@@ -83,7 +94,8 @@ fn main() -> ! {
 
     let mut battery = Battery::setup(&p.RCC, &p.GPIOB, p.ADC1);
 
-    let mut uart = Uart::setup(&p.RCC, &mut cp.NVIC, p.USART1, &p.GPIOA);
+    let mut uart =
+        Uart::setup(&p.RCC, &mut cp.NVIC, p.USART1, p.DMA2, &p.GPIOA);
 
     let left_motor = LeftMotor::setup(&p.RCC, p.TIM3, &p.GPIOA);
 
@@ -92,11 +104,16 @@ fn main() -> ! {
     let right_motor = RightMotor::setup(&p.RCC, p.TIM4, &p.GPIOB);
     let right_encoder = RightEncoder::setup(&p.RCC, &p.GPIOA, p.TIM5);
 
+    exti::setup(&p.RCC, &mut cp.NVIC, &p.SYSCFG, p.EXTI);
+
+    let i2c1 = I2c::i2c1(&p.RCC, &mut cp.NVIC, p.I2C1, Speed::Fast);
+    let i2c2 = I2c::i2c2(&p.RCC, &mut cp.NVIC, p.I2C2, Speed::Fast);
+    let i2c3 = I2c::i2c3(&p.RCC, &mut cp.NVIC, p.I2C3, Speed::Fast);
+
     // Init the hal things
     let rcc = p.RCC.constrain();
-    let clocks = rcc.cfgr.freeze();
+    let _clocks = rcc.cfgr.freeze();
 
-    let gpioa = p.GPIOA.split();
     let gpiob = p.GPIOB.split();
     let gpioc = p.GPIOC.split();
 
@@ -115,23 +132,18 @@ fn main() -> ! {
     writeln!(uart, "Initializing").ignore();
 
     let mut front_distance = {
-        let scl = gpiob.pb8.into_open_drain_output().into_alternate_af4();
-        let sda = gpiob.pb9.into_open_drain_output().into_alternate_af4();
-
         let mut gpio0 = gpioc.pc0.into_open_drain_output();
         gpio0.set_high();
 
-        let mut gpio1 = gpioc.pc1.into_open_drain_output();
-        gpio1.set_high();
-
-        let i2c =
-            stm32f4::i2c::I2c::i2c1(p.I2C1, (scl, sda), 100.khz(), clocks);
+        // Sample ready interrupt
+        let _gpio1 = gpioc.pc1.into_pull_up_input();
 
         time.delay(10000);
 
-        let mut distance = vl6180x::VL6180x::new(i2c, 0x29);
-        distance.init_private_registers();
-        distance.init_default();
+        let mut distance = vl6180x::VL6180x::new(i2c1, 0x29);
+        if let Err(e) = distance.init() {
+            writeln!(uart, "front distance: {:?}", e).ignore();
+        }
         distance
     };
 
@@ -139,23 +151,18 @@ fn main() -> ! {
     blue_led.set_high();
 
     let mut left_distance = {
-        let scl = gpiob.pb10.into_open_drain_output().into_alternate_af4();
-        let sda = gpiob.pb11.into_open_drain_output().into_alternate_af4();
-
         let mut gpio0 = gpioc.pc2.into_open_drain_output();
         gpio0.set_high();
 
-        let mut gpio1 = gpioc.pc3.into_open_drain_output();
-        gpio1.set_high();
-
-        let i2c =
-            stm32f4::i2c::I2c::i2c2(p.I2C2, (scl, sda), 100.khz(), clocks);
+        // Sample ready interrupt
+        let _gpio1 = gpioc.pc3.into_pull_up_input();
 
         time.delay(1000);
 
-        let mut distance = vl6180x::VL6180x::new(i2c, 0x29);
-        distance.init_private_registers();
-        distance.init_default();
+        let mut distance = vl6180x::VL6180x::new(i2c2, 0x29);
+        if let Err(e) = distance.init() {
+            writeln!(uart, "left distance: {:?}", e).ignore();
+        }
         distance
     };
 
@@ -163,23 +170,18 @@ fn main() -> ! {
     blue_led.set_high();
 
     let mut right_distance = {
-        let scl = gpioa.pa8.into_open_drain_output().into_alternate_af4();
-        let sda = gpioc.pc9.into_open_drain_output().into_alternate_af4();
-
         let mut gpio0 = gpioc.pc4.into_open_drain_output();
         gpio0.set_high();
 
-        let mut gpio1 = gpioc.pc5.into_open_drain_output();
-        gpio1.set_high();
-
-        let i2c =
-            stm32f4::i2c::I2c::i2c3(p.I2C3, (scl, sda), 100.khz(), clocks);
+        // Sample ready interrupt
+        let _gpio1 = gpioc.pc5.into_pull_up_input();
 
         time.delay(1000);
 
-        let mut distance = vl6180x::VL6180x::new(i2c, 0x29);
-        distance.init_private_registers();
-        distance.init_default();
+        let mut distance = vl6180x::VL6180x::new(i2c3, 0x29);
+        if let Err(e) = distance.init() {
+            writeln!(uart, "right distance: {:?}", e).ignore();
+        }
         distance
     };
 
@@ -212,40 +214,34 @@ fn main() -> ! {
         orange_led.toggle();
     }
 
-    let config = BotConfig {
-        path_p: 1.0,
-        path_i: 0.0,
-        path_d: 0.0,
-        ticks_per_spin: 2064.03,
-        ticks_per_cell: 1620.0,
-        cell_width: 180.0,
-        cell_offset: 53.0,
-        wall_threshold: 120.0,
-        front_wall_distance: 35.0,
+    let mut flash = Flash::new(p.FLASH);
+
+    let config = match config::load(&flash) {
+        Ok(config) => {
+            writeln!(uart, "Loaded config").ignore();
+            config
+        }
+        Err(e) => {
+            writeln!(uart, "Using default config: {:?}", e).ignore();
+            BotConfig::default()
+        }
     };
 
-    let bot = Bot::new(
+    let mut bot = Bot::new(
         left_motor,
         left_encoder,
         right_motor,
         right_encoder,
-        front_distance,
-        left_distance,
-        right_distance,
+        Triggered::new(front_distance, ReadyLine::Front),
+        Triggered::new(left_distance, ReadyLine::Left),
+        Triggered::new(right_distance, ReadyLine::Right),
         config,
     );
+    bot.calibrate_sensors();
 
     let control = Control::new(bot);
 
-    /*
-    let navigate = RandomNavigate::new([
-        15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
-    ]);
-    */
-
-    let navigate = LessRandomNavigate::new([
-        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
-    ]);
+    let navigate = LessRandomNavigate::new();
 
     let mut plan = Plan::new(control, navigate);
 
@@ -253,21 +249,23 @@ fn main() -> ! {
 
     let mut last_time: u32 = 0;
 
-    let mut report = false;
+    let mut report = Report::new();
+    let mut telemetry = false;
+    let mut last_move = MoveKind::Idle;
 
     loop {
         let now: u32 = time.now();
 
         if let Ok(line) = uart.read_line() {
             if let Ok(string) = str::from_utf8(&line) {
-                let string = string.trim_matches(|c| c as u8 == 0).trim();
+                let string = string.trim();
                 writeln!(uart, ">> {}", string).ignore();
                 if string.starts_with('!') {
                     writeln!(uart, "Stopping report").ignore();
-                    report = false;
+                    report.stop();
                 } else if string.starts_with('@') {
                     writeln!(uart, "Starting report").ignore();
-                    report = true;
+                    report.start();
                 } else {
                     let mut args = string.split_whitespace();
 
@@ -275,6 +273,49 @@ fn main() -> ! {
 
                     if command == Some(plan.keyword_command()) {
                         plan.handle_command(&mut uart, args);
+                    } else if command == Some(report.keyword_command()) {
+                        report.handle_command(&mut uart, args);
+                    } else if command == Some(battery.keyword_command()) {
+                        battery.handle_command(&mut uart, args);
+                    } else if command == Some("uart") {
+                        if args.next() == Some("reset") {
+                            uart.reset_stats();
+                        }
+                        let stats = uart.stats();
+                        writeln!(uart, "{:#?}", stats).ignore();
+                    } else if command == Some("telemetry") {
+                        match args.next() {
+                            Some("on") => telemetry = true,
+                            Some("off") => telemetry = false,
+                            _ => {}
+                        }
+                        writeln!(uart, "telemetry: {}", telemetry).ignore();
+                    } else if command == Some("config") {
+                        let config = &mut plan.control().bot_mut().config;
+                        match args.next() {
+                            Some("save") => {
+                                match config::save(config, &mut flash) {
+                                    Ok(()) => writeln!(uart, "config: saved"),
+                                    Err(e) => writeln!(uart, "config: {:?}", e),
+                                }
+                                .ignore()
+                            }
+                            Some("load") => match config::load(&flash) {
+                                Ok(loaded) => {
+                                    *config = loaded;
+                                    writeln!(uart, "config: loaded").ignore();
+                                }
+                                Err(e) => {
+                                    writeln!(uart, "config: {:?}", e).ignore()
+                                }
+                            },
+                            Some("defaults") => {
+                                *config = BotConfig::default();
+                                writeln!(uart, "config: defaults").ignore();
+                            }
+                            _ => writeln!(uart, "config: unknown command")
+                                .ignore(),
+                        }
                     } else {
                         writeln!(uart, "Invalid Command!").ignore();
                     }
@@ -283,28 +324,50 @@ fn main() -> ! {
         }
 
         if now - last_time >= 20u32 {
-            if report {
-                writeln!(
-                    uart,
-                    "{}",
-                    now,
-                    //plan.x_pos(),
-                    //plan.y_pos(),
-                    //plan.direction(),
-                    //plan.control().bot().left_pos(),
-                    //plan.control().bot().right_pos(),
-                    //control.bot().left_velocity(),
-                    //control.bot().right_velocity(),
-                    //control.bot().left_power(),
-                    //control.bot().right_power(),
-                    //plan.control().bot().linear_pos(),
-                    //plan.control().bot().spin_pos(),
-                    //plan.control().bot().linear_velocity(),
-                    //plan.control().bot().spin_velocity(),
-                    //plan.control().bot().left_distance(),
-                    //plan.control().bot().front_distance(),
-                    //plan.control().bot().right_distance(),
-                )
+            if telemetry {
+                let bot = plan.control().bot();
+                let pose = bot.pose();
+
+                uart.add_message(&Message::State(State {
+                    time: now,
+                    left_pos: bot.left_pos() as f32,
+                    right_pos: bot.right_pos() as f32,
+                    left_velocity: bot.left_velocity() as f32,
+                    right_velocity: bot.right_velocity() as f32,
+                    left_target: bot.left_target() as f32,
+                    right_target: bot.right_target() as f32,
+                    left_volts: bot.left_volts() as f32,
+                    right_volts: bot.right_volts() as f32,
+                    left_duty: bot.left_duty() as i16,
+                    right_duty: bot.right_duty() as i16,
+                    x: pose.x,
+                    y: pose.y,
+                    heading: pose.heading,
+                }))
+                .ignore();
+
+                uart.add_message(&Message::Sensors(Sensors {
+                    time: now,
+                    front: bot.front_sample().range,
+                    left: bot.left_sample().range,
+                    right: bot.right_sample().range,
+                    front_status: bot.front_sample().status.code(),
+                    left_status: bot.left_sample().status.code(),
+                    right_status: bot.right_sample().status.code(),
+                    battery: battery.pack_mv() as u16,
+                }))
+                .ignore();
+
+                let walls = plan.walls();
+                uart.add_message(&Message::Walls(Walls {
+                    time: now,
+                    front: walls.front.present,
+                    left: walls.left.present,
+                    right: walls.right.present,
+                    front_confidence: walls.front.confidence,
+                    left_confidence: walls.left.confidence,
+                    right_confidence: walls.right.confidence,
+                }))
                 .ignore();
             }
 
@@ -322,7 +385,7 @@ fn main() -> ! {
                 blue_led.set_low();
             }
 
-            if battery.is_dead() {
+            if battery.level().led(now) {
                 red_led.set_high();
             } else {
                 red_led.set_low();
@@ -339,7 +402,39 @@ fn main() -> ! {
             last_time = now;
         }
 
+        if report.is_due(now) {
+            let snapshot = plan.snapshot(now);
+            report.update(&mut uart, &snapshot);
+        }
+
         plan.update(now);
-        battery.update(now);
+        battery.update(now, &plan.control().bot().config.battery);
+        plan.control().bot_mut().set_battery_mv(battery.pack_mv());
+
+        let current_move =
+            MoveKind::from_name(plan.control().current_move_name());
+        if current_move != last_move {
+            if telemetry {
+                if last_move != MoveKind::Idle {
+                    uart.add_message(&Message::MoveEvent(MoveEvent {
+                        time: now,
+                        kind: last_move,
+                        started: false,
+                    }))
+                    .ignore();
+                }
+
+                if current_move != MoveKind::Idle {
+                    uart.add_message(&Message::MoveEvent(MoveEvent {
+                        time: now,
+                        kind: current_move,
+                        started: true,
+                    }))
+                    .ignore();
+                }
+            }
+
+            last_move = current_move;
+        }
     }
 }
@@ -1,11 +1,8 @@
+pub mod control;
 pub mod left;
 pub mod right;
 
-pub trait Motor {
-    fn change_power(&mut self, power: i32);
-}
-
-pub trait Encoder {
-    fn count(&self) -> i32;
-    fn reset(&mut self);
-}
+pub use micromouse_core::motors::velocity;
+pub use micromouse_core::motors::{
+    volts_to_duty, Encoder, Feedforward, Motor, PWM_MAX,
+};
@@ -2,10 +2,7 @@ use core::i32;
 
 use stm32f4xx_hal::stm32 as stm32f405;
 
-use crate::motors::{Encoder, Motor};
-
-const FORWARD_DEADBAND: i32 = 0;
-const BACKWARD_DEADBAND: i32 = 0;
+use crate::motors::{Encoder, Motor, PWM_MAX};
 
 pub struct LeftMotor {
     timer: stm32f405::TIM3,
@@ -33,7 +30,7 @@ impl LeftMotor {
         // setup the timer
         timer.psc.write(|w| unsafe { w.psc().bits(10u16) });
         timer.cr1.write(|w| w.arpe().set_bit());
-        timer.arr.write(|w| w.arr().bits(10000u32));
+        timer.arr.write(|w| w.arr().bits(PWM_MAX as u32));
         timer.ccr1.write(|w| w.ccr1().bits(0u32));
         timer.ccr2.write(|w| w.ccr2().bits(0u32));
         timer.ccmr1_output.write(|w| unsafe {
@@ -58,12 +55,12 @@ impl Motor for LeftMotor {
     fn change_power(&mut self, power: i32) {
         self.timer.ccer.write(|w| {
             if power > 0 {
-                let speed = (power.abs() + FORWARD_DEADBAND) as u32;
+                let speed = power.abs() as u32;
                 self.timer.ccr1.write(|w| w.ccr1().bits(speed));
                 self.timer.ccr2.write(|w| w.ccr2().bits(speed));
                 w.cc1e().clear_bit().cc2e().set_bit()
             } else if power < 0 {
-                let speed = (power.abs() + BACKWARD_DEADBAND) as u32;
+                let speed = power.abs() as u32;
                 self.timer.ccr1.write(|w| w.ccr1().bits(speed));
                 self.timer.ccr2.write(|w| w.ccr2().bits(speed));
                 w.cc1e().set_bit().cc2e().clear_bit()
@@ -1,166 +1,52 @@
+use core::fmt::Debug;
 use core::fmt::Write;
 
 use ignore_result::Ignore;
 
-use rand::rngs::SmallRng;
-use rand::Rng;
-use rand::SeedableRng;
-
-use crate::plan::Direction;
-
-use crate::plan::Move;
-use crate::plan::MoveOptions;
+use micromouse_core::navigate::FloodFillNavigate;
+use micromouse_core::navigate::LessRandomNavigate;
+use micromouse_core::navigate::Navigate;
+use micromouse_core::navigate::RandomNavigate;
+use micromouse_core::navigate::TwelvePartitionNavigate;
 
 use crate::uart::Command;
 use crate::uart::Uart;
 
-const MAZE_SIZE: usize = 3;
-const MAZE_LIMIT: i32 = MAZE_SIZE as i32 - 1;
-
-pub trait Navigate: Command {
-    fn navigate(
-        &mut self,
-        x: i32,
-        y: i32,
-        dir: Direction,
-        move_options: MoveOptions,
-    ) -> [Option<Move>; 2];
-}
+const MAZE_SIZE: i32 = 16;
 
-pub struct LessRandomNavigate {
-    rng: SmallRng,
-    cells: [[u8; MAZE_SIZE]; MAZE_SIZE],
-    left_cell: u8,
-    right_cell: u8,
-    front_cell: u8,
-}
-
-impl LessRandomNavigate {
-    pub fn new(seed: [u8; 16]) -> LessRandomNavigate {
-        LessRandomNavigate {
-            rng: SmallRng::from_seed(seed),
-            cells: [[0; MAZE_SIZE]; MAZE_SIZE],
-            left_cell: 0,
-            right_cell: 0,
-            front_cell: 0,
+fn write_cells<N>(uart: &mut Uart, navigate: &N)
+where
+    N: Navigate,
+    N::Cell: Debug,
+{
+    for y in (0..MAZE_SIZE).rev() {
+        for x in 0..MAZE_SIZE {
+            write!(uart, "{:?}\t", navigate.get_cell(x, y)).ignore();
         }
+        writeln!(uart).ignore();
     }
+}
 
-    fn get_cell(&self, x: i32, y: i32) -> u8 {
-        if x >= 0 && x <= MAZE_LIMIT && y >= 0 && y <= MAZE_LIMIT {
-            self.cells[x as usize][y as usize]
-        } else {
-            255
-        }
+impl Command for LessRandomNavigate {
+    fn keyword_command(&self) -> &str {
+        "nav"
     }
-}
 
-impl Navigate for LessRandomNavigate {
-    fn navigate(
+    fn handle_command<'a, I: Iterator<Item = &'a str>>(
         &mut self,
-        x: i32,
-        y: i32,
-        d: Direction,
-        move_options: MoveOptions,
-    ) -> [Option<Move>; 2] {
-        let ux = if x < 0 {
-            0
-        } else if x > MAZE_LIMIT {
-            MAZE_LIMIT
-        } else {
-            x
-        } as usize;
-        let uy = if y < 0 {
-            0
-        } else if y > MAZE_LIMIT {
-            MAZE_LIMIT
-        } else {
-            y
-        } as usize;
-
-        if self.cells[ux][uy] < 255 {
-            self.cells[ux][uy] += 1;
-        }
+        uart: &mut Uart,
+        mut args: I,
+    ) {
+        let command = args.next();
 
-        // win condition
-        if x >= 7 && x <= 8 && y >= 7 && y <= 8 {
-            //[Some(Move::TurnLeft), Some(Move::TurnLeft)]
-            [Some(Move::TurnAround), Some(Move::TurnLeft)]
-        } else {
-            let left_cell = match d {
-                Direction::Up => self.get_cell(x - 1, y),
-                Direction::Down => self.get_cell(x + 1, y),
-                Direction::Right => self.get_cell(x, y + 1),
-                Direction::Left => self.get_cell(x, y - 1),
-            };
-
-            self.left_cell = left_cell;
-
-            let front_cell = match d {
-                Direction::Up => self.get_cell(x, y + 1),
-                Direction::Down => self.get_cell(x, y - 1),
-                Direction::Right => self.get_cell(x + 1, y),
-                Direction::Left => self.get_cell(x - 1, y),
-            };
-
-            self.front_cell = front_cell;
-
-            let right_cell = match d {
-                Direction::Up => self.get_cell(x + 1, y),
-                Direction::Down => self.get_cell(x - 1, y),
-                Direction::Right => self.get_cell(x, y - 1),
-                Direction::Left => self.get_cell(x, y + 1),
-            };
-
-            self.right_cell = right_cell;
-
-            if move_options.forward
-                && if move_options.left {
-                    front_cell <= left_cell
-                } else {
-                    true
-                }
-                && if move_options.right {
-                    front_cell <= right_cell
-                } else {
-                    true
-                }
-            {
-                [Some(Move::Forward), None]
-            } else if move_options.left
-                && if move_options.forward {
-                    left_cell <= front_cell
-                } else {
-                    true
-                }
-                && if move_options.right {
-                    left_cell <= right_cell
-                } else {
-                    true
-                }
-            {
-                [Some(Move::TurnLeft), Some(Move::Forward)]
-            } else if move_options.right
-                && if move_options.forward {
-                    right_cell <= front_cell
-                } else {
-                    true
-                }
-                && if move_options.left {
-                    right_cell <= left_cell
-                } else {
-                    true
-                }
-            {
-                [Some(Move::TurnRight), Some(Move::Forward)]
-            } else {
-                [Some(Move::TurnAround), Some(Move::Forward)]
-            }
+        match command {
+            Some("cells") => write_cells(uart, self),
+            c => writeln!(uart, "lrn: unknown command: {:?}", c).ignore(),
         }
     }
 }
 
-impl Command for LessRandomNavigate {
+impl Command for FloodFillNavigate {
     fn keyword_command(&self) -> &str {
         "nav"
     }
@@ -173,65 +59,27 @@ impl Command for LessRandomNavigate {
         let command = args.next();
 
         match command {
-            Some("cells") => writeln!(uart, "{:?}", self.cells).ignore(),
-            c => writeln!(uart, "lrn: unknown command: {:?}", c).ignore(),
+            Some("cells") => write_cells(uart, self),
+            c => writeln!(uart, "ffn: unknown command: {:?}", c).ignore(),
         }
     }
 }
 
-pub struct RandomNavigate {
-    rng: SmallRng,
-}
-
-impl RandomNavigate {
-    pub fn new(seed: [u8; 16]) -> RandomNavigate {
-        RandomNavigate {
-            rng: SmallRng::from_seed(seed),
-        }
+impl Command for TwelvePartitionNavigate {
+    fn keyword_command(&self) -> &str {
+        "nav"
     }
-}
 
-impl Navigate for RandomNavigate {
-    fn navigate(
+    fn handle_command<'a, I: Iterator<Item = &'a str>>(
         &mut self,
-        _x: i32,
-        _y: i32,
-        _d: Direction,
-        move_options: MoveOptions,
-    ) -> [Option<Move>; 2] {
-        match (move_options.left, move_options.forward, move_options.right) {
-            (true, true, true) => match self.rng.gen_range(0, 3) {
-                0 => [Some(Move::TurnLeft), Some(Move::Forward)],
-                1 => [Some(Move::TurnRight), Some(Move::Forward)],
-                _ => [Some(Move::Forward), None],
-            },
-
-            (true, false, true) => match self.rng.gen_range(0, 2) {
-                0 => [Some(Move::TurnLeft), Some(Move::Forward)],
-                _ => [Some(Move::TurnRight), Some(Move::Forward)],
-            },
-
-            (false, true, true) => match self.rng.gen_range(0, 2) {
-                0 => [Some(Move::TurnRight), Some(Move::Forward)],
-                _ => [Some(Move::Forward), None],
-            },
-
-            (true, true, false) => match self.rng.gen_range(0, 2) {
-                0 => [Some(Move::TurnLeft), Some(Move::Forward)],
-                _ => [Some(Move::Forward), None],
-            },
-
-            (false, true, false) => [Some(Move::Forward), None],
-
-            (true, false, false) => [Some(Move::TurnLeft), Some(Move::Forward)],
-
-            (false, false, true) => {
-                [Some(Move::TurnRight), Some(Move::Forward)]
-            }
-
-            (false, false, false) => {
-                [Some(Move::TurnAround), Some(Move::Forward)]
-            }
+        uart: &mut Uart,
+        mut args: I,
+    ) {
+        let command = args.next();
+
+        match command {
+            Some("cells") => write_cells(uart, self),
+            c => writeln!(uart, "tpn: unknown command: {:?}", c).ignore(),
         }
     }
 }
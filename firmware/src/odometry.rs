@@ -0,0 +1,112 @@
+use core::f32::consts::PI;
+
+use micromath::F32Ext;
+
+use micromouse_core::mouse::MouseConfig;
+
+/**
+ *  Where the mouse is in the maze
+ *
+ *  x and y are in mm, with x to the east and y to the north. The heading is
+ *  in radians, counterclockwise from the x axis, and kept within -pi..pi.
+ */
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Pose {
+    pub x: f32,
+    pub y: f32,
+    pub heading: f32,
+}
+
+fn wrap_angle(angle: f32) -> f32 {
+    let mut angle = angle;
+
+    while angle > PI {
+        angle -= 2.0 * PI;
+    }
+
+    while angle <= -PI {
+        angle += 2.0 * PI;
+    }
+
+    angle
+}
+
+/**
+ *  Integrates the wheel encoders into a pose
+ *
+ *  The encoders get reset after every move, so this only keeps track of the
+ *  last counts it saw and needs to be told when the encoders are reset.
+ */
+pub struct Odometry {
+    pose: Pose,
+    last_left: f32,
+    last_right: f32,
+}
+
+impl Odometry {
+    pub fn new(pose: Pose) -> Odometry {
+        Odometry {
+            pose,
+            last_left: 0.0,
+            last_right: 0.0,
+        }
+    }
+
+    /// Update the pose with the current encoder counts, in ticks
+    pub fn update(&mut self, mouse: &MouseConfig, left: f32, right: f32) {
+        let delta_left = mouse.ticks_to_mm(left - self.last_left);
+        let delta_right = mouse.ticks_to_mm(right - self.last_right);
+
+        let delta_linear = (delta_left + delta_right) / 2.0;
+
+        // Positive spin is clockwise, but the heading is counterclockwise
+        let delta_heading = -mouse.mm_to_rads((delta_left - delta_right) / 2.0);
+
+        let mid_heading = self.pose.heading + delta_heading / 2.0;
+
+        self.pose.x += delta_linear * mid_heading.cos();
+        self.pose.y += delta_linear * mid_heading.sin();
+        self.pose.heading = wrap_angle(self.pose.heading + delta_heading);
+
+        self.last_left = left;
+        self.last_right = right;
+    }
+
+    /// The encoders were set back to 0
+    pub fn encoders_reset(&mut self) {
+        self.last_left = 0.0;
+        self.last_right = 0.0;
+    }
+
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    pub fn set_pose(&mut self, pose: Pose) {
+        self.pose = Pose {
+            heading: wrap_angle(pose.heading),
+            ..pose
+        };
+    }
+
+    /**
+     *  Move the x position towards a measured one
+     *
+     *  The weight is how much the measurement is trusted over the current
+     *  estimate, from 0 (ignore it) to 1 (take it as is).
+     */
+    pub fn correct_x(&mut self, x: f32, weight: f32) {
+        self.pose.x += (x - self.pose.x) * weight;
+    }
+
+    /// Move the y position towards a measured one, like `correct_x`
+    pub fn correct_y(&mut self, y: f32, weight: f32) {
+        self.pose.y += (y - self.pose.y) * weight;
+    }
+
+    /// Move the heading towards a measured one, like `correct_x`
+    pub fn correct_heading(&mut self, heading: f32, weight: f32) {
+        let error = wrap_angle(heading - self.pose.heading);
+        self.pose.heading = wrap_angle(self.pose.heading + error * weight);
+    }
+}
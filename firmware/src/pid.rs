@@ -0,0 +1,39 @@
+/*!
+ *  The PID the controllers in micromouse-core run on the mouse
+ */
+use pid_control::Controller as _;
+use pid_control::PIDController;
+
+use micromouse_core::pid::Controller;
+
+pub struct Pid(pub PIDController);
+
+impl Pid {
+    pub fn set_gains(&mut self, p: f64, i: f64, d: f64) {
+        self.0.p_gain = p;
+        self.0.i_gain = i;
+        self.0.d_gain = d;
+    }
+
+    pub fn gains(&self) -> (f64, f64, f64) {
+        (self.0.p_gain, self.0.i_gain, self.0.d_gain)
+    }
+}
+
+impl Controller for Pid {
+    fn update(&mut self, value: f64, delta_t: f64) -> f64 {
+        self.0.update(value, delta_t)
+    }
+
+    fn set_target(&mut self, target: f64) {
+        self.0.set_target(target);
+    }
+
+    fn target(&self) -> f64 {
+        self.0.target()
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+}
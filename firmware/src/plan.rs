@@ -6,87 +6,111 @@ use arrayvec::ArrayVec;
 
 use crate::control::Control;
 
-use crate::navigate::Navigate;
+use crate::motors::Encoder;
+use crate::motors::Motor;
 
-use crate::uart::Command;
-use crate::uart::Uart;
-
-#[derive(Copy, Clone)]
-pub enum Move {
-    TurnLeft,
-    TurnRight,
-    TurnAround,
-    Forward,
-}
-
-pub struct MoveOptions {
-    pub forward: bool,
-    pub left: bool,
-    pub right: bool,
-}
+use micromouse_core::distance::AmbientSample;
+use micromouse_core::distance::DistanceSensor;
+use micromouse_core::vl6180x::Vl6180xExt;
+use micromouse_core::walls::WallDetector;
+use micromouse_core::walls::Walls;
 
-#[derive(Copy, Clone, Debug)]
-pub enum Direction {
-    Up,
-    Down,
-    Left,
-    Right,
-}
+use crate::report::Snapshot;
 
-impl Direction {
-    pub fn turn_left(self) -> Direction {
-        match self {
-            Direction::Up => Direction::Left,
-            Direction::Left => Direction::Down,
-            Direction::Down => Direction::Right,
-            Direction::Right => Direction::Up,
-        }
-    }
+use micromouse_core::navigate::Direction;
+use micromouse_core::navigate::Move;
+use micromouse_core::navigate::MoveOptions;
+use micromouse_core::navigate::Navigate;
+use micromouse_core::telemetry::MoveKind;
 
-    pub fn turn_right(self) -> Direction {
-        match self {
-            Direction::Up => Direction::Right,
-            Direction::Right => Direction::Down,
-            Direction::Down => Direction::Left,
-            Direction::Left => Direction::Up,
-        }
-    }
-}
+use crate::uart::Command;
+use crate::uart::Uart;
 
-pub struct Plan<N>
+pub struct Plan<N, LM, LE, RM, RE, FD, LD, RD>
 where
-    N: Navigate,
+    N: Navigate + Command,
+    LM: Motor,
+    LE: Encoder,
+    RM: Motor,
+    RE: Encoder,
+    FD: DistanceSensor,
+    LD: DistanceSensor,
+    RD: DistanceSensor,
 {
-    control: Control,
+    control: Control<LM, LE, RM, RE, FD, LD, RD>,
     move_buffer: ArrayVec<[Move; 32]>,
     going: bool,
     navigate: N,
+    walls: WallDetector,
+    last_walls: Walls,
+    /// Whether the current move is driving forward a cell
+    crossing: bool,
     x_pos: i32,
     y_pos: i32,
     direction: Direction,
 }
 
-impl<N> Plan<N>
+impl<N, LM, LE, RM, RE, FD, LD, RD> Plan<N, LM, LE, RM, RE, FD, LD, RD>
 where
-    N: Navigate,
+    N: Navigate + Command,
+    LM: Motor,
+    LE: Encoder,
+    RM: Motor,
+    RE: Encoder,
+    FD: DistanceSensor,
+    LD: DistanceSensor,
+    RD: DistanceSensor,
 {
-    pub fn new(control: Control, navigate: N) -> Plan<N> {
+    pub fn new(
+        control: Control<LM, LE, RM, RE, FD, LD, RD>,
+        navigate: N,
+    ) -> Plan<N, LM, LE, RM, RE, FD, LD, RD> {
         Plan {
             control,
             move_buffer: ArrayVec::new(),
             going: false,
             navigate,
+            walls: WallDetector::new(),
+            last_walls: Walls::default(),
+            crossing: false,
             x_pos: 0,
             y_pos: 0,
-            direction: Direction::Up,
+            direction: Direction::North,
         }
     }
 
     pub fn update(&mut self, now: u32) {
+        if self.crossing && !self.control.is_idle() {
+            // Read the walls over the end of the cell, where they are
+            // closest to the walls of the next one
+            let bot = self.control.bot();
+            let window_start =
+                (1.0 - bot.config.wall_window) * bot.config.ticks_per_cell;
+
+            if bot.linear_pos() >= window_start {
+                self.walls.update(
+                    &bot.config,
+                    bot.front_filtered(),
+                    bot.left_filtered(),
+                    bot.right_filtered(),
+                );
+            }
+        }
+
         if self.control.is_idle() {
+            self.crossing = false;
+
             if let Some(next_move) = self.move_buffer.pop_at(0) {
                 let ticks_per_spin = self.control.bot().config.ticks_per_spin;
                 let ticks_per_cell = self.control.bot().config.ticks_per_cell;
+
+                if next_move == Move::Forward {
+                    self.walls.start();
+                    self.crossing = true;
+                } else {
+                    self.walls.reset();
+                }
+
                 match next_move {
                     Move::TurnLeft => {
                         self.control.spin(-ticks_per_spin / 4.0);
@@ -100,17 +124,16 @@ where
 
                     Move::TurnAround => {
                         self.control.spin(ticks_per_spin / 2.0);
-                        self.direction =
-                            self.direction().turn_right().turn_right();
+                        self.direction = self.direction.turn_around();
                     }
 
                     Move::Forward => {
                         self.control.linear(ticks_per_cell);
                         let (dx, dy) = match self.direction {
-                            Direction::Up => (0, 1),
-                            Direction::Down => (0, -1),
-                            Direction::Left => (-1, 0),
-                            Direction::Right => (1, 0),
+                            Direction::North => (0, 1),
+                            Direction::South => (0, -1),
+                            Direction::West => (-1, 0),
+                            Direction::East => (1, 0),
                         };
                         self.x_pos += dx;
                         self.y_pos += dy;
@@ -118,12 +141,24 @@ where
                 }
             } else {
                 if self.going {
-                    let threshold = self.control.bot().config.wall_threshold;
+                    let bot = self.control.bot();
+
+                    // One more look from where the mouse stopped, which is
+                    // all there is after a turn
+                    self.walls.update(
+                        &bot.config,
+                        bot.front_filtered(),
+                        bot.left_filtered(),
+                        bot.right_filtered(),
+                    );
+
+                    let walls = self.walls.walls();
+                    self.last_walls = walls;
+
                     let move_options = MoveOptions {
-                        left: self.control.bot().left_distance() > threshold,
-                        forward: self.control.bot().front_distance()
-                            > threshold,
-                        right: self.control.bot().right_distance() > threshold,
+                        left: !walls.left.present,
+                        forward: !walls.front.present,
+                        right: !walls.right.present,
                     };
 
                     let next_moves = self.navigate.navigate(
@@ -149,7 +184,7 @@ where
         }
     }
 
-    pub fn control(&mut self) -> &mut Control {
+    pub fn control(&mut self) -> &mut Control<LM, LE, RM, RE, FD, LD, RD> {
         &mut self.control
     }
 
@@ -174,14 +209,79 @@ where
         self.direction
     }
 
+    /// The walls around the cell the last moves were planned from
+    pub fn walls(&self) -> Walls {
+        self.last_walls
+    }
+
     pub fn is_win(&self) -> bool {
         self.x_pos == 1 && self.y_pos == 1
     }
 }
 
-impl<N> Command for Plan<N>
+impl<N, LM, LE, RM, RE, FD, LD, RD> Plan<N, LM, LE, RM, RE, FD, LD, RD>
 where
-    N: Navigate,
+    N: Navigate + Command,
+    LM: Motor,
+    LE: Encoder,
+    RM: Motor,
+    RE: Encoder,
+    FD: Vl6180xExt,
+    LD: Vl6180xExt,
+    RD: Vl6180xExt,
+{
+    /// Copy out everything that can be reported
+    pub fn snapshot(&self, now: u32) -> Snapshot {
+        let bot = self.control.bot();
+        let pose = bot.pose();
+        let lux = |ambient: Option<AmbientSample>| {
+            ambient.map_or(core::f64::NAN, |a| f64::from(a.lux))
+        };
+
+        Snapshot {
+            time: now,
+            left_pos: bot.left_pos(),
+            right_pos: bot.right_pos(),
+            left_velocity: bot.left_velocity(),
+            right_velocity: bot.right_velocity(),
+            left_target: bot.left_target(),
+            right_target: bot.right_target(),
+            left_volts: bot.left_volts(),
+            right_volts: bot.right_volts(),
+            left_duty: f64::from(bot.left_duty()),
+            right_duty: f64::from(bot.right_duty()),
+            linear_pos: bot.linear_pos(),
+            spin_pos: bot.spin_pos(),
+            linear_velocity: bot.linear_velocity(),
+            spin_velocity: bot.spin_velocity(),
+            front_distance: bot.front_distance(),
+            left_distance: bot.left_distance(),
+            right_distance: bot.right_distance(),
+            front_lux: lux(bot.front_ambient()),
+            left_lux: lux(bot.left_ambient()),
+            right_lux: lux(bot.right_ambient()),
+            current_move: MoveKind::from_name(self.control.current_move_name()),
+            x: pose.x,
+            y: pose.y,
+            heading: pose.heading,
+            cell_x: self.x_pos,
+            cell_y: self.y_pos,
+            direction: self.direction,
+        }
+    }
+}
+
+impl<N, LM, LE, RM, RE, FD, LD, RD> Command
+    for Plan<N, LM, LE, RM, RE, FD, LD, RD>
+where
+    N: Navigate + Command,
+    LM: Motor,
+    LE: Encoder,
+    RM: Motor,
+    RE: Encoder,
+    FD: Vl6180xExt,
+    LD: Vl6180xExt,
+    RD: Vl6180xExt,
 {
     fn keyword_command(&self) -> &str {
         "plan"
@@ -206,6 +306,22 @@ where
                 Some("forward") => self.add_moves(&[Some(Move::Forward)]),
                 Some("go") => self.go(),
                 Some("stop") => self.stop(),
+                Some("walls") => {
+                    let sides = [
+                        ("front", self.last_walls.front),
+                        ("left", self.last_walls.left),
+                        ("right", self.last_walls.right),
+                    ];
+
+                    for (name, wall) in sides.iter() {
+                        writeln!(
+                            uart,
+                            "{}: present: {} confidence: {}",
+                            name, wall.present, wall.confidence
+                        )
+                        .ignore();
+                    }
+                }
                 _ => writeln!(uart, "plan: unknown command").ignore(),
             }
         }
@@ -0,0 +1,340 @@
+use core::fmt::Write;
+
+use ignore_result::Ignore;
+
+use arrayvec::ArrayVec;
+
+use crate::uart::Command;
+use crate::uart::Uart;
+
+use micromouse_core::navigate::Direction;
+use micromouse_core::telemetry::MoveKind;
+
+const MAX_SIGNALS: usize = 16;
+
+/// Everything that can be reported, copied out of the plan at one time
+#[derive(Copy, Clone, Debug)]
+pub struct Snapshot {
+    pub time: u32,
+
+    pub left_pos: f64,
+    pub right_pos: f64,
+    pub left_velocity: f64,
+    pub right_velocity: f64,
+    pub left_target: f64,
+    pub right_target: f64,
+    pub left_volts: f64,
+    pub right_volts: f64,
+    pub left_duty: f64,
+    pub right_duty: f64,
+
+    pub linear_pos: f64,
+    pub spin_pos: f64,
+    pub linear_velocity: f64,
+    pub spin_velocity: f64,
+
+    pub front_distance: f64,
+    pub left_distance: f64,
+    pub right_distance: f64,
+
+    /// Ambient light, NaN when it isn't being measured
+    pub front_lux: f64,
+    pub left_lux: f64,
+    pub right_lux: f64,
+
+    pub current_move: MoveKind,
+
+    pub x: f32,
+    pub y: f32,
+    pub heading: f32,
+
+    pub cell_x: i32,
+    pub cell_y: i32,
+    pub direction: Direction,
+}
+
+pub struct Signal {
+    pub name: &'static str,
+    pub units: &'static str,
+    pub get: fn(&Snapshot) -> f64,
+}
+
+pub static SIGNALS: &[Signal] = &[
+    Signal {
+        name: "time",
+        units: "ms",
+        get: |s| f64::from(s.time),
+    },
+    Signal {
+        name: "left_pos",
+        units: "ticks",
+        get: |s| s.left_pos,
+    },
+    Signal {
+        name: "right_pos",
+        units: "ticks",
+        get: |s| s.right_pos,
+    },
+    Signal {
+        name: "left_velocity",
+        units: "ticks/ms",
+        get: |s| s.left_velocity,
+    },
+    Signal {
+        name: "right_velocity",
+        units: "ticks/ms",
+        get: |s| s.right_velocity,
+    },
+    Signal {
+        name: "left_target",
+        units: "ticks/ms",
+        get: |s| s.left_target,
+    },
+    Signal {
+        name: "right_target",
+        units: "ticks/ms",
+        get: |s| s.right_target,
+    },
+    Signal {
+        name: "left_volts",
+        units: "V",
+        get: |s| s.left_volts,
+    },
+    Signal {
+        name: "right_volts",
+        units: "V",
+        get: |s| s.right_volts,
+    },
+    Signal {
+        name: "left_duty",
+        units: "counts",
+        get: |s| s.left_duty,
+    },
+    Signal {
+        name: "right_duty",
+        units: "counts",
+        get: |s| s.right_duty,
+    },
+    Signal {
+        name: "linear_pos",
+        units: "ticks",
+        get: |s| s.linear_pos,
+    },
+    Signal {
+        name: "spin_pos",
+        units: "ticks",
+        get: |s| s.spin_pos,
+    },
+    Signal {
+        name: "linear_velocity",
+        units: "ticks/ms",
+        get: |s| s.linear_velocity,
+    },
+    Signal {
+        name: "spin_velocity",
+        units: "ticks/ms",
+        get: |s| s.spin_velocity,
+    },
+    Signal {
+        name: "front_distance",
+        units: "mm",
+        get: |s| s.front_distance,
+    },
+    Signal {
+        name: "left_distance",
+        units: "mm",
+        get: |s| s.left_distance,
+    },
+    Signal {
+        name: "right_distance",
+        units: "mm",
+        get: |s| s.right_distance,
+    },
+    Signal {
+        name: "front_lux",
+        units: "lux",
+        get: |s| s.front_lux,
+    },
+    Signal {
+        name: "left_lux",
+        units: "lux",
+        get: |s| s.left_lux,
+    },
+    Signal {
+        name: "right_lux",
+        units: "lux",
+        get: |s| s.right_lux,
+    },
+    Signal {
+        name: "move",
+        units: "0 idle, 1 spin, 2 linear, 3 path",
+        get: |s| match s.current_move {
+            MoveKind::Idle => 0.0,
+            MoveKind::Spin => 1.0,
+            MoveKind::Linear => 2.0,
+            MoveKind::Path => 3.0,
+        },
+    },
+    Signal {
+        name: "x",
+        units: "mm",
+        get: |s| f64::from(s.x),
+    },
+    Signal {
+        name: "y",
+        units: "mm",
+        get: |s| f64::from(s.y),
+    },
+    Signal {
+        name: "heading",
+        units: "rad",
+        get: |s| f64::from(s.heading),
+    },
+    Signal {
+        name: "cell_x",
+        units: "cells",
+        get: |s| f64::from(s.cell_x),
+    },
+    Signal {
+        name: "cell_y",
+        units: "cells",
+        get: |s| f64::from(s.cell_y),
+    },
+    Signal {
+        name: "direction",
+        units: "0 north, 1 east, 2 south, 3 west",
+        get: |s| match s.direction {
+            Direction::North => 0.0,
+            Direction::East => 1.0,
+            Direction::South => 2.0,
+            Direction::West => 3.0,
+        },
+    },
+];
+
+fn find(name: &str) -> Option<usize> {
+    SIGNALS.iter().position(|s| s.name == name)
+}
+
+/**
+ *  Prints the chosen signals, tab separated, every period
+ *
+ *  The signals are picked at runtime out of `SIGNALS`, so they can be
+ *  changed on the bench without reflashing.
+ */
+pub struct Report {
+    signals: ArrayVec<[usize; MAX_SIGNALS]>,
+    period: u32,
+    last_report: u32,
+    running: bool,
+}
+
+impl Default for Report {
+    fn default() -> Report {
+        Report::new()
+    }
+}
+
+impl Report {
+    pub fn new() -> Report {
+        let mut signals = ArrayVec::new();
+        signals.push(0);
+
+        Report {
+            signals,
+            period: 20,
+            last_report: 0,
+            running: false,
+        }
+    }
+
+    pub fn start(&mut self) {
+        self.running = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    /// Whether a report is due, so the snapshot only gets taken when needed
+    pub fn is_due(&self, now: u32) -> bool {
+        self.running && now.wrapping_sub(self.last_report) >= self.period
+    }
+
+    pub fn update(&mut self, uart: &mut Uart, snapshot: &Snapshot) {
+        for (i, &signal) in self.signals.iter().enumerate() {
+            if i > 0 {
+                write!(uart, "\t").ignore();
+            }
+            write!(uart, "{}", (SIGNALS[signal].get)(snapshot)).ignore();
+        }
+        writeln!(uart).ignore();
+
+        self.last_report = snapshot.time;
+    }
+
+    fn add(&mut self, uart: &mut Uart, name: &str) {
+        match find(name) {
+            Some(signal) if self.signals.contains(&signal) => {}
+            Some(signal) => {
+                if self.signals.try_push(signal).is_err() {
+                    writeln!(uart, "report: too many signals").ignore();
+                }
+            }
+            None => writeln!(uart, "report: unknown signal").ignore(),
+        }
+    }
+
+    fn remove(&mut self, uart: &mut Uart, name: &str) {
+        match find(name) {
+            Some(signal) => self.signals.retain(|s| *s != signal),
+            None => writeln!(uart, "report: unknown signal").ignore(),
+        }
+    }
+
+    fn list(&self, uart: &mut Uart) {
+        for (i, signal) in SIGNALS.iter().enumerate() {
+            let mark = if self.signals.contains(&i) { "*" } else { " " };
+            writeln!(uart, "{} {} ({})", mark, signal.name, signal.units)
+                .ignore();
+        }
+        writeln!(uart, "period: {} ms", self.period).ignore();
+    }
+}
+
+impl Command for Report {
+    fn keyword_command(&self) -> &str {
+        "report"
+    }
+
+    fn handle_command<'a, I: Iterator<Item = &'a str>>(
+        &mut self,
+        uart: &mut Uart,
+        mut args: I,
+    ) {
+        match args.next() {
+            Some("add") => {
+                for name in args {
+                    self.add(uart, name);
+                }
+            }
+            Some("remove") => {
+                for name in args {
+                    self.remove(uart, name);
+                }
+            }
+            Some("clear") => self.signals.clear(),
+            Some("period") => match args.next().map(str::parse::<u32>) {
+                Some(Ok(period)) if period > 0 => self.period = period,
+                Some(_) => writeln!(uart, "report: invalid period").ignore(),
+                None => {
+                    writeln!(uart, "report: period {} ms", self.period).ignore()
+                }
+            },
+            Some("start") => self.start(),
+            Some("stop") => self.stop(),
+            Some("list") | None => self.list(uart),
+            _ => writeln!(uart, "report: unknown command").ignore(),
+        }
+    }
+}
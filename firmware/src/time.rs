@@ -2,6 +2,11 @@ use stm32f4xx_hal::stm32 as stm32f405;
 
 static OVERFLOW_VALUE: u32 = 65535;
 
+/// The raw ms count, for interrupts that can't get at `Time`
+pub fn counter() -> u16 {
+    unsafe { (*stm32f405::TIM1::ptr()).cnt.read().cnt().bits() }
+}
+
 pub struct Time {
     timer: stm32f405::TIM1,
     last_time: u32,
@@ -2,6 +2,7 @@ use core::fmt;
 use core::fmt::Write;
 
 use core::cell::RefCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use cortex_m::interrupt::Mutex;
 use cortex_m_rt_macros::interrupt as isr;
@@ -9,6 +10,12 @@ use cortex_m_rt_macros::interrupt as isr;
 use stm32f4xx_hal::stm32 as stm32f405;
 use stm32f4xx_hal::stm32::Interrupt as interrupt;
 
+use arrayvec::ArrayVec;
+
+use micromouse_core::line::{LineAssembler, LINE_LEN};
+use micromouse_core::ring::RingBuffer;
+use micromouse_core::telemetry::{encode_frame, Message, MAX_FRAME};
+
 pub trait Command {
     fn keyword_command(&self) -> &str;
     fn handle_command<'a, I: Iterator<Item = &'a str>>(
@@ -18,22 +25,29 @@ pub trait Command {
     );
 }
 
-const RX_BUFFER_LEN: usize = 1024;
-const TX_BUFFER_LEN: usize = 1024;
-
-struct Buffer<T> {
-    bytes: T,
-    len: usize,
-}
+/// The USART1 transmit request is on DMA2 stream 7, channel 4
+const DMA_CHANNEL: u8 = 4;
 
 static UART: Mutex<RefCell<Option<stm32f405::USART1>>> =
     Mutex::new(RefCell::new(None));
 
-static RX_BUF: Mutex<RefCell<Buffer<[u8; RX_BUFFER_LEN]>>> =
-    Mutex::new(RefCell::new(Buffer { bytes: [0; RX_BUFFER_LEN], len: 0 }));
+static DMA: Mutex<RefCell<Option<stm32f405::DMA2>>> =
+    Mutex::new(RefCell::new(None));
 
-static TX_BUF: Mutex<RefCell<Buffer<[u8; TX_BUFFER_LEN]>>> =
-    Mutex::new(RefCell::new(Buffer { bytes: [0; TX_BUFFER_LEN], len: 0 }));
+/// Filled by the USART1 interrupt, emptied by `read_line`
+static RX_BUF: RingBuffer = RingBuffer::new();
+
+/// Filled by `add_bytes`, emptied by DMA
+static TX_BUF: RingBuffer = RingBuffer::new();
+
+/// How many bytes the current DMA transfer is sending, 0 when idle
+static TX_IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+static RX_DROPPED: AtomicUsize = AtomicUsize::new(0);
+static RX_OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+static TX_DROPPED: AtomicUsize = AtomicUsize::new(0);
+static REPLIES_DROPPED: AtomicUsize = AtomicUsize::new(0);
+static TX_ERRORS: AtomicUsize = AtomicUsize::new(0);
 
 pub enum TxError {
     BufferFull,
@@ -53,20 +67,49 @@ pub enum RxError {
     NotInitialized,
 }
 
-pub struct Uart { }
+/// Counts of everything the driver had to throw away
+#[derive(Copy, Clone, Debug, Default)]
+pub struct UartStats {
+    /// Received bytes that didn't fit in the receive buffer
+    pub rx_dropped: usize,
+    /// Received bytes the hardware lost before the interrupt read them
+    pub rx_overruns: usize,
+    /// Telemetry bytes that didn't fit in the transmit buffer, or bytes that
+    /// were in a transfer that failed
+    pub tx_dropped: usize,
+    /// Bytes of replies to commands that didn't fit in the transmit buffer
+    pub replies_dropped: usize,
+    /// Transmit DMA transfers that failed
+    pub tx_errors: usize,
+    /// Lines longer than `LINE_LEN`
+    pub long_lines: u32,
+}
+
+/**
+ *  Interrupt driven receive and DMA driven transmit on USART1
+ *
+ *  Both directions go through lock free ring buffers, so writing never
+ *  waits on the uart. When the transmit buffer is full, the whole write is
+ *  dropped and counted instead of blocking the control loop.
+ */
+pub struct Uart {
+    lines: LineAssembler,
+}
 
 impl Uart {
     pub fn setup(
         rcc: &stm32f405::RCC,
         nvic: &mut stm32f405::NVIC,
         uart: stm32f405::USART1,
+        dma: stm32f405::DMA2,
         gpioa: &stm32f405::GPIOA,
     ) -> Uart {
         // enable clock for usart
         rcc.apb2enr.modify(|_, w| w.usart1en().set_bit());
 
-        // enable clock for gpioa
-        rcc.ahb1enr.modify(|_, w| w.gpioaen().set_bit());
+        // enable clock for gpioa and dma2
+        rcc.ahb1enr
+            .modify(|_, w| w.gpioaen().set_bit().dma2en().set_bit());
 
         // set pins to alternate function
         gpioa
@@ -79,6 +122,9 @@ impl Uart {
         // set buadrate
         uart.brr.write(|w| unsafe { w.bits(0x683) });
 
+        // transmit with dma
+        uart.cr3.write(|w| w.dmat().set_bit());
+
         // enable rx and tx
         uart.cr1.write(|w| {
             w.ue()
@@ -89,125 +135,217 @@ impl Uart {
                 .set_bit()
                 .rxneie()
                 .set_bit()
+        });
+
+        // memory to peripheral, incrementing memory, one byte at a time,
+        // interrupt when done or on an error
+        dma.s7par
+            .write(|w| unsafe { w.bits(&uart.dr as *const _ as u32) });
+        dma.s7cr.write(|w| unsafe {
+            w.chsel()
+                .bits(DMA_CHANNEL)
+                .dir()
+                .bits(0b01)
+                .minc()
+                .set_bit()
+                .msize()
+                .bits(0b00)
+                .psize()
+                .bits(0b00)
                 .tcie()
                 .set_bit()
+                .teie()
+                .set_bit()
+                .dmeie()
+                .set_bit()
         });
 
-        cortex_m::interrupt::free(|cs| UART.borrow(cs).replace(Some(uart)));
+        cortex_m::interrupt::free(|cs| {
+            UART.borrow(cs).replace(Some(uart));
+            DMA.borrow(cs).replace(Some(dma));
+        });
 
         nvic.enable(interrupt::USART1);
+        nvic.enable(interrupt::DMA2_STREAM7);
 
-        Uart { }
+        Uart {
+            lines: LineAssembler::new(),
+        }
     }
 
-    fn add_byte(&mut self, c: u8) -> Result<(), TxError> {
-        cortex_m::interrupt::free(|cs| {
-            if let Ok(mut buf) = TX_BUF.borrow(cs).try_borrow_mut() {
-                if buf.len < buf.bytes.len() {
-                    for i in (1..buf.len).rev() {
-                        buf.bytes[i] = buf.bytes[i-1];
-                    }
-                    buf.bytes[0] = c;
-                    buf.len += 1;
-
-                    if buf.len == 1 {
-                        if let Some(uart) = UART.borrow(cs).borrow().as_ref() {
-                            uart.dr.write(|w| w.dr().bits(buf.bytes[0] as u16));
-                            Ok(())
-                        } else {
-                            Err(TxError::Busy)
-                        }
-                    } else {
-                        Ok(())
-                    }
-                } else {
-                    Err(TxError::BufferFull)
-                }
-            } else {
-                Err(TxError::Busy)
-            }
-        })
-    }
+    /// Queue bytes to send, all or none of them
+    pub fn add_bytes(&mut self, bytes: &[u8]) -> Result<(), TxError> {
+        if bytes.len() > TX_BUF.free() {
+            return Err(TxError::BufferFull);
+        }
 
-    pub fn add_str(&mut self, s: &str) -> Result<(), TxError> {
-        for &c in s.as_bytes() {
-            self.add_byte(c)?;
+        for &c in bytes {
+            TX_BUF.push(c).map_err(|_| TxError::BufferFull)?;
         }
 
+        start_tx();
+
         Ok(())
     }
 
-    pub fn read_byte(&mut self) -> Result<u8, RxError> {
-        cortex_m::interrupt::free(|cs| {
-            if let Ok(mut buf) = RX_BUF.borrow(cs).try_borrow_mut() {
-                if buf.len > 0 {
-                    let c = buf.bytes[0];
-                    for i in 1..buf.len {
-                        buf.bytes[i-1] = buf.bytes[i];
-                    }
-                    buf.len -= 1;
-                    let len = buf.len;
-                    buf.bytes[len] = 0;
-                    Ok(c)
-                } else {
-                    Err(RxError::BufferEmpty)
-                }
-            } else {
-                Err(RxError::Busy)
-            }
+    pub fn add_str(&mut self, s: &str) -> Result<(), TxError> {
+        self.add_bytes(s.as_bytes())
+    }
+
+    /// Send a telemetry message as a binary frame
+    pub fn add_message(&mut self, message: &Message) -> Result<(), TxError> {
+        let mut frame = [0; MAX_FRAME];
+        let len = encode_frame(message, &mut frame)
+            .map_err(|_| TxError::BufferFull)?;
+        self.add_bytes(&frame[..len]).map_err(|err| {
+            TX_DROPPED.fetch_add(len, Ordering::Relaxed);
+            err
         })
     }
 
-    pub fn read_line(&mut self) -> Result<[u8; RX_BUFFER_LEN], RxError> {
-        cortex_m::interrupt::free(|cs| {
-            if let Ok(mut buf) = RX_BUF.borrow(cs).try_borrow_mut() {
-                if buf.len > 0 && buf.bytes[buf.len-1] == 0x0A {
-                    let new_buf = buf.bytes.clone();
-                    buf.bytes = [0; RX_BUFFER_LEN];
-                    buf.len = 0;
-                    Ok(new_buf)
-                } else {
-                    Err(RxError::BufferEmpty)
-                }
-            } else {
-                Err(RxError::Busy)
+    pub fn read_byte(&mut self) -> Result<u8, RxError> {
+        RX_BUF.pop().ok_or(RxError::BufferEmpty)
+    }
+
+    /**
+     *  The next complete line that was received, without the newline
+     *
+     *  This only reads as far as the end of the first line, so if several
+     *  came in at once the rest stay queued for the next call.
+     */
+    pub fn read_line(&mut self) -> Result<ArrayVec<[u8; LINE_LEN]>, RxError> {
+        while let Some(c) = RX_BUF.pop() {
+            if let Some(line) = self.lines.push(c) {
+                let mut bytes = ArrayVec::new();
+                bytes.extend(line.iter().cloned());
+                return Ok(bytes);
             }
-        })
+        }
+
+        Err(RxError::BufferEmpty)
+    }
+
+    pub fn stats(&self) -> UartStats {
+        UartStats {
+            rx_dropped: RX_DROPPED.load(Ordering::Relaxed),
+            rx_overruns: RX_OVERRUNS.load(Ordering::Relaxed),
+            tx_dropped: TX_DROPPED.load(Ordering::Relaxed),
+            replies_dropped: REPLIES_DROPPED.load(Ordering::Relaxed),
+            tx_errors: TX_ERRORS.load(Ordering::Relaxed),
+            long_lines: self.lines.overflows(),
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        RX_DROPPED.store(0, Ordering::Relaxed);
+        RX_OVERRUNS.store(0, Ordering::Relaxed);
+        TX_DROPPED.store(0, Ordering::Relaxed);
+        REPLIES_DROPPED.store(0, Ordering::Relaxed);
+        TX_ERRORS.store(0, Ordering::Relaxed);
+        self.lines.reset_overflows();
     }
 }
 
+/// For replies to commands, which are counted apart from the telemetry
 impl Write for Uart {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.add_str(s).map(|_| ()).map_err(|_| fmt::Error)
+        self.add_str(s).map_err(|_| {
+            REPLIES_DROPPED.fetch_add(s.len(), Ordering::Relaxed);
+            fmt::Error
+        })
     }
 }
 
+/// Start sending whatever is queued, unless a transfer is already going
+fn start_tx() {
+    cortex_m::interrupt::free(|cs| {
+        if TX_IN_FLIGHT.load(Ordering::Relaxed) != 0 {
+            return;
+        }
+
+        if let Some(dma) = DMA.borrow(cs).borrow().as_ref() {
+            let bytes = TX_BUF.contiguous();
+            if bytes.is_empty() {
+                return;
+            }
+
+            TX_IN_FLIGHT.store(bytes.len(), Ordering::Relaxed);
+
+            dma.hifcr.write(|w| {
+                w.ctcif7()
+                    .set_bit()
+                    .chtif7()
+                    .set_bit()
+                    .cteif7()
+                    .set_bit()
+                    .cdmeif7()
+                    .set_bit()
+                    .cfeif7()
+                    .set_bit()
+            });
+            dma.s7m0ar
+                .write(|w| unsafe { w.bits(bytes.as_ptr() as u32) });
+            dma.s7ndtr.write(|w| unsafe { w.bits(bytes.len() as u32) });
+            dma.s7cr.modify(|_, w| w.en().set_bit());
+        }
+    });
+}
+
 #[isr]
 fn USART1() {
     cortex_m::interrupt::free(|cs| {
         if let Some(uart) = UART.borrow(cs).borrow().as_ref() {
-            if uart.sr.read().rxne().bit() {
-                let rx = uart.dr.read().dr().bits() as u8;
-                //uart.dr.write(|w| w.dr().bits(rx as u16));
-                if let Ok(mut buf) = RX_BUF.borrow(cs).try_borrow_mut() {
-                    if buf.len < RX_BUFFER_LEN {
-                        let len = buf.len;
-                        buf.bytes[len] = rx;
-                        buf.len += 1;
-                    }
-                }
+            let sr = uart.sr.read();
+
+            if sr.ore().bit() {
+                RX_OVERRUNS.fetch_add(1, Ordering::Relaxed);
             }
 
-            if uart.sr.read().tc().bit() {
-                if let Ok(mut buf) = TX_BUF.borrow(cs).try_borrow_mut() {
-                    if buf.len > 0 {
-                        buf.len -= 1;
-                        uart.dr.write(|w| w.dr().bits(buf.bytes[buf.len] as u16));
-                    }
+            if sr.rxne().bit() || sr.ore().bit() {
+                // Reading the data clears both flags
+                let rx = uart.dr.read().dr().bits() as u8;
+                if RX_BUF.push(rx).is_err() {
+                    RX_DROPPED.fetch_add(1, Ordering::Relaxed);
                 }
+            }
+        }
+    });
+}
 
-                uart.sr.modify(|_, w| w.tc().clear_bit());
+#[isr]
+fn DMA2_STREAM7() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(dma) = DMA.borrow(cs).borrow().as_ref() {
+            let hisr = dma.hisr.read();
+
+            if hisr.teif7().bit() || hisr.dmeif7().bit() || hisr.feif7().bit() {
+                // There is no telling how much of the chunk went out, so stop
+                // the stream and drop the chunk rather than send any of it
+                // twice. Stopping sets the transfer complete flag too.
+                dma.s7cr.modify(|_, w| w.en().clear_bit());
+                while dma.s7cr.read().en().bit() {}
+
+                dma.hifcr.write(|w| {
+                    w.ctcif7()
+                        .set_bit()
+                        .cteif7()
+                        .set_bit()
+                        .cdmeif7()
+                        .set_bit()
+                        .cfeif7()
+                        .set_bit()
+                });
+
+                let dropped = TX_IN_FLIGHT.swap(0, Ordering::Relaxed);
+                TX_BUF.consume(dropped);
+                TX_DROPPED.fetch_add(dropped, Ordering::Relaxed);
+                TX_ERRORS.fetch_add(1, Ordering::Relaxed);
+            } else if hisr.tcif7().bit() {
+                dma.hifcr.write(|w| w.ctcif7().set_bit());
+                TX_BUF.consume(TX_IN_FLIGHT.swap(0, Ordering::Relaxed));
             }
         }
     });
+
+    start_tx();
 }
@@ -2,7 +2,6 @@ extern crate piston_window;
 
 mod maze2;
 mod mouse;
-mod navigate;
 
 use std::fs::File;
 use std::io::Read;
@@ -12,19 +11,18 @@ use piston_window::*;
 use maze2::Edge;
 use maze2::Maze;
 
-use navigate::Navigate;
-use navigate::CountingDeadEndNavigate;
-use navigate::CountingNavigate;
-use navigate::DeadEndNavigate;
-use navigate::FloodFillDeadEndNavigate;
-use navigate::FloodFillNavigate;
-use navigate::FloodFillSquareDeadEndNavigate;
-use navigate::FloodFillSquareNavigate;
-use navigate::LeftWall;
-use navigate::RandomNavigate;
-use navigate::TwelvePartitionNavigate;
-
-use mouse::Direction;
+use micromouse_core::navigate::Navigate;
+use micromouse_core::navigate::CountingDeadEndNavigate;
+use micromouse_core::navigate::CountingNavigate;
+use micromouse_core::navigate::DeadEndNavigate;
+use micromouse_core::navigate::FloodFillDeadEndNavigate;
+use micromouse_core::navigate::FloodFillNavigate;
+use micromouse_core::navigate::FloodFillSquareDeadEndNavigate;
+use micromouse_core::navigate::FloodFillSquareNavigate;
+use micromouse_core::navigate::LeftWall;
+use micromouse_core::navigate::RandomNavigate;
+use micromouse_core::navigate::TwelvePartitionNavigate;
+
 use mouse::Mouse;
 
 pub const CELL_SIZE: f64 = 20.0;
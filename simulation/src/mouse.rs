@@ -7,9 +7,10 @@ use crate::Visualize;
 use crate::maze2::Edge;
 use crate::maze2::Maze;
 
-use crate::navigate::Move;
-use crate::navigate::MoveOptions;
-use crate::navigate::Navigate;
+use micromouse_core::navigate::Direction;
+use micromouse_core::navigate::Move;
+use micromouse_core::navigate::MoveOptions;
+use micromouse_core::navigate::Navigate;
 
 pub const WIDTH: f64 = CELL_SIZE * 0.4;
 pub const LENGTH: f64 = CELL_SIZE * 0.5;
@@ -17,49 +18,12 @@ pub const LENGTH: f64 = CELL_SIZE * 0.5;
 const LINEAR_SPEED: f64 = 4.0 * CELL_SIZE;
 const TURN_SPEED: f64 = 8.0 * 90.0;
 
-#[derive(Debug, Copy, Clone)]
-pub enum Direction {
-    North,
-    South,
-    West,
-    East,
-}
-
-impl Direction {
-    pub fn turn_left(&mut self) {
-        *self = match self {
-            Direction::North => Direction::West,
-            Direction::West => Direction::South,
-            Direction::South => Direction::East,
-            Direction::East => Direction::North,
-        }
-    }
-
-    pub fn turn_right(&mut self) {
-        *self = match self {
-            Direction::North => Direction::East,
-            Direction::East => Direction::South,
-            Direction::South => Direction::West,
-            Direction::West => Direction::North,
-        }
-    }
-
-    pub fn turn_around(&mut self) {
-        *self = match self {
-            Direction::North => Direction::South,
-            Direction::East => Direction::East,
-            Direction::South => Direction::North,
-            Direction::West => Direction::East,
-        }
-    }
-
-    pub fn rotation(&self) -> f64 {
-        match self {
-            Direction::North => 0.0,
-            Direction::East => 270.0,
-            Direction::South => 180.0,
-            Direction::West => 90.0,
-        }
+fn rotation(direction: Direction) -> f64 {
+    match direction {
+        Direction::North => 0.0,
+        Direction::East => 270.0,
+        Direction::South => 180.0,
+        Direction::West => 90.0,
     }
 }
 
@@ -119,7 +83,7 @@ impl<C: Visualize + Copy> Mouse<C> {
         (
             self.cell_x as f64 * CELL_SIZE + self.local_x,
             self.cell_y as f64 * CELL_SIZE + self.local_y,
-            self.direction.rotation() + self.local_direction,
+            rotation(self.direction) + self.local_direction,
         )
     }
 
@@ -173,8 +137,8 @@ impl<C: Visualize + Copy> Mouse<C> {
                     };
 
                     let moves = self.nav.navigate(
-                        self.cell_x,
-                        self.cell_y,
+                        self.cell_x as i32,
+                        self.cell_y as i32,
                         self.direction,
                         move_options,
                     );
@@ -244,7 +208,7 @@ impl<C: Visualize + Copy> Mouse<C> {
                     let turns = (target / 90.0).abs().round() as usize;
 
                     for _ in 0..turns {
-                        if target > 0.0 {
+                        self.direction = if target > 0.0 {
                             self.direction.turn_right()
                         } else {
                             self.direction.turn_left()